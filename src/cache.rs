@@ -0,0 +1,199 @@
+use std::collections::{HashMap, VecDeque};
+use discord::{ChannelRef, State};
+use discord::model::*;
+
+use config;
+use mentions;
+use message::format_attachment;
+use store;
+use types::*;
+
+// The latest messages, so edits, deletes and reactions can say what they're
+// about without searching buffers, which also forget old lines. Line
+// pointers aren't kept: WeeChat frees lines that scroll out of a buffer, so
+// edits and deletes print a line of their own. Replies aren't either, as
+// discord-rs's Message has no reference to what it replies to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedMessage {
+    pub channel_id: ChannelId,
+    pub author_id: UserId,
+    // without color, as it was when the message was sent
+    pub author: String,
+    pub content: String,
+    // formatted, one per line
+    pub attachments: Vec<String>,
+}
+
+// At most weecord.network.message_store_max per channel, so all that's
+// stored fits
+struct Cache {
+    messages: HashMap<MessageId, CachedMessage>,
+    // oldest first, deleted ones too
+    order: HashMap<ChannelId, VecDeque<MessageId>>,
+}
+
+static mut CACHE: *mut Cache = 0 as *mut _;
+
+fn cache() -> &'static mut Cache {
+    unsafe {
+        if CACHE.is_null() {
+            CACHE = Box::into_raw(Box::new(Cache {
+                                               messages: HashMap::new(),
+                                               order: HashMap::new(),
+                                           }));
+        }
+        &mut *CACHE
+    }
+}
+
+impl CachedMessage {
+    pub fn new(state: &State, message: &Message) -> CachedMessage {
+        let member_name = match state.find_channel(message.channel_id) {
+            Some(ChannelRef::Public(server, _)) => {
+                mentions::member_name(server, message.author.id)
+            }
+            _ => None,
+        };
+        CachedMessage {
+            channel_id: message.channel_id,
+            author_id: message.author.id,
+            author: member_name.unwrap_or_else(|| message.author.name(&NameFormat::none())),
+            content: message.content.clone(),
+            attachments: message.attachments.iter().map(format_attachment).collect(),
+        }
+    }
+
+    // What gets printed, less the author
+    pub fn text(&self) -> String {
+        let mut lines = Vec::new();
+        if !self.content.is_empty() {
+            lines.push(self.content.clone());
+        }
+        lines.extend(self.attachments.iter().cloned());
+        lines.join("\n")
+    }
+}
+
+// After on_event, which still wants what edits and deletes change
+pub fn update(state: &State, event: &Event) {
+    match *event {
+        Event::MessageCreate(ref message) => {
+            let cached = CachedMessage::new(state, message);
+            store::save(message.id, &cached);
+            insert(message.id, cached);
+        }
+        Event::MessageUpdate {
+            id,
+            ref content,
+            ref attachments,
+            ..
+        } => {
+            edit(id, content.as_ref().map(|x| &**x), attachments.as_ref());
+        }
+        Event::MessageDelete { message_id, .. } => {
+            remove(message_id);
+        }
+        Event::MessageDeleteBulk { ref ids, .. } => {
+            for &id in ids {
+                remove(id);
+            }
+        }
+        _ => (),
+    }
+}
+
+// Not stored, see store::save
+pub fn insert(id: MessageId, message: CachedMessage) {
+    let cache = cache();
+    let channel_id = message.channel_id;
+    if cache.messages.insert(id, message).is_some() {
+        return;
+    }
+    let order = cache.order.entry(channel_id).or_insert_with(VecDeque::new);
+    order.push_back(id);
+    while order.len() > config::message_store_max() {
+        if let Some(oldest) = order.pop_front() {
+            cache.messages.remove(&oldest);
+        }
+    }
+}
+
+pub fn get(id: MessageId) -> Option<CachedMessage> {
+    cache().messages.get(&id).cloned()
+}
+
+// Edits only send what changed
+pub fn edit(id: MessageId, content: Option<&str>, attachments: Option<&Vec<Attachment>>) {
+    if let Some(message) = cache().messages.get_mut(&id) {
+        if let Some(content) = content {
+            message.content = content.into();
+        }
+        if let Some(attachments) = attachments {
+            message.attachments = attachments.iter().map(format_attachment).collect();
+        }
+        store::save(id, message);
+    }
+}
+
+pub fn remove(id: MessageId) -> Option<CachedMessage> {
+    let removed = cache().messages.remove(&id);
+    if removed.is_some() {
+        store::forget(id);
+    }
+    removed
+}
+
+// Oldest first
+pub fn channel_messages(channel_id: ChannelId) -> Vec<(MessageId, CachedMessage)> {
+    let mut messages = cache()
+        .messages
+        .iter()
+        .filter(|&(_, message)| message.channel_id == channel_id)
+        .map(|(&id, message)| (id, message.clone()))
+        .collect::<Vec<_>>();
+    messages.sort_by_key(|x| x.0);
+    messages
+}
+
+pub fn end() {
+    unsafe {
+        if !CACHE.is_null() {
+            let _ = Box::from_raw(CACHE);
+            CACHE = ::std::ptr::null_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use discord::model::{ChannelId, MessageId, UserId};
+
+    use config;
+    use mock_host;
+    use super::*;
+
+    fn cached(content: &str) -> CachedMessage {
+        CachedMessage {
+            channel_id: ChannelId(1),
+            author_id: UserId(2),
+            author: "alice".into(),
+            content: content.into(),
+            attachments: vec!["cat.png (1 KiB) https://example.com/cat.png".into()],
+        }
+    }
+
+    #[test]
+    fn keeps_the_latest_messages() {
+        let _session = mock_host::setup();
+        for id in 0..config::message_store_max() as u64 + 2 {
+            insert(MessageId(id), cached("hi"));
+        }
+        assert_eq!(get(MessageId(1)), None);
+        assert_eq!(get(MessageId(2)), Some(cached("hi")));
+        edit(MessageId(2), Some("hello"), None);
+        assert_eq!(unwrap!(get(MessageId(2))).text(),
+                   "hello\ncat.png (1 KiB) https://example.com/cat.png");
+        assert_eq!(remove(MessageId(2)), Some(cached("hello")));
+        assert_eq!(get(MessageId(2)), None);
+    }
+}
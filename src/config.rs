@@ -0,0 +1,1190 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use libc::c_int;
+use discord::State;
+use discord::model::{ChannelId, ServerId, User, UserId};
+
+use ffi::*;
+use types::DiscordId;
+
+pub const CONFIG_NAME: &'static str = "weecord";
+
+// An option that exists once per server/channel, named `<key>.<name>`, see
+// update_names for the keys
+struct DynamicOption {
+    name: &'static str,
+    kind: &'static str,
+    description: &'static str,
+    default: &'static str,
+    on_change: Option<fn(ConfigOption)>,
+}
+
+static SERVER_OPTIONS: &'static [DynamicOption] =
+    &[DynamicOption {
+          name: "on_delete",
+          kind: "string",
+          description: "id of a channel to repost deleted messages of this server to (see \
+                        modlog for more)",
+          default: "",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "modlog",
+          kind: "string",
+          description: "comma separated places to log moderation events of this server to: \
+                        a channel id, \"buffer\" for the weecord.modlog buffer, or \
+                        \"file:<path>\"",
+          default: "",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "modlog_events",
+          kind: "string",
+          description: "comma separated events to log (empty: all): edit, delete, \
+                        bulk_delete, join, leave, ban, unban, roles",
+          default: "",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "mute",
+          kind: "boolean",
+          description: "don't open buffers for any channel of this server (follows the \
+                        official client)",
+          default: "off",
+          on_change: Some(on_mute_change),
+      },
+      DynamicOption {
+          name: "mute_until",
+          kind: "string",
+          description: "unix time at which the server mute ends (empty: never)",
+          default: "",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "muted_by_discord",
+          kind: "boolean",
+          description: "the mute comes from the official client, which lifts it again when \
+                        it's unmuted there (mutes made otherwise stay)",
+          default: "off",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "notify",
+          kind: "string",
+          description: "which messages go on the hotlist: all, mentions or nothing (empty: \
+                        all). Follows the official client, changes made here are overwritten",
+          default: "",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "suppress_everyone",
+          kind: "boolean",
+          description: "don't highlight on @everyone and @here. Follows the official client",
+          default: "off",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "highlight_words",
+          kind: "string",
+          description: "comma separated words that highlight in this server (case \
+                        insensitive, whole words only)",
+          default: "",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "highlight_regex",
+          kind: "string",
+          description: "messages in this server matching this regex highlight",
+          default: "",
+          on_change: Some(::highlight::check_regex),
+      },
+      DynamicOption {
+          name: "no_highlight_roles",
+          kind: "string",
+          description: "comma separated role names or ids whose mentions don't highlight",
+          default: "",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "no_highlight_here",
+          kind: "boolean",
+          description: "don't highlight on @here (@everyone still does)",
+          default: "off",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "ignore",
+          kind: "string",
+          description: "comma separated ids of users whose messages are hidden in this server",
+          default: "",
+          on_change: None,
+      }];
+
+static CHANNEL_OPTIONS: &'static [DynamicOption] =
+    &[DynamicOption {
+          name: "mute",
+          kind: "boolean",
+          description: "don't open a buffer for this channel (set automatically when the \
+                        channel is muted in the official client; set it to off to override that)",
+          default: "off",
+          on_change: Some(on_mute_change),
+      },
+      DynamicOption {
+          name: "mute_until",
+          kind: "string",
+          description: "unix time at which the channel mute ends (empty: never)",
+          default: "",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "muted_by_discord",
+          kind: "boolean",
+          description: "the mute comes from the official client, which lifts it again when \
+                        it's unmuted there (mutes made otherwise stay)",
+          default: "off",
+          on_change: None,
+      },
+      DynamicOption {
+          name: "notify",
+          kind: "string",
+          description: "which messages go on the hotlist: all, mentions or nothing (empty: \
+                        same as the server). Follows the official client",
+          default: "",
+          on_change: None,
+      }];
+
+// Moderation log events and their default templates, see modlog.rs
+static MODLOG_TEMPLATES: &'static [(&'static str, &'static str)] =
+    &[("edit", "Edited message by ${author} in ${channel}: ${before} -> ${after}"),
+      // what on_delete always said
+      ("delete", "AUTO: Deleted message by ${author} in ${channel}: ${content}"),
+      ("bulk_delete", "Deleted ${count} messages in ${channel}: ${content}"),
+      ("join", "${user} joined ${server}"),
+      ("leave", "${user} left ${server}"),
+      ("ban", "${user} was banned from ${server}"),
+      ("unban", "${user} was unbanned from ${server}"),
+      ("roles", "Roles of ${user} changed: ${changes}")];
+
+// named after what's renamed
+static RENAME_OPTION: DynamicOption =
+    DynamicOption {
+        name: "",
+        kind: "string",
+        description: "name to display instead of the real one for this user (@name#1234), \
+                      server, channel (server.#channel) or role (server.@role)",
+        default: "",
+        on_change: Some(on_name_change),
+    };
+
+struct Config {
+    file: ConfigFile,
+    look_embeds: ConfigOption,
+    look_emoji_urls: ConfigOption,
+    look_everyone_max_members: ConfigOption,
+    look_ignore: ConfigOption,
+    look_ignored_messages: ConfigOption,
+    look_max_group_len: ConfigOption,
+    look_role_colors: ConfigOption,
+    look_nicklist_max: ConfigOption,
+    look_reactions: ConfigOption,
+    network_token: ConfigOption,
+    network_upload_limit: ConfigOption,
+    network_download_dir: ConfigOption,
+    network_sync_mutes: ConfigOption,
+    network_idle_on_away: ConfigOption,
+    network_api_url: ConfigOption,
+    network_lazy_members: ConfigOption,
+    network_message_store: ConfigOption,
+    network_message_store_max: ConfigOption,
+    network_message_store_days: ConfigOption,
+    color_embed_bar: ConfigOption,
+    modlog_templates: Vec<(&'static str, ConfigOption)>,
+    server: ConfigSection,
+    channel: ConfigSection,
+    rename: ConfigSection,
+    // id -> key of the servers, channels and roles we know of
+    names: RefCell<HashMap<u64, String>>,
+}
+
+// *DO NOT* touch this outside of init/end
+static mut CONFIG: *mut Config = 0 as *mut _;
+
+fn config() -> &'static Config {
+    unsafe {
+        if CONFIG.is_null() {
+            really_bad("config used before it was loaded".into());
+        }
+        &*CONFIG
+    }
+}
+
+// Dynamic options that are gone from the file go away too
+fn on_reload(file: &ConfigFile) -> c_int {
+    let config = config();
+    for section in &[config.server, config.channel, config.rename] {
+        section.free_options();
+    }
+    file.reload()
+}
+
+// Anything that affects how names are displayed
+fn on_name_change(option: ConfigOption) {
+    let _ = option;
+    ::connection::sync_all_names();
+}
+
+// Loads what's on disk, or stops writing to it
+fn on_store_change(option: ConfigOption) {
+    ::store::stop();
+    if option.boolean() {
+        if let Err(err) = ::store::start() {
+            MAIN_BUFFER.print(&format!("Unable to load stored messages: {}", err));
+        }
+    }
+}
+
+// Unmuted channels get their buffer back right away
+fn on_mute_change(option: ConfigOption) {
+    let name = option.name();
+    if let Some(id) = name.rsplitn(2, '.').nth(1).and_then(key_id) {
+        ::connection::open_buffers_of(id);
+    }
+}
+
+fn dynamic_option(section: ConfigSection, name: &str) -> Option<&'static DynamicOption> {
+    let config = config();
+    if section == config.rename {
+        return if name.is_empty() { None } else { Some(&RENAME_OPTION) };
+    }
+    let options = if section == config.server {
+        SERVER_OPTIONS
+    } else if section == config.channel {
+        CHANNEL_OPTIONS
+    } else {
+        return None;
+    };
+    let mut split = name.rsplitn(2, '.');
+    match (split.next(), split.next()) {
+        (Some(name), Some(key)) if !key.is_empty() => options.iter().find(|o| o.name == name),
+        _ => None,
+    }
+}
+
+// Without calling on_change
+fn new_dynamic(section: ConfigSection, name: &str, value: &str) -> Option<ConfigOption> {
+    let option = tryopt!(dynamic_option(section, name));
+    config()
+        .file
+        .new_option(section,
+                    name,
+                    option.kind,
+                    option.description,
+                    "",
+                    0,
+                    0,
+                    option.default,
+                    value,
+                    option.on_change)
+}
+
+fn create_option(section: ConfigSection, name: &str, value: &str) -> c_int {
+    match new_dynamic(section, name, value) {
+        Some(option) => {
+            // WeeChat only calls it for options that already exist
+            if let Some(on_change) = dynamic_option(section, name).and_then(|x| x.on_change) {
+                on_change(option);
+            }
+            CONFIG_OPTION_SET_OK_CHANGED
+        }
+        None => CONFIG_OPTION_SET_ERROR,
+    }
+}
+
+// Back to the default first, so /unset undoes whatever the option did
+fn delete_option(section: ConfigSection, option: ConfigOption) {
+    if let Some(dynamic) = dynamic_option(section, &option.name()) {
+        option.set(dynamic.default);
+    }
+    option.free();
+}
+
+pub fn init() -> Option<()> {
+    let file = tryopt!(ConfigFile::new(CONFIG_NAME, on_reload));
+
+    let look = tryopt!(file.new_section("look", None));
+    let look_embeds = tryopt!(file.new_option(look,
+                                              "embeds",
+                                              "integer",
+                                              "how much of rich embeds (link previews, bot \
+                                               output) to show below messages",
+                                              "none|title|full",
+                                              0,
+                                              0,
+                                              "full",
+                                              "full",
+                                              None));
+    let look_emoji_urls = tryopt!(file.new_option(look,
+                                                  "emoji_urls",
+                                                  "boolean",
+                                                  "show the image link after custom emoji",
+                                                  "",
+                                                  0,
+                                                  0,
+                                                  "off",
+                                                  "off",
+                                                  None));
+    let look_everyone_max_members =
+        tryopt!(file.new_option(look,
+                                "everyone_max_members",
+                                "integer",
+                                "ignore @everyone and @here in servers with more members than \
+                                 this (0: never ignore)",
+                                "",
+                                0,
+                                i32::max_value(),
+                                "0",
+                                "0",
+                                None));
+    let look_ignore = tryopt!(file.new_option(look,
+                                              "ignore",
+                                              "string",
+                                              "comma separated ids of users whose messages \
+                                               are hidden everywhere",
+                                              "",
+                                              0,
+                                              0,
+                                              "",
+                                              "",
+                                              None));
+    let look_ignored_messages = tryopt!(file.new_option(look,
+                                                        "ignored_messages",
+                                                        "integer",
+                                                        "what to show when an ignored or \
+                                                         blocked user talks",
+                                                        "hide|placeholder",
+                                                        0,
+                                                        0,
+                                                        "placeholder",
+                                                        "placeholder",
+                                                        None));
+    let look_max_group_len = tryopt!(file.new_option(look,
+                                                     "max_group_len",
+                                                     "integer",
+                                                     "shorten the names of group DMs to this \
+                                                      many characters (0: don't shorten)",
+                                                     "",
+                                                     0,
+                                                     i32::max_value(),
+                                                     "16",
+                                                     "16",
+                                                     Some(on_name_change)));
+    let look_role_colors = tryopt!(file.new_option(look,
+                                                   "role_colors",
+                                                   "boolean",
+                                                   "color nicks in the nicklist like their \
+                                                    highest colored role",
+                                                   "",
+                                                   0,
+                                                   0,
+                                                   "on",
+                                                   "on",
+                                                   Some(on_name_change)));
+    let look_nicklist_max = tryopt!(file.new_option(look,
+                                                    "nicklist_max",
+                                                    "integer",
+                                                    "servers with more members get a lazy \
+                                                     nicklist of at most this many nicks: \
+                                                     filled when the buffer is shown, and \
+                                                     with whoever talks (0: no limit)",
+                                                    "",
+                                                    0,
+                                                    i32::max_value(),
+                                                    "1000",
+                                                    "1000",
+                                                    Some(on_name_change)));
+    let look_reactions = tryopt!(file.new_option(look,
+                                                 "reactions",
+                                                 "boolean",
+                                                 "show reactions to recent messages",
+                                                 "",
+                                                 0,
+                                                 0,
+                                                 "on",
+                                                 "on",
+                                                 None));
+
+    let network = tryopt!(file.new_section("network", None));
+    let network_token = tryopt!(file.new_option(network,
+                                                "token",
+                                                "string",
+                                                "Discord login token",
+                                                "",
+                                                0,
+                                                0,
+                                                "",
+                                                "",
+                                                None));
+    let network_upload_limit = tryopt!(file.new_option(network,
+                                                       "upload_limit",
+                                                       "integer",
+                                                       "largest file /discord upload will send, \
+                                                        in bytes",
+                                                       "",
+                                                       0,
+                                                       i32::max_value(),
+                                                       "8388608",
+                                                       "8388608",
+                                                       None));
+    let network_download_dir = tryopt!(file.new_option(network,
+                                                       "download_dir",
+                                                       "string",
+                                                       "where /discord download saves \
+                                                        attachments (default: \
+                                                        <weechat_dir>/weecord_downloads)",
+                                                       "",
+                                                       0,
+                                                       0,
+                                                       "",
+                                                       "",
+                                                       None));
+
+    let network_sync_mutes = tryopt!(file.new_option(network,
+                                                     "sync_mutes",
+                                                     "boolean",
+                                                     "send mutes made with /discord mute to \
+                                                      Discord, so the official client follows",
+                                                     "",
+                                                     0,
+                                                     0,
+                                                     "off",
+                                                     "off",
+                                                     None));
+    let network_idle_on_away = tryopt!(file.new_option(network,
+                                                       "idle_on_away",
+                                                       "boolean",
+                                                       "set the Discord status to idle while \
+                                                        /away is set",
+                                                       "",
+                                                       0,
+                                                       0,
+                                                       "off",
+                                                       "off",
+                                                       None));
+
+    let network_api_url = tryopt!(file.new_option(network,
+                                                  "api_url",
+                                                  "string",
+                                                  "Discord API to connect to, e.g. a local \
+                                                   fake server for offline testing",
+                                                  "",
+                                                  0,
+                                                  0,
+                                                  ::rest::DEFAULT_API_URL,
+                                                  ::rest::DEFAULT_API_URL,
+                                                  None));
+    let network_lazy_members = tryopt!(file.new_option(network,
+                                                       "lazy_members",
+                                                       "boolean",
+                                                       "don't download the member lists of \
+                                                        large servers when connecting, only \
+                                                        members that show up are known",
+                                                       "",
+                                                       0,
+                                                       0,
+                                                       "off",
+                                                       "off",
+                                                       None));
+    let network_message_store = tryopt!(file.new_option(network,
+                                                        "message_store",
+                                                        "boolean",
+                                                        "keep recent messages on disk \
+                                                         (weecord_messages.jsonl), for edits \
+                                                         and deletes after a restart and to \
+                                                         fill new buffers",
+                                                        "",
+                                                        0,
+                                                        0,
+                                                        "off",
+                                                        "off",
+                                                        Some(on_store_change)));
+    let network_message_store_max = tryopt!(file.new_option(network,
+                                                            "message_store_max",
+                                                            "integer",
+                                                            "messages kept per channel, in memory \
+                                                             and on disk",
+                                                            "",
+                                                            1,
+                                                            i32::max_value(),
+                                                            "200",
+                                                            "200",
+                                                            None));
+    let network_message_store_days = tryopt!(file.new_option(network,
+                                                             "message_store_days",
+                                                             "integer",
+                                                             "forget stored messages older than \
+                                                              this (0: keep them)",
+                                                             "",
+                                                             0,
+                                                             i32::max_value(),
+                                                             "30",
+                                                             "30",
+                                                             None));
+
+    let color = tryopt!(file.new_section("color", None));
+    let color_embed_bar = tryopt!(file.new_option(color,
+                                                  "embed_bar",
+                                                  "color",
+                                                  "color of the bar left of embeds that don't \
+                                                   specify their own",
+                                                  "",
+                                                  0,
+                                                  0,
+                                                  "darkgray",
+                                                  "darkgray",
+                                                  None));
+
+    let modlog = tryopt!(file.new_section("modlog", None));
+    let mut modlog_templates = Vec::new();
+    for &(event, default) in MODLOG_TEMPLATES {
+        let description = format!("moderation log line for {} events, ${{...}} are replaced \
+                                   (see /help discord)",
+                                  event);
+        let option = tryopt!(file.new_option(modlog,
+                                             event,
+                                             "string",
+                                             &description,
+                                             "",
+                                             0,
+                                             0,
+                                             default,
+                                             default,
+                                             None));
+        modlog_templates.push((event, option));
+    }
+
+    let dynamic = Some((create_option as CreateOptionFn, delete_option as DeleteOptionFn));
+    let server = tryopt!(file.new_section("server", dynamic));
+    let channel = tryopt!(file.new_section("channel", dynamic));
+    let rename = tryopt!(file.new_section("rename", dynamic));
+
+    let config = Config {
+        file: file,
+        look_embeds: look_embeds,
+        look_emoji_urls: look_emoji_urls,
+        look_everyone_max_members: look_everyone_max_members,
+        look_ignore: look_ignore,
+        look_ignored_messages: look_ignored_messages,
+        look_max_group_len: look_max_group_len,
+        look_role_colors: look_role_colors,
+        look_nicklist_max: look_nicklist_max,
+        look_reactions: look_reactions,
+        network_token: network_token,
+        network_upload_limit: network_upload_limit,
+        network_download_dir: network_download_dir,
+        network_sync_mutes: network_sync_mutes,
+        network_idle_on_away: network_idle_on_away,
+        network_api_url: network_api_url,
+        network_lazy_members: network_lazy_members,
+        network_message_store: network_message_store,
+        network_message_store_max: network_message_store_max,
+        network_message_store_days: network_message_store_days,
+        color_embed_bar: color_embed_bar,
+        modlog_templates: modlog_templates,
+        server: server,
+        channel: channel,
+        rename: rename,
+        names: RefCell::new(HashMap::new()),
+    };
+    unsafe {
+        CONFIG = Box::into_raw(Box::new(config));
+    }
+    // reading creates the dynamic options, which needs CONFIG set
+    config().file.read();
+    migrate();
+    Some(())
+}
+
+pub fn end() {
+    unsafe {
+        if !CONFIG.is_null() {
+            (*CONFIG).file.write();
+            let _ = Box::from_raw(CONFIG);
+            CONFIG = ::std::ptr::null_mut();
+        }
+    }
+}
+
+// Everything used to live in plugins.var.weecord.*
+fn migrate() {
+    let config = config();
+    let mut migrated = false;
+    for (name, value) in plugin_options() {
+        let bool_value = if value == "1" { "on" } else { "off" };
+        let mut split = name.splitn(2, '.');
+        let message = match (split.next(), split.next()) {
+            (Some("token"), None) => config.network_token.set(&value),
+            (Some("upload_limit"), None) => config.network_upload_limit.set(&value),
+            (Some("download_dir"), None) => config.network_download_dir.set(&value),
+            (Some("embeds"), None) => config.look_embeds.set(&value),
+            (Some("emoji_urls"), None) => config.look_emoji_urls.set(bool_value),
+            (Some("rename"), Some(id)) => set_dynamic(config.rename, id, &value),
+            (Some("mute"), Some(id)) => {
+                set_dynamic(config.channel, &format!("{}.mute", id), bool_value)
+            }
+            (Some("on_delete"), Some(id)) => {
+                set_dynamic(config.server, &format!("{}.on_delete", id), &value)
+            }
+            _ => continue,
+        };
+        MAIN_BUFFER.print(&format!("{}: migrated plugins.var.weecord.{}: {}",
+                                   ::weechat::COMMAND,
+                                   name,
+                                   message));
+        unset_plugin_option(&name);
+        migrated = true;
+    }
+    if migrated {
+        config.file.write();
+    }
+}
+
+fn get_dynamic(section: ConfigSection, name: &str) -> Option<ConfigOption> {
+    config().file.search_option(section, name)
+}
+
+fn set_dynamic(section: ConfigSection, name: &str, value: &str) -> String {
+    match get_dynamic(section, name) {
+        Some(option) => option.set(value),
+        None => {
+            if create_option(section, name, value) == CONFIG_OPTION_SET_OK_CHANGED {
+                format!("option {} successfully set to {}", name, value)
+            } else {
+                format!("error when setting option {} to {}", name, value)
+            }
+        }
+    }
+}
+
+// Only creates the option for a non-empty value, so clearing doesn't leave
+// empty options behind
+fn set_dynamic_string(section: ConfigSection, name: &str, value: &str) {
+    if !value.is_empty() {
+        set_dynamic(section, name, value);
+    } else if let Some(option) = get_dynamic(section, name) {
+        option.set("");
+    }
+}
+
+// Spaces and dots would end up in the wrong part of the option name
+fn key_part(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_whitespace() || c == '.' { '_' } else { c })
+        .collect()
+}
+
+// Users are too many to keep track of, so they're keyed by their tag
+fn user_key(user: &User) -> String {
+    format!("@{}#{:04}", key_part(&user.name), user.discriminator)
+}
+
+fn name_key(id: u64) -> String {
+    config()
+        .names
+        .borrow()
+        .get(&id)
+        .cloned()
+        .unwrap_or_else(|| id.to_string())
+}
+
+fn key_id(key: &str) -> Option<u64> {
+    key.parse().ok().or_else(|| {
+        config()
+            .names
+            .borrow()
+            .iter()
+            .find(|&(_, x)| x == key)
+            .map(|(&id, _)| id)
+    })
+}
+
+// Servers, channels and roles are keyed by name in the server, channel and
+// rename sections: "Test", "Test.#general" and "Test.@mods". A name two of
+// them share goes to the first one, the other keeps its id, as does
+// anything we don't know of yet. Options follow when a key changes.
+pub fn update_names(state: &State) {
+    let mut names = HashMap::new();
+    {
+        let mut taken = HashSet::new();
+        let mut add = |id: u64, key: String| if taken.insert(key.clone()) {
+            names.insert(id, key);
+        };
+        for server in state.servers() {
+            let server_key = key_part(&server.name);
+            add(server.id.0, server_key.clone());
+            for channel in &server.channels {
+                add(channel.id.0, format!("{}.#{}", server_key, key_part(&channel.name)));
+            }
+            for role in &server.roles {
+                add(role.id.0, format!("{}.@{}", server_key, key_part(&role.name)));
+            }
+        }
+    }
+    let old = mem::replace(&mut *config().names.borrow_mut(), names.clone());
+    for (id, key) in names {
+        let before = old.get(&id).cloned().unwrap_or_else(|| id.to_string());
+        if before != key {
+            move_options(&before, &key);
+        }
+    }
+}
+
+// Unless there's one under the new key already
+fn move_options(from: &str, to: &str) {
+    let config = config();
+    let mut names = vec![(config.rename, from.to_owned(), to.to_owned())];
+    for &(section, options) in &[(config.server, SERVER_OPTIONS),
+                                 (config.channel, CHANNEL_OPTIONS)] {
+        names.extend(options
+                         .iter()
+                         .map(|o| {
+                                  (section,
+                                   format!("{}.{}", from, o.name),
+                                   format!("{}.{}", to, o.name))
+                              }));
+    }
+    for (section, from, to) in names {
+        let option = match get_dynamic(section, &from) {
+            Some(option) => option,
+            None => continue,
+        };
+        if get_dynamic(section, &to).is_none() &&
+           new_dynamic(section, &to, &option.string()).is_some() {
+            option.free();
+        }
+    }
+}
+
+// An option of a server or channel, which may still be under its id if it
+// was set before its key was known
+fn get_keyed(section: ConfigSection, id: u64, name: &str) -> Option<ConfigOption> {
+    get_dynamic(section, &format!("{}.{}", name_key(id), name))
+        .or_else(|| get_dynamic(section, &format!("{}.{}", id, name)))
+}
+
+fn get_keyed_string(section: ConfigSection, id: u64, name: &str) -> Option<String> {
+    let value = tryopt!(get_keyed(section, id, name)).string();
+    if value.is_empty() { None } else { Some(value) }
+}
+
+// The one under the id makes way for the one under the key
+fn free_by_id(section: ConfigSection, id: u64, name: &str) -> bool {
+    let by_id = format!("{}.{}", id, name);
+    if format!("{}.{}", name_key(id), name) == by_id {
+        return false;
+    }
+    match get_dynamic(section, &by_id) {
+        Some(option) => {
+            option.free();
+            true
+        }
+        None => false,
+    }
+}
+
+fn set_keyed(section: ConfigSection, id: u64, name: &str, value: &str) -> String {
+    free_by_id(section, id, name);
+    set_dynamic(section, &format!("{}.{}", name_key(id), name), value)
+}
+
+fn set_keyed_string(section: ConfigSection, id: u64, name: &str, value: &str) {
+    free_by_id(section, id, name);
+    set_dynamic_string(section, &format!("{}.{}", name_key(id), name), value)
+}
+
+pub fn token() -> Option<String> {
+    let token = config().network_token.string();
+    if token.is_empty() { None } else { Some(token) }
+}
+
+pub fn set_token(token: &str) -> String {
+    config().network_token.set(token)
+}
+
+pub fn upload_limit() -> u64 {
+    config().network_upload_limit.integer() as u64
+}
+
+pub fn download_dir() -> Option<String> {
+    let dir = config().network_download_dir.string();
+    if dir.is_empty() { None } else { Some(dir) }
+}
+
+pub fn sync_mutes() -> bool {
+    config().network_sync_mutes.boolean()
+}
+
+pub fn idle_on_away() -> bool {
+    config().network_idle_on_away.boolean()
+}
+
+pub fn lazy_members() -> bool {
+    config().network_lazy_members.boolean()
+}
+
+pub fn message_store() -> bool {
+    config().network_message_store.boolean()
+}
+
+pub fn message_store_max() -> usize {
+    config().network_message_store_max.integer() as usize
+}
+
+// 0 for no limit
+pub fn message_store_days() -> u64 {
+    config().network_message_store_days.integer() as u64
+}
+
+pub fn api_url() -> String {
+    let url = config().network_api_url.string();
+    let url = url.trim_right_matches('/');
+    if url.is_empty() {
+        ::rest::DEFAULT_API_URL.into()
+    } else {
+        url.into()
+    }
+}
+
+// index into "none|title|full"
+pub fn embeds() -> i32 {
+    config().look_embeds.integer()
+}
+
+pub fn emoji_urls() -> bool {
+    config().look_emoji_urls.boolean()
+}
+
+pub fn everyone_max_members() -> u64 {
+    config().look_everyone_max_members.integer() as u64
+}
+
+// index into "hide|placeholder"
+pub fn ignored_messages() -> i32 {
+    config().look_ignored_messages.integer()
+}
+
+pub fn max_group_len() -> usize {
+    config().look_max_group_len.integer() as usize
+}
+
+pub fn role_colors() -> bool {
+    config().look_role_colors.boolean()
+}
+
+// 0 for no limit
+pub fn nicklist_max() -> usize {
+    config().look_nicklist_max.integer() as usize
+}
+
+pub fn reactions() -> bool {
+    config().look_reactions.boolean()
+}
+
+pub fn embed_bar_color() -> String {
+    config().color_embed_bar.color()
+}
+
+pub fn rename<Id: DiscordId>(id: &Id) -> Option<String> {
+    get_rename(&name_key(id.raw_id()), id.raw_id())
+}
+
+pub fn user_rename(user: &User) -> Option<String> {
+    get_rename(&user_key(user), user.id.0)
+}
+
+fn get_rename(key: &str, id: u64) -> Option<String> {
+    let rename = config().rename;
+    let option = tryopt!(get_dynamic(rename, key).or_else(|| get_dynamic(rename, &id.to_string())));
+    let name = option.string();
+    if name.is_empty() { None } else { Some(name) }
+}
+
+// An empty name removes the rename
+pub fn set_rename(id: u64, name: &str) -> String {
+    set_rename_key(&name_key(id), id, name)
+}
+
+pub fn set_user_rename(user: &User, name: &str) -> String {
+    set_rename_key(&user_key(user), user.id.0, name)
+}
+
+fn set_rename_key(key: &str, id: u64, name: &str) -> String {
+    let rename = config().rename;
+    let by_id = match get_dynamic(rename, &id.to_string()) {
+        Some(option) if key != id.to_string() => {
+            option.free();
+            true
+        }
+        _ => false,
+    };
+    if !name.is_empty() {
+        return set_dynamic(rename, key, name);
+    }
+    let by_key = match get_dynamic(rename, key) {
+        Some(option) => {
+            option.free();
+            true
+        }
+        None => false,
+    };
+    if by_key || by_id {
+        ::connection::sync_all_names();
+        format!("option {} removed", key)
+    } else {
+        format!("option {} is not set", key)
+    }
+}
+
+pub fn channel_muted(channel_id: ChannelId) -> Option<bool> {
+    get_keyed(config().channel, channel_id.0, "mute").map(|x| x.boolean())
+}
+
+pub fn set_channel_muted(channel_id: ChannelId, muted: bool) -> String {
+    set_keyed(config().channel,
+              channel_id.0,
+              "mute",
+              if muted { "on" } else { "off" })
+}
+
+pub fn server_muted(server_id: ServerId) -> Option<bool> {
+    get_keyed(config().server, server_id.0, "mute").map(|x| x.boolean())
+}
+
+pub fn set_server_muted(server_id: ServerId, muted: bool) -> String {
+    set_keyed(config().server,
+              server_id.0,
+              "mute",
+              if muted { "on" } else { "off" })
+}
+
+fn mute_section(is_server: bool) -> ConfigSection {
+    if is_server {
+        config().server
+    } else {
+        config().channel
+    }
+}
+
+// unix time
+pub fn mute_until(is_server: bool, id: u64) -> Option<u64> {
+    get_keyed(mute_section(is_server), id, "mute_until")
+        .and_then(|x| x.string().parse().ok())
+}
+
+pub fn set_mute_until(is_server: bool, id: u64, until: Option<u64>) {
+    set_keyed_string(mute_section(is_server),
+                     id,
+                     "mute_until",
+                     &until.map(|x| format!("{}", x)).unwrap_or_default());
+}
+
+pub fn muted_by_discord(is_server: bool, id: u64) -> bool {
+    get_keyed(mute_section(is_server), id, "muted_by_discord")
+        .map_or(false, |x| x.boolean())
+}
+
+// Removed rather than turned off, so it only shows up on such mutes
+pub fn set_muted_by_discord(is_server: bool, id: u64, muted: bool) {
+    if muted {
+        set_keyed(mute_section(is_server), id, "muted_by_discord", "on");
+    } else {
+        // under the key and still under the id
+        while let Some(option) = get_keyed(mute_section(is_server), id, "muted_by_discord") {
+            option.free();
+        }
+    }
+}
+
+pub fn server_notify(server_id: ServerId) -> Option<String> {
+    get_keyed_string(config().server, server_id.0, "notify")
+}
+
+pub fn set_server_notify(server_id: ServerId, level: &str) {
+    set_keyed_string(config().server, server_id.0, "notify", level)
+}
+
+pub fn channel_notify(channel_id: ChannelId) -> Option<String> {
+    get_keyed_string(config().channel, channel_id.0, "notify")
+}
+
+pub fn set_channel_notify(channel_id: ChannelId, level: &str) {
+    set_keyed_string(config().channel, channel_id.0, "notify", level)
+}
+
+pub fn server_suppress_everyone(server_id: ServerId) -> bool {
+    get_keyed(config().server, server_id.0, "suppress_everyone")
+        .map_or(false, |x| x.boolean())
+}
+
+pub fn set_server_suppress_everyone(server_id: ServerId, suppress: bool) {
+    set_keyed(config().server,
+              server_id.0,
+              "suppress_everyone",
+              if suppress { "on" } else { "off" });
+}
+
+pub fn highlight_words(server_id: ServerId) -> Option<String> {
+    get_keyed_string(config().server, server_id.0, "highlight_words")
+}
+
+pub fn highlight_regex(server_id: ServerId) -> Option<String> {
+    get_keyed_string(config().server, server_id.0, "highlight_regex")
+}
+
+pub fn no_highlight_roles(server_id: ServerId) -> Option<String> {
+    get_keyed_string(config().server, server_id.0, "no_highlight_roles")
+}
+
+pub fn no_highlight_here(server_id: ServerId) -> bool {
+    get_keyed(config().server, server_id.0, "no_highlight_here")
+        .map_or(false, |x| x.boolean())
+}
+
+// Everywhere for None
+pub fn ignored(server_id: Option<ServerId>) -> Vec<UserId> {
+    let list = match server_id {
+        Some(server_id) => {
+            get_keyed_string(config().server, server_id.0, "ignore").unwrap_or_default()
+        }
+        None => config().look_ignore.string(),
+    };
+    list.split(',')
+        .filter_map(|x| x.trim().parse::<u64>().ok())
+        .map(UserId)
+        .collect()
+}
+
+pub fn set_ignored(server_id: Option<ServerId>, users: &[UserId]) -> String {
+    let list = users
+        .iter()
+        .map(|x| format!("{}", x.0))
+        .collect::<Vec<_>>()
+        .join(",");
+    match server_id {
+        Some(server_id) => set_keyed(config().server, server_id.0, "ignore", &list),
+        None => config().look_ignore.set(&list),
+    }
+}
+
+pub fn on_delete(server_id: ServerId) -> Option<ChannelId> {
+    get_keyed(config().server, server_id.0, "on_delete")
+        .and_then(|x| x.string().parse::<u64>().ok())
+        .map(ChannelId)
+}
+
+pub fn modlog(server_id: ServerId) -> Vec<String> {
+    get_keyed_string(config().server, server_id.0, "modlog")
+        .unwrap_or_default()
+        .split(',')
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+// All of them for None
+pub fn modlog_events(server_id: ServerId) -> Option<Vec<String>> {
+    get_keyed_string(config().server, server_id.0, "modlog_events")
+        .map(|x| x.split(',').map(|x| x.trim().to_owned()).collect())
+}
+
+pub fn modlog_template(event: &str) -> String {
+    config()
+        .modlog_templates
+        .iter()
+        .find(|x| x.0 == event)
+        .map(|x| x.1.string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use discord::model::{ChannelId, ServerId, UserId};
+
+    use fixtures::{self, ALICE, GENERAL, SERVER};
+    use host::Host;
+    use mock_host;
+    use util::ServerExt;
+    use super::*;
+
+    #[test]
+    fn dynamic_options_are_created_on_demand() {
+        let session = mock_host::setup();
+        assert_eq!(channel_muted(ChannelId(5)), None);
+        assert_eq!(set_channel_muted(ChannelId(5), true),
+                   "option 5.mute successfully set to on");
+        assert_eq!(channel_muted(ChannelId(5)), Some(true));
+        assert_eq!(session.host().get("weecord.channel.5.mute"), Some("on".into()));
+        // unknown keys, and ids that aren't ids, are refused
+        assert_eq!(session.host().set("weecord.server.5.bogus", "on"), 3);
+        assert_eq!(session.host().set("weecord.channel.mute", "on"), 3);
+        assert_eq!(session.host().set("weecord.server.5.mute", "maybe"), 3);
+    }
+
+    #[test]
+    fn clearing_doesnt_leave_empty_options() {
+        let session = mock_host::setup();
+        set_server_notify(ServerId(5), "");
+        assert_eq!(session.host().get("weecord.server.5.notify"), None);
+        set_server_notify(ServerId(5), "mentions");
+        assert_eq!(server_notify(ServerId(5)), Some("mentions".into()));
+        set_server_notify(ServerId(5), "");
+        assert_eq!(server_notify(ServerId(5)), None);
+        set_rename(2, "al");
+        assert_eq!(rename(&UserId(2)), Some("al".into()));
+        set_rename(2, "");
+        assert_eq!(session.host().get("weecord.rename.2"), None);
+    }
+
+    #[test]
+    fn options_follow_names() {
+        let session = mock_host::setup();
+        let state = fixtures::state();
+        set_channel_muted(ChannelId(GENERAL), true);
+        set_rename(SERVER, "tests");
+        update_names(&state);
+        assert_eq!(session.host().get("weecord.channel.11.mute"), None);
+        assert_eq!(session.host().get("weecord.channel.Test.#general.mute"),
+                   Some("on".into()));
+        assert_eq!(session.host().get("weecord.rename.Test"), Some("tests".into()));
+        assert_eq!(channel_muted(ChannelId(GENERAL)), Some(true));
+        let alice = unwrap!(state.servers()[0].find_member(UserId(ALICE))).user.clone();
+        set_user_rename(&alice, "al");
+        assert_eq!(session.host().get("weecord.rename.@alice#0002"), Some("al".into()));
+        assert!(session.host().unset("weecord.rename.@alice#0002"));
+        assert_eq!(user_rename(&alice), None);
+    }
+
+    #[test]
+    fn ignore_lists() {
+        let _session = mock_host::setup();
+        set_ignored(None, &[UserId(2), UserId(3)]);
+        set_ignored(Some(ServerId(5)), &[UserId(4)]);
+        assert_eq!(ignored(None), vec![UserId(2), UserId(3)]);
+        assert_eq!(ignored(Some(ServerId(5))), vec![UserId(4)]);
+        assert_eq!(ignored(Some(ServerId(6))), Vec::<UserId>::new());
+    }
+
+    #[test]
+    fn migrates_plugin_options() {
+        let session = mock_host::setup();
+        end();
+        session.host().add_plugin_option("token", "abc");
+        session.host().add_plugin_option("mute.5", "1");
+        session.host().add_plugin_option("rename.2", "al");
+        init();
+        assert_eq!(token(), Some("abc".into()));
+        assert_eq!(channel_muted(ChannelId(5)), Some(true));
+        assert_eq!(rename(&UserId(2)), Some("al".into()));
+        assert!(session.host().plugin_options().is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::sync::mpsc::*;
 use std::thread::{spawn, JoinHandle};
 use discord;
@@ -5,10 +6,29 @@ use discord::*;
 use discord::model::*;
 
 use command_print;
+use config;
 use ffi::*;
 use message;
+use emoji;
+use mentions;
+use cache;
+use store;
+use rename;
+use ignore;
+use friends;
+use whois;
+use group;
+use presence::{self, OwnPresence, PresenceUpdate};
 use event_proc;
 use types::*;
+use util::{split_arg, tag, xterm_color};
+use members::MemberRequests;
+use mute;
+use nicklist;
+use notify;
+use rest;
+use record::{self, Relay};
+use transfer::Transfers;
 
 pub struct ChannelData<'a> {
     pub state: &'a State,
@@ -35,37 +55,173 @@ impl<'dis> ChannelData<'dis> {
             .set("localvar_set_nick", &self.state.user().username);
     }
 
+    // What the message store remembers, dated as it was sent
+    fn prefill(&self) {
+        for (id, _) in cache::channel_messages(self.channel.id()) {
+            let message = message::format_message(self,
+                                                  id,
+                                                  None,
+                                                  None,
+                                                  None,
+                                                  None,
+                                                  "",
+                                                  notify::Mention::None);
+            if let Some(message) = message {
+                self.buffer.print_date_tags(store::timestamp(id) as i64,
+                                            &format!("no_log,notify_none,no_highlight,\
+                                                      discord_messageid_{}",
+                                                     id.0),
+                                            &format!("{}\t{}", message.author, message.content));
+            }
+        }
+    }
+
     fn sync_init(&self) {
         self.buffer.set("type", "formatted");
         let channel_id = format!("{}", self.channel.id().0);
         self.buffer.set("localvar_set_channelid", &channel_id);
         // localvar_set_type is an undocumented localvar found by digging
         // through source. Causes indentation on channels.
-        if let ChannelRef::Public(server, _) = self.channel {
-            self.buffer.set("localvar_set_type", "channel");
-            self.buffer.set("nicklist", "1");
-            for member in &server.members {
-                let name = member.name(&NameFormat::none());
-                self.buffer.add_nick(&name);
+        match self.channel {
+            ChannelRef::Public(_, _) => {
+                self.buffer.set("localvar_set_type", "channel");
+                self.buffer.set("nicklist", "1");
+            }
+            ChannelRef::Group(_) => {
+                self.buffer.set("localvar_set_type", "private");
+                self.buffer.set("nicklist", "1");
+            }
+            ChannelRef::Private(_) => {
+                self.buffer.set("localvar_set_type", "private");
             }
-        } else {
-            self.buffer.set("localvar_set_type", "private");
         };
+        self.sync_nicklist();
         self.sync_name();
         self.sync_self_nick();
     }
 
+    // Rebuilds the whole nicklist, for when names change
+    pub fn sync_nicklist(&self) {
+        match self.channel {
+            ChannelRef::Public(server, _) => {
+                nicklist::clear(&self.buffer);
+                if self.is_lazy() {
+                    // the rest comes with fill_nicklist and add_speaker
+                    let me = self.state.user().id;
+                    if let Some(me) = server.members.iter().find(|x| x.user.id == me) {
+                        self.add_member(me);
+                    }
+                    return;
+                }
+                for member in &server.members {
+                    self.add_member(member);
+                }
+            }
+            ChannelRef::Group(group) => {
+                nicklist::clear(&self.buffer);
+                let me = self.state.user();
+                nicklist::add(&self.buffer, me.id, &me.name(&NameFormat::none()), "");
+                for recipient in &group.recipients {
+                    self.add_user(recipient);
+                }
+            }
+            ChannelRef::Private(_) => (),
+        }
+    }
+
+    // Also for members whose nick or roles changed
     pub fn add_member(&self, member: &Member) {
-        let name = member.name(&NameFormat::none());
-        self.buffer.add_nick(&name);
+        nicklist::add(&self.buffer,
+                      member.user.id,
+                      &member.name(&NameFormat::none()),
+                      &self.member_color(member));
+    }
+
+    // Servers too large to have everyone in every nicklist
+    pub fn is_lazy(&self) -> bool {
+        let max = config::nicklist_max();
+        match self.channel {
+            ChannelRef::Public(server, _) => {
+                max > 0 &&
+                (server.member_count as usize > max || server.members.len() > max)
+            }
+            _ => false,
+        }
+    }
+
+    // A lazy nicklist gets online members first, up to the limit
+    pub fn fill_nicklist(&self) {
+        let server = match self.channel {
+            ChannelRef::Public(server, _) if self.is_lazy() => server,
+            _ => return,
+        };
+        let max = config::nicklist_max();
+        let online = online_users(server);
+        let (online, offline): (Vec<&Member>, Vec<&Member>) =
+            server
+                .members
+                .iter()
+                .partition(|member| online.contains(&member.user.id));
+        for member in online.into_iter().chain(offline) {
+            if nicklist::len(&self.buffer) >= max {
+                break;
+            }
+            if !nicklist::contains(&self.buffer, member.user.id) {
+                self.add_member(member);
+            }
+        }
+    }
+
+    // Lazy nicklists keep the latest speakers
+    pub fn add_speaker(&self, user: &User) {
+        let server = match self.channel {
+            ChannelRef::Public(server, _) if self.is_lazy() => server,
+            _ => return,
+        };
+        match server.members.iter().find(|x| x.user.id == user.id) {
+            Some(member) => self.add_member(member),
+            // until lazy_members brings in the member
+            None => self.add_user(user),
+        }
+        // never ourselves, nor who just spoke
+        nicklist::truncate(&self.buffer,
+                           config::nicklist_max(),
+                           &[self.state.user().id, user.id]);
+    }
+
+    // Joins and member downloads, which lazy nicklists leave out unless
+    // they already show the user
+    pub fn member_joined(&self, member: &Member) {
+        if !self.is_lazy() || nicklist::contains(&self.buffer, member.user.id) {
+            self.add_member(member);
+        }
+    }
+
+    // The highest role with a color, or "" for WeeChat's nick colors
+    fn member_color(&self, member: &Member) -> String {
+        let server = match self.channel {
+            ChannelRef::Public(server, _) if config::role_colors() => server,
+            _ => return String::new(),
+        };
+        server
+            .roles
+            .iter()
+            .filter(|role| role.color != 0 && member.roles.contains(&role.id))
+            .max_by_key(|role| role.position)
+            .map_or_else(String::new, |role| format!("{}", xterm_color(role.color)))
     }
 
-    pub fn remove_member(&self, member: &Member) {
-        let name = member.name(&NameFormat::none());
-        self.buffer.remove_nick(&name);
+    // Group recipients, which aren't members of anything
+    pub fn add_user(&self, user: &User) {
+        nicklist::add(&self.buffer, user.id, &user.name(&NameFormat::none()), "");
     }
 
-    fn from_buffer_impl(state: &'dis State, buffer: &Buffer) -> Option<ChannelRef<'dis>> {
+    // Members and group recipients
+    pub fn remove_user(&self, user: &User) {
+        nicklist::remove(&self.buffer, user.id);
+    }
+
+    pub fn from_buffer_impl(state: &'dis State, buffer: &Buffer) -> Option<ChannelRef<'dis>> {
         let channel_id_str = tryopt!(buffer.get("localvar_channelid"));
         let channel_id = ChannelId(tryopt!(channel_id_str.parse().ok()));
         state.find_channel(channel_id)
@@ -88,19 +244,6 @@ impl<'dis> ChannelData<'dis> {
         }
     }
 
-    pub fn mute_channels(settings: &UserServerSettings) {
-        for channel in &settings.channel_overrides {
-            if channel.muted {
-                let key = format!("mute.{}", channel.channel_id);
-                if get_option(&key).map_or(false, |x| !x.is_empty()) {
-                    // already set
-                    continue;
-                }
-                MAIN_BUFFER.print(&set_option(&key, "1"));
-            }
-        }
-    }
-
     fn is_channel_blocked(channel: ChannelRef<'dis>) -> bool {
         if let ChannelRef::Public(_, channel) = channel {
             if channel.kind == ChannelType::Voice {
@@ -117,10 +260,17 @@ impl<'dis> ChannelData<'dis> {
             //    return true;
             //}
         }
-        if let Some(muted) = get_option(&format!("mute.{}", channel.id())) {
-            return muted.parse::<i32>().ok().map_or(false, |x| x != 0);
-        }
-        false
+        mute::is_muted(channel)
+    }
+
+    // "server_id.channel_id", with 0 as the server of private channels
+    pub fn buffer_name(channel: ChannelRef) -> String {
+        let (server_id, channel_id) = match channel {
+            ChannelRef::Private(private) => (ServerId(0), private.id()),
+            ChannelRef::Group(group) => (ServerId(0), group.id()),
+            ChannelRef::Public(server, channel) => (server.id(), channel.id()),
+        };
+        format!("{}.{}", server_id, channel_id)
     }
 
     pub fn from_channel(state: &'dis State,
@@ -131,12 +281,7 @@ impl<'dis> ChannelData<'dis> {
         if Self::is_channel_blocked(channel) {
             return None;
         }
-        let (server_id, channel_id) = match channel {
-            ChannelRef::Private(private) => (ServerId(0), private.id()),
-            ChannelRef::Group(group) => (ServerId(0), group.id()),
-            ChannelRef::Public(server, channel) => (server.id(), channel.id()),
-        };
-        let name_id = format!("{}.{}", server_id, channel_id);
+        let name_id = Self::buffer_name(channel);
         let (buffer, is_new) = if let Some(buffer) = Buffer::search(&name_id) {
             (buffer, false)
         } else if auto_open {
@@ -152,6 +297,9 @@ impl<'dis> ChannelData<'dis> {
         };
         if is_new {
             result.sync_init();
+            if config::message_store() {
+                result.prefill();
+            }
         }
         Some(result)
     }
@@ -182,8 +330,8 @@ impl<'dis> ChannelData<'dis> {
 }
 
 fn buffer_input(buffer: Buffer, message: &str) {
-    let (state, discord) = match MyConnection::magic() {
-        Some(con) => (&con.state, &con.discord),
+    let (state, discord, token) = match MyConnection::magic() {
+        Some(con) => (&con.state, &con.discord, &con.token),
         None => {
             buffer.print("Discord is not connected");
             return;
@@ -198,33 +346,263 @@ fn buffer_input(buffer: Buffer, message: &str) {
         }
     };
     let to_send = message::format_message_send(&channel.channel, message.into());
-    let result = channel
-        .discord
-        .send_message(channel.channel.id(), &to_send, "", false);
-    match result {
-        Ok(_) => (),
-        Err(err) => channel.buffer.print(&format!("{}", err)),
+    if let Err(err) = rest::send_message(token, channel.channel.id(), &to_send) {
+        channel.buffer.print(&err);
+    }
+}
+
+// Renames changed, redo every buffer title and nicklist
+pub fn sync_all_names() {
+    mentions::clear();
+    if let Some(con) = MyConnection::magic() {
+        con.sync_all_names();
+    }
+}
+
+// A server or channel changed its mute, open what isn't muted anymore
+pub fn open_buffers_of(id: u64) {
+    if let Some(con) = MyConnection::magic() {
+        con.open_buffers_of(id);
     }
 }
 
-pub fn debug_command(command: &str) {
+pub fn debug_command(buffer: &Buffer, command: &str) {
+    let (action, args) = split_arg(command);
+    match action {
+        "record" => record_command(buffer, args),
+        "replay" => {
+            if MyConnection::magic().is_some() {
+                buffer.print("Disconnect before replaying a recording");
+                return;
+            }
+            match record::replay(args) {
+                Ok(count) => command_print(&format!("replayed {} events from {}", count, args)),
+                Err(err) => buffer.print(&err),
+            }
+        }
+        _ => {
+            if let Some(x) = MyConnection::magic() {
+                x.debug_command(command)
+            }
+        }
+    }
+}
+
+fn record_command(buffer: &Buffer, args: &str) {
+    let (flag, path) = split_arg(args);
+    if flag == "-stop" {
+        match record::stop() {
+            Some(path) => command_print(&format!("stopped recording to {}", path)),
+            None => buffer.print("Not recording"),
+        }
+        return;
+    }
+    let (redact, path) = if flag == "-redact" {
+        (true, path)
+    } else {
+        (false, args)
+    };
+    if path.is_empty() {
+        buffer.print("Usage: /discord debug record [-redact] <file>|-stop");
+        return;
+    }
+    if let Err(err) = record::start(path, redact) {
+        buffer.print(&err);
+        return;
+    }
+    command_print(&format!("recording gateway events to {}", path));
+    // only new connections go through the recorder
+    if let Some(token) = MyConnection::magic().map(|con| con.token.clone()) {
+        command_print("reconnecting to start recording");
+        MyConnection::drop();
+        MyConnection::create(token);
+    }
+}
+
+pub fn query_command(buffer: &Buffer, args: &str) {
     if let Some(x) = MyConnection::magic() {
-        x.debug_command(command)
+        x.query_command(buffer, args)
+    } else {
+        buffer.print("Discord is not connected");
     }
 }
 
-pub fn query_command(buffer: &Buffer, user: &str) {
+pub fn upload_command(buffer: &Buffer, args: &str) {
     if let Some(x) = MyConnection::magic() {
-        x.query_command(buffer, user)
+        x.upload_command(buffer, args)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn download_command(buffer: &Buffer, args: &str) {
+    if let Some(x) = MyConnection::magic() {
+        x.download_command(buffer, args)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn rename_command(buffer: &Buffer, args: &str) {
+    if let Some(x) = MyConnection::magic() {
+        rename::rename_command(&x.state, buffer, args)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn mute_command(buffer: &Buffer, args: &str, mute: bool) {
+    if let Some(x) = MyConnection::magic() {
+        mute::mute_command(&x.state, &x.token, buffer, args, mute)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn ignore_command(buffer: &Buffer, args: &str, ignore: bool) {
+    if let Some(x) = MyConnection::magic() {
+        ignore::ignore_command(&x.state, buffer, args, ignore)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn friends_command(buffer: &Buffer, args: &str) {
+    if let Some(x) = MyConnection::magic() {
+        friends::friends_command(&x.state, &x.token, buffer, args)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn whois_command(buffer: &Buffer, name: &str) {
+    if let Some(x) = MyConnection::magic() {
+        whois::whois_command(&x.state, buffer, name)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn group_command(buffer: &Buffer, args: &str) {
+    if let Some(x) = MyConnection::magic() {
+        group::group_command(&x.state, &x.token, buffer, args)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn status_command(buffer: &Buffer, args: &str) {
+    if let Some(x) = MyConnection::magic() {
+        x.presence.status_command(&x.token, buffer, args)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn game_command(buffer: &Buffer, args: &str) {
+    if let Some(x) = MyConnection::magic() {
+        x.presence.game_command(&x.token, buffer, args)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn custom_status_command(buffer: &Buffer, args: &str) {
+    if let Some(x) = MyConnection::magic() {
+        x.presence.custom_status_command(&x.token, buffer, args)
+    } else {
+        buffer.print("Discord is not connected");
+    }
+}
+
+pub fn away_command(command: &str) {
+    if let Some(x) = MyConnection::magic() {
+        x.presence.on_away_command(&x.token, command)
+    }
+}
+
+// Empty while disconnected, which hides the item
+pub fn status_bar_item() -> String {
+    match MyConnection::magic() {
+        Some(x) => format!("discord: {}", x.presence.bar_item()),
+        None => String::new(),
+    }
+}
+
+fn online_users(server: &LiveServer) -> HashSet<UserId> {
+    server
+        .presences
+        .iter()
+        .filter(|x| x.status != OnlineStatus::Offline)
+        .map(|x| x.user_id)
+        .collect()
+}
+
+// Lazy nicklists fill up once they're looked at
+pub fn buffer_switched(buffer: &Buffer) {
+    if let Some(con) = MyConnection::magic() {
+        if let Ok(channel) = ChannelData::from_buffer(&con.state,
+                                                      &con.discord,
+                                                      Buffer::from_ptr(buffer.ptr())) {
+            channel.fill_nicklist();
+            // online users lazy_members hasn't brought in yet
+            if let ChannelRef::Public(server, _) = channel.channel {
+                let mut unknown = online_users(server);
+                for member in &server.members {
+                    unknown.remove(&member.user.id);
+                }
+                con.members
+                    .users(server.id, &unknown.into_iter().collect::<Vec<_>>());
+            }
+        }
+    }
+}
+
+// Every member of the buffer's server, nicklist or not
+pub fn members_completion(buffer: &Buffer, completion: &mut Completion) {
+    let con = match MyConnection::magic() {
+        Some(con) => con,
+        None => return,
+    };
+    if let Some(ChannelRef::Public(server, _)) = ChannelData::from_buffer_impl(&con.state,
+                                                                                buffer) {
+        for member in &server.members {
+            completion.add(&member.name(&NameFormat::none()));
+        }
+        con.members.completing(server.id);
+    }
+}
+
+pub fn emoji_completion(buffer: &Buffer, completion: &mut Completion) {
+    let con = MyConnection::magic();
+    let channel = con.as_ref()
+        .and_then(|con| ChannelData::from_buffer_impl(&con.state, buffer));
+    emoji::complete(channel.as_ref(), completion);
+}
+
+// Server buffers and the channel buffers that aren't muted
+pub fn open_buffers(state: &State, discord: &Discord) {
+    for server in state.servers() {
+        ChannelData::create_server(server);
+        for channel in &server.channels {
+            ChannelData::from_channel(state, discord, ChannelRef::Public(server, channel), true);
+        }
     }
 }
 
 pub struct MyConnection {
     state: State,
     discord: Discord,
+    token: String,
+    transfers: Transfers,
+    _mute_timer: Option<Timer>,
+    presence: OwnPresence,
+    members: MemberRequests,
     recv: Receiver<discord::Result<Event>>,
     _poke_fd: PokeableFd,
     _listen_thread: JoinHandle<()>,
+    // while recording; closing it ends the listening thread too
+    _relay: Option<Relay>,
 }
 
 static mut MAGIC: *mut MyConnection = 0 as *mut _;
@@ -253,6 +631,7 @@ impl MyConnection {
             unsafe {
                 MAGIC = con;
             }
+            BarItem::update(presence::BAR_ITEM_NAME);
         }
     }
 
@@ -263,6 +642,9 @@ impl MyConnection {
                 MAGIC = ::std::ptr::null_mut();
             }
         }
+        // the next connection brings its own state
+        mentions::clear();
+        BarItem::update(presence::BAR_ITEM_NAME);
     }
 
     fn debug_command(&mut self, command: &str) {
@@ -270,7 +652,7 @@ impl MyConnection {
             for server in self.state.servers() {
                 MAIN_BUFFER.print(&format!("Server: {}", &server.name));
                 if let Some(chan) = self.state.find_channel(server.channels[0].id) {
-                    for (user, mention) in message::all_names(&chan, &NameFormat::prefix()) {
+                    for (user, mention) in mentions::names(&chan) {
                         MAIN_BUFFER.print(&format!("{} : {}", user, mention))
                     }
                 }
@@ -278,38 +660,139 @@ impl MyConnection {
         }
     }
 
-    fn query_command(&mut self, buffer: &Buffer, nick: &str) {
-        if let Some(user) = message::all_names_everywhere(&self.state,
-                                                          |name, user| if name == nick {
-                                                              Some(user.id())
-                                                          } else {
-                                                              None
-                                                          }) {
-            for existing in self.state.private_channels() {
-                if existing.recipient.id() == user {
-                    ChannelData::from_channel(&self.state,
-                                              &self.discord,
-                                              ChannelRef::Private(existing),
-                                              true);
-                    return;
+    fn query_command(&mut self, buffer: &Buffer, args: &str) {
+        let (nick, text) = split_arg(args);
+        let users = message::find_users(&self.state, &nick);
+        let user_id = match (users.len(), message::query_id(&nick)) {
+            (1, _) => users[0].id,
+            // someone we share no server with, Discord knows whether they exist
+            (0, Some(id)) => id,
+            (0, None) => {
+                buffer.print(&format!("User not found: {}", nick));
+                return;
+            }
+            _ => {
+                buffer.print(&format!("Several users are called {}, use name#1234 or the id:",
+                                      nick));
+                for user in &users {
+                    buffer.print(&format!("  {} ({})", tag(user), user.id));
+                }
+                return;
+            }
+        };
+        let existing = self.state
+            .private_channels()
+            .iter()
+            .find(|x| x.recipient.id == user_id);
+        let new_channel;
+        let private = match existing {
+            Some(private) => private,
+            None => {
+                match rest::create_private_channel(&self.token, user_id) {
+                    Ok(private) => {
+                        new_channel = private;
+                        &new_channel
+                    }
+                    Err(err) => {
+                        buffer.print(&format!("Unable to create a PM with {}: {}", nick, err));
+                        return;
+                    }
                 }
             }
-            match self.discord.create_private_channel(user) {
-                Ok(new_channel) => {
+        };
+        let channel = match ChannelData::from_channel(&self.state,
+                                                      &self.discord,
+                                                      ChannelRef::Private(private),
+                                                      true) {
+            Some(channel) => channel,
+            None => {
+                buffer.print(&format!("The PM with {} is muted", private.recipient.name));
+                return;
+            }
+        };
+        channel.buffer.set("display", "1");
+        if !text.is_empty() {
+            let to_send = message::format_message_send(&channel.channel, text.into());
+            if let Err(err) = rest::send_message(&self.token, private.id, &to_send) {
+                channel.buffer.print(&err);
+            }
+        }
+    }
+
+    fn sync_all_names(&self) {
+        config::update_names(&self.state);
+        for server in self.state.servers() {
+            ChannelData::create_server(server);
+            for channel in &server.channels {
+                if let Some(chan) = ChannelData::from_channel(&self.state,
+                                                              &self.discord,
+                                                              ChannelRef::Public(server, channel),
+                                                              false) {
+                    chan.sync_name();
+                    chan.sync_nicklist();
+                }
+            }
+        }
+        for private in self.state.private_channels() {
+            if let Some(chan) = ChannelData::from_channel(&self.state,
+                                                          &self.discord,
+                                                          ChannelRef::Private(private),
+                                                          false) {
+                chan.sync_name();
+            }
+        }
+        for group in self.state.groups().values() {
+            if let Some(chan) = ChannelData::from_channel(&self.state,
+                                                          &self.discord,
+                                                          ChannelRef::Group(group),
+                                                          false) {
+                chan.sync_name();
+                chan.sync_nicklist();
+            }
+        }
+    }
+
+    fn open_buffers_of(&self, id: u64) {
+        for server in self.state.servers() {
+            for channel in &server.channels {
+                if server.id.0 == id || channel.id.0 == id {
                     ChannelData::from_channel(&self.state,
                                               &self.discord,
-                                              ChannelRef::Private(&new_channel),
+                                              ChannelRef::Public(server, channel),
                                               true);
                 }
-                Err(err) => {
-                    buffer.print(&format!("Unable to create a PM with {}: {}", user, err));
-                }
             }
-        } else {
-            buffer.print(&format!("User not found: {}", nick));
         }
     }
 
+    fn upload_command(&mut self, buffer: &Buffer, args: &str) {
+        let channel = match ChannelData::from_buffer_impl(&self.state, buffer) {
+            Some(channel) => channel,
+            None => {
+                buffer.print("Uploads must be run from a Discord channel buffer");
+                return;
+            }
+        };
+        self.transfers
+            .upload(&self.token,
+                    buffer,
+                    channel.id(),
+                    args,
+                    |comment| message::format_message_send(&channel, comment.into()));
+    }
+
+    fn download_command(&mut self, buffer: &Buffer, args: &str) {
+        let channel = match ChannelData::from_buffer_impl(&self.state, buffer) {
+            Some(channel) => channel,
+            None => {
+                buffer.print("Downloads must be run from a Discord channel buffer");
+                return;
+            }
+        };
+        self.transfers
+            .download(&self.token, buffer, channel.id(), args);
+    }
+
     fn on_poke(&mut self) {
         loop {
             let event = self.recv.try_recv();
@@ -325,16 +808,42 @@ impl MyConnection {
                     break;
                 }
             };
-            self.state.update(&event);
-            event_proc::on_event(&self.state, &self.discord, &event);
+            event_proc::update(&mut self.state, &self.discord, &event);
+            match event {
+                Event::UserSettingsUpdate { status: Some(status), .. } => {
+                    self.presence.on_settings_update(status);
+                }
+                Event::MessageCreate(ref message) => self.speaker_joined(message),
+                _ => (),
+            }
+        }
+    }
+
+    // lazy_members leaves out everyone until they show up
+    fn speaker_joined(&mut self, message: &Message) {
+        if let Some(ChannelRef::Public(server, _)) = self.state.find_channel(message.channel_id) {
+            if !server.members.iter().any(|x| x.user.id == message.author.id) {
+                self.members.users(server.id, &[message.author.id]);
+            }
         }
     }
 
     fn run_thread(mut connection: Connection,
                   pipe_poker: PokeableFdPoker,
-                  send: Sender<discord::Result<Event>>) {
+                  send: Sender<discord::Result<Event>>,
+                  presence: Receiver<PresenceUpdate>,
+                  syncs: Receiver<ServerId>) {
         loop {
             let event = connection.recv_event();
+            // only this thread has the connection, so what the main thread
+            // wants sent waits for the next event
+            while let Ok((game, status, afk)) = presence.try_recv() {
+                connection.set_presence(game, status, afk);
+            }
+            let servers = syncs.try_iter().collect::<Vec<_>>();
+            if !servers.is_empty() {
+                connection.sync_servers(&servers);
+            }
             // note we want to send even if it's an error
             match (event.is_err(), send.send(event)) {
                 // break if we failed to send, or got an error
@@ -347,32 +856,55 @@ impl MyConnection {
         pipe_poker.poke();
     }
 
-    fn new(token: String) -> discord::Result<MyConnection> {
-        let discord = Discord::from_user_token(&token)?;
-        let (mut connection, ready) = discord.connect()?;
-        if let Some(ref settings) = ready.user_server_settings {
-            for setting in settings {
-                ChannelData::mute_channels(setting);
-            }
+    fn new(token: String) -> ::std::result::Result<MyConnection, String> {
+        let discord = Discord::from_user_token(&token).map_err(|err| format!("{}", err))?;
+        // not discord.connect(), so weecord.network.api_url is followed
+        let mut gateway = rest::gateway(&token)?;
+        let relay = match record::active() {
+            Some(recording) => Some(record::relay(&gateway, recording)?),
+            None => None,
+        };
+        if let Some(ref relay) = relay {
+            gateway = relay.url().into();
         }
+        let (mut connection, ready) = Connection::new(&gateway, &token)
+            .map_err(|err| format!("{}", err))?;
+        let settings = ready.user_server_settings.clone().unwrap_or_default();
+        let status = ready
+            .user_settings
+            .as_ref()
+            .map_or(OnlineStatus::Online, |x| x.status);
         let mut state = State::new(ready);
+        config::update_names(&state);
+        for setting in &settings {
+            mute::apply_settings(&state, setting, true);
+            notify::apply_settings(&state, setting);
+        }
         connection.sync_servers(&state.all_servers()[..]);
-        connection.download_all_members(&mut state);
+        if !config::lazy_members() {
+            connection.download_all_members(&mut state);
+        }
         let (send, recv) = channel();
         let pipe = PokeableFd::new(move || if let Some(x) = Self::magic() {
                                        x.on_poke()
                                    });
         let pipe_poker = pipe.get_poker();
-        let listen_thread = spawn(move || Self::run_thread(connection, pipe_poker, send));
-        for server in state.servers() {
-            ChannelData::create_server(server);
-            for channel in &server.channels {
-                ChannelData::from_channel(&state,
-                                          &discord,
-                                          ChannelRef::Public(server, channel),
-                                          true);
-            }
-        }
+        let (presence_send, presence_recv) = channel();
+        let (sync_send, sync_recv) = channel();
+        let listen_thread = spawn(move || {
+                                      Self::run_thread(connection,
+                                                       pipe_poker,
+                                                       send,
+                                                       presence_recv,
+                                                       sync_recv)
+                                  });
+        let transfers = Transfers::new(|| if let Some(x) = Self::magic() {
+                                           x.transfers.on_poke()
+                                       });
+        let mute_timer = Timer::new(60 * 1000, || if let Some(x) = Self::magic() {
+                                        mute::expire_mutes(&x.state)
+                                    });
+        open_buffers(&state, &discord);
         // let completion_hook =
         // ffi::hook_completion("weecord_completion", "",
         // move |buffer, completion| {
@@ -383,9 +915,166 @@ impl MyConnection {
         Ok(MyConnection {
                discord: discord,
                state: state,
+               token: token,
+               transfers: transfers,
+               _mute_timer: mute_timer,
+               presence: OwnPresence::new(status, presence_send),
+               members: MemberRequests::new(sync_send),
                recv: recv,
                _poke_fd: pipe,
                _listen_thread: listen_thread,
+               _relay: relay,
            })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+    use hyper::method::Method;
+
+    use fake_discord::FakeDiscord;
+    use ffi::{Buffer, MAIN_BUFFER};
+    use fixtures::{self, ALICE, ME};
+    use mock_host::{self, MockHost};
+    use presence;
+
+    // What WeeChat does whenever the listening thread pokes the pipe
+    fn wait_for<F: Fn() -> bool>(host: &MockHost, done: F) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(10) {
+            host.fire_fds();
+            if done() {
+                return true;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn chats_through_a_fake_discord() {
+        let fake = FakeDiscord::start(fixtures::ready_json());
+        let session = mock_host::setup_plugin();
+        let host = session.host();
+        host.set("weecord.network.api_url", &fake.api_url);
+        host.run_command(&MAIN_BUFFER, "/discord token test");
+        host.run_command(&MAIN_BUFFER, "/discord connect");
+        let buffer = unwrap!(Buffer::search("10.11"));
+        assert_eq!(host.nicks(&buffer), vec!["me", "alice"]);
+
+        fake.dispatch("MESSAGE_CREATE",
+                      fixtures::message_json(100, ALICE, "hi <@1>", &[ME]));
+        assert!(wait_for(host, || host.messages(&buffer) == vec!["hi @me"]));
+
+        // rate limits are reported instead of hanging WeeChat
+        fake.rate_limit("/channels/11/messages", 1);
+        host.input(&buffer, "hello @alice");
+        assert_eq!(host.messages(&buffer)[1], "rate limited, try again in 0.0s");
+        host.input(&buffer, "hello @alice");
+        let sent = fake.calls()
+            .into_iter()
+            .filter(|call| call.method == Method::Post && call.path == "/channels/11/messages")
+            .collect::<Vec<_>>();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].body["content"], "hello <@2>");
+        buffer.clear();
+
+        // events keep coming after the connection is resumed
+        fake.disconnect();
+        fake.dispatch("MESSAGE_UPDATE",
+                      fixtures::message_json(100, ALICE, "hi again", &[]));
+        assert!(wait_for(host, || host.messages(&buffer).len() == 1));
+        assert_eq!(host.messages(&buffer)[0], "EDIT: hi again");
+    }
+
+    #[test]
+    fn sends_presence_updates_right_away() {
+        let fake = FakeDiscord::start(fixtures::ready_json());
+        let session = mock_host::setup_plugin();
+        let host = session.host();
+        host.set("weecord.network.api_url", &fake.api_url);
+        host.run_command(&MAIN_BUFFER, "/discord token test");
+        host.run_command(&MAIN_BUFFER, "/discord connect");
+        host.run_command(&MAIN_BUFFER, "/discord game chess");
+        host.run_command(&MAIN_BUFFER, "/discord status invisible");
+        // nothing but the echo of the saved status comes from Discord
+        assert!(wait_for(host, || {
+            fake.sent()
+                .iter()
+                .any(|x| x["op"] == 3 && x["d"]["status"] == "invisible")
+        }));
+        let presence = unwrap!(fake.sent().into_iter().rev().find(|x| x["op"] == 3));
+        assert_eq!(presence["d"]["game"]["name"], "chess");
+        assert_eq!(host.bar_item(presence::BAR_ITEM_NAME),
+                   Some("discord: invisible (playing chess)".into()));
+    }
+
+    #[test]
+    fn requests_members_as_they_show_up() {
+        let fake = FakeDiscord::start(fixtures::ready_json());
+        let session = mock_host::setup_plugin();
+        let host = session.host();
+        host.set("weecord.network.api_url", &fake.api_url);
+        host.set("weecord.network.lazy_members", "on");
+        host.set("weecord.look.nicklist_max", "1");
+        host.run_command(&MAIN_BUFFER, "/discord token test");
+        host.run_command(&MAIN_BUFFER, "/discord connect");
+        let buffer = unwrap!(Buffer::search("10.11"));
+        let syncs = || fake.sent().into_iter().filter(|x| x["op"] == 12).count();
+        // every server, on connect
+        assert!(wait_for(host, || syncs() == 1));
+
+        // someone who isn't a member yet speaks up, twice
+        fake.dispatch("MESSAGE_CREATE", fixtures::message_json(100, 3, "hi", &[]));
+        fake.dispatch("MESSAGE_CREATE", fixtures::message_json(101, 3, "hi", &[]));
+        assert!(wait_for(host, || host.messages(&buffer).len() == 2));
+        assert_eq!(host.nicks(&buffer), vec!["me", "someone"]);
+        // sent once the listening thread has another event
+        fake.dispatch("MESSAGE_CREATE", fixtures::message_json(102, ALICE, "hi", &[]));
+        assert!(wait_for(host, || syncs() == 2));
+
+        assert_eq!(host.complete(&buffer, "weecord_members"), vec!["me", "alice"]);
+        assert_eq!(host.complete(&buffer, "weecord_members"), vec!["me", "alice"]);
+        fake.dispatch("MESSAGE_CREATE", fixtures::message_json(103, ALICE, "hi", &[]));
+        assert!(wait_for(host, || host.messages(&buffer).len() == 4));
+        assert!(wait_for(host, || syncs() == 3));
+        fake.dispatch("MESSAGE_CREATE", fixtures::message_json(104, ALICE, "hi", &[]));
+        assert!(wait_for(host, || host.messages(&buffer).len() == 5));
+        assert_eq!(syncs(), 3);
+    }
+
+    #[test]
+    fn records_and_replays_the_gateway() {
+        let fake = FakeDiscord::start(fixtures::ready_json());
+        let session = mock_host::setup_plugin();
+        let host = session.host();
+        let path = env::temp_dir().join("weecord-test-recording.jsonl");
+        let path = unwrap!(path.to_str());
+        host.set("weecord.network.api_url", &fake.api_url);
+        host.run_command(&MAIN_BUFFER, "/discord token secret");
+        host.run_command(&MAIN_BUFFER, &format!("/discord debug record -redact {}", path));
+        host.run_command(&MAIN_BUFFER, "/discord connect");
+        let buffer = unwrap!(Buffer::search("10.11"));
+        fake.dispatch("MESSAGE_CREATE",
+                      fixtures::message_json(100, ALICE, "hi <@1>", &[ME]));
+        assert!(wait_for(host, || host.messages(&buffer).len() == 1));
+        host.run_command(&MAIN_BUFFER, "/discord debug record -stop");
+        host.run_command(&MAIN_BUFFER, "/discord disconnect");
+
+        let mut recording = String::new();
+        unwrap1!(unwrap1!(File::open(path)).read_to_string(&mut recording));
+        assert!(recording.contains("READY"));
+        assert!(!recording.contains("secret"));
+        assert!(!recording.contains("hi <@1>"));
+
+        buffer.clear();
+        host.run_command(&MAIN_BUFFER, &format!("/discord debug replay {}", path));
+        assert_eq!(host.messages(&buffer), vec!["REDACTED"]);
+        let _ = fs::remove_file(path);
+    }
+}
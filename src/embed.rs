@@ -0,0 +1,90 @@
+use discord::ChannelRef;
+use serde_json::Value;
+
+use ffi;
+use config;
+use mentions::replace_mentions;
+use util::xterm_color;
+
+#[derive(PartialEq, Eq)]
+enum Verbosity {
+    None,
+    Title,
+    Full,
+}
+
+fn verbosity() -> Verbosity {
+    match config::embeds() {
+        0 => Verbosity::None,
+        1 => Verbosity::Title,
+        _ => Verbosity::Full,
+    }
+}
+
+fn get_str<'a>(value: &'a Value, path: &[&str]) -> Option<&'a str> {
+    let mut value = value;
+    for key in path {
+        value = tryopt!(value.get(key));
+    }
+    value.as_str().and_then(|x| if x.is_empty() { None } else { Some(x) })
+}
+
+// One line per entry, to be appended below the message content.
+pub fn format_embeds(channel: &ChannelRef, embeds: &[Value]) -> Vec<String> {
+    let verbosity = verbosity();
+    let mut lines = Vec::new();
+    if verbosity == Verbosity::None {
+        return lines;
+    }
+    for embed in embeds {
+        let mut body = Vec::new();
+        if let Some(provider) = get_str(embed, &["provider", "name"]) {
+            body.push(provider.to_owned());
+        }
+        if let Some(author) = get_str(embed, &["author", "name"]) {
+            body.push(author.to_owned());
+        }
+        match (get_str(embed, &["title"]), get_str(embed, &["url"])) {
+            (Some(title), Some(url)) => body.push(format!("{} ({})", title, url)),
+            (Some(title), None) => body.push(title.to_owned()),
+            (None, Some(url)) => body.push(url.to_owned()),
+            (None, None) => (),
+        }
+        if verbosity == Verbosity::Full {
+            if let Some(description) = get_str(embed, &["description"]) {
+                let description = replace_mentions(channel, description);
+                body.extend(description.lines().map(|x| x.to_owned()));
+            }
+            if let Some(fields) = embed.get("fields").and_then(|x| x.as_array()) {
+                for field in fields {
+                    let name = get_str(field, &["name"]).unwrap_or("");
+                    let value = replace_mentions(channel,
+                                                 get_str(field, &["value"]).unwrap_or(""));
+                    let mut value_lines = value.lines();
+                    let first = value_lines.next().unwrap_or("");
+                    body.push(format!("{}{}{}: {}",
+                                      ffi::color("bold"),
+                                      name,
+                                      ffi::color("-bold"),
+                                      first));
+                    body.extend(value_lines.map(|x| format!("  {}", x)));
+                }
+            }
+            if let Some(image) = get_str(embed, &["image", "url"]) {
+                body.push(image.to_owned());
+            }
+            if let Some(footer) = get_str(embed, &["footer", "text"]) {
+                body.push(footer.to_owned());
+            }
+        }
+        let bar_color = match embed.get("color").and_then(|x| x.as_u64()) {
+            Some(rgb) => ffi::color(&format!("{}", xterm_color(rgb))),
+            None => config::embed_bar_color(),
+        };
+        let reset = ffi::color("reset");
+        for line in body {
+            lines.push(format!("{}\u{2503}{} {}", bar_color, reset, line));
+        }
+    }
+    lines
+}
@@ -0,0 +1,274 @@
+use discord::ChannelRef;
+use discord::model::Emoji;
+
+use config;
+use ffi::Completion;
+
+// The shortcodes people actually type. Discord's full list is a couple
+// thousand entries long, anything missing here is sent through untouched.
+static UNICODE_EMOJI: &'static [(&'static str, &'static str)] = &[
+    ("+1", "\u{1f44d}"),
+    ("-1", "\u{1f44e}"),
+    ("100", "\u{1f4af}"),
+    ("alien", "\u{1f47d}"),
+    ("angry", "\u{1f620}"),
+    ("anguished", "\u{1f627}"),
+    ("astonished", "\u{1f632}"),
+    ("balloon", "\u{1f388}"),
+    ("beer", "\u{1f37a}"),
+    ("beers", "\u{1f37b}"),
+    ("bell", "\u{1f514}"),
+    ("blush", "\u{1f60a}"),
+    ("boom", "\u{1f4a5}"),
+    ("broken_heart", "\u{1f494}"),
+    ("bug", "\u{1f41b}"),
+    ("cake", "\u{1f370}"),
+    ("cat", "\u{1f431}"),
+    ("check", "\u{2714}\u{fe0f}"),
+    ("clap", "\u{1f44f}"),
+    ("coffee", "\u{2615}"),
+    ("cold_sweat", "\u{1f630}"),
+    ("confounded", "\u{1f616}"),
+    ("confused", "\u{1f615}"),
+    ("cookie", "\u{1f36a}"),
+    ("cool", "\u{1f192}"),
+    ("cry", "\u{1f622}"),
+    ("disappointed", "\u{1f61e}"),
+    ("dizzy_face", "\u{1f635}"),
+    ("dog", "\u{1f436}"),
+    ("eyes", "\u{1f440}"),
+    ("facepalm", "\u{1f926}"),
+    ("fearful", "\u{1f628}"),
+    ("fire", "\u{1f525}"),
+    ("flushed", "\u{1f633}"),
+    ("frowning", "\u{1f626}"),
+    ("ghost", "\u{1f47b}"),
+    ("gift", "\u{1f381}"),
+    ("grimacing", "\u{1f62c}"),
+    ("grin", "\u{1f601}"),
+    ("grinning", "\u{1f600}"),
+    ("hand_splayed", "\u{1f590}"),
+    ("heart", "\u{2764}\u{fe0f}"),
+    ("heart_eyes", "\u{1f60d}"),
+    ("hearts", "\u{2665}\u{fe0f}"),
+    ("hugging", "\u{1f917}"),
+    ("hushed", "\u{1f62f}"),
+    ("innocent", "\u{1f607}"),
+    ("joy", "\u{1f602}"),
+    ("kiss", "\u{1f48b}"),
+    ("kissing_heart", "\u{1f618}"),
+    ("laughing", "\u{1f606}"),
+    ("mask", "\u{1f637}"),
+    ("muscle", "\u{1f4aa}"),
+    ("neutral_face", "\u{1f610}"),
+    ("no_entry", "\u{26d4}"),
+    ("no_mouth", "\u{1f636}"),
+    ("ok", "\u{1f197}"),
+    ("ok_hand", "\u{1f44c}"),
+    ("open_mouth", "\u{1f62e}"),
+    ("pensive", "\u{1f614}"),
+    ("persevere", "\u{1f623}"),
+    ("point_down", "\u{1f447}"),
+    ("point_left", "\u{1f448}"),
+    ("point_right", "\u{1f449}"),
+    ("point_up", "\u{261d}\u{fe0f}"),
+    ("poop", "\u{1f4a9}"),
+    ("pray", "\u{1f64f}"),
+    ("question", "\u{2753}"),
+    ("rage", "\u{1f621}"),
+    ("raised_hands", "\u{1f64c}"),
+    ("relaxed", "\u{263a}\u{fe0f}"),
+    ("relieved", "\u{1f60c}"),
+    ("robot", "\u{1f916}"),
+    ("rocket", "\u{1f680}"),
+    ("rofl", "\u{1f923}"),
+    ("rolling_eyes", "\u{1f644}"),
+    ("scream", "\u{1f631}"),
+    ("see_no_evil", "\u{1f648}"),
+    ("shrug", "\u{1f937}"),
+    ("skull", "\u{1f480}"),
+    ("sleeping", "\u{1f634}"),
+    ("sleepy", "\u{1f62a}"),
+    ("slight_frown", "\u{1f641}"),
+    ("slight_smile", "\u{1f642}"),
+    ("smile", "\u{1f604}"),
+    ("smiley", "\u{1f603}"),
+    ("smirk", "\u{1f60f}"),
+    ("sob", "\u{1f62d}"),
+    ("sparkles", "\u{2728}"),
+    ("star", "\u{2b50}"),
+    ("stuck_out_tongue", "\u{1f61b}"),
+    ("stuck_out_tongue_winking_eye", "\u{1f61c}"),
+    ("sunglasses", "\u{1f60e}"),
+    ("sweat", "\u{1f613}"),
+    ("sweat_smile", "\u{1f605}"),
+    ("tada", "\u{1f389}"),
+    ("thinking", "\u{1f914}"),
+    ("thumbsdown", "\u{1f44e}"),
+    ("thumbsup", "\u{1f44d}"),
+    ("tired_face", "\u{1f62b}"),
+    ("triumph", "\u{1f624}"),
+    ("unamused", "\u{1f612}"),
+    ("upside_down", "\u{1f643}"),
+    ("v", "\u{270c}\u{fe0f}"),
+    ("warning", "\u{26a0}\u{fe0f}"),
+    ("wave", "\u{1f44b}"),
+    ("weary", "\u{1f629}"),
+    ("white_check_mark", "\u{2705}"),
+    ("wink", "\u{1f609}"),
+    ("worried", "\u{1f61f}"),
+    ("x", "\u{274c}"),
+    ("yum", "\u{1f60b}"),
+    ("zipper_mouth", "\u{1f910}"),
+    ("zzz", "\u{1f4a4}"),
+];
+
+fn find_unicode(shortcode: &str) -> Option<&'static str> {
+    UNICODE_EMOJI.iter().find(|&&(name, _)| name == shortcode).map(|&(_, emoji)| emoji)
+}
+
+// Custom emoji can only be used in the server they belong to (without nitro).
+// discord-rs's State applies ServerEmojisUpdate to LiveServer::emojis, so
+// this is always the current list.
+fn server_emojis<'a>(channel: &ChannelRef<'a>) -> &'a [Emoji] {
+    match *channel {
+        ChannelRef::Public(server, _) => &server.emojis,
+        _ => &[],
+    }
+}
+
+fn is_shortcode_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '+'
+}
+
+// `:name:` -> server emoji `<:name:id>` or unicode, on send
+pub fn replace_shortcodes(channel: &ChannelRef, content: String) -> String {
+    if !content.contains(':') {
+        return content;
+    }
+    let emojis = server_emojis(channel);
+    let mut result = String::with_capacity(content.len());
+    let mut rest = &*content;
+    while let Some(start) = rest.find(|c: char| c == ':' || c == '<') {
+        if rest[start..].starts_with('<') {
+            // custom emoji, mentions and links go through as they are
+            let len = match rest[start..].find(|c: char| c == '>' || c.is_whitespace()) {
+                Some(end) if rest[start + end..].starts_with('>') => end + 1,
+                _ => 1,
+            };
+            result.push_str(&rest[..start + len]);
+            rest = &rest[start + len..];
+            continue;
+        }
+        let after = &rest[start + 1..];
+        let end = after.find(|c: char| !is_shortcode_char(c));
+        let replacement = match end {
+            Some(end) if end > 0 && after[end..].starts_with(':') => {
+                let name = &after[..end];
+                let custom = emojis.iter().find(|e| e.name == name);
+                match custom {
+                    Some(emoji) => Some((format!("<:{}:{}>", emoji.name, emoji.id.0), end)),
+                    None => find_unicode(name).map(|x| (x.to_owned(), end)),
+                }
+            }
+            _ => None,
+        };
+        match replacement {
+            Some((emoji, end)) => {
+                result.push_str(&rest[..start]);
+                result.push_str(&emoji);
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push_str(&rest[..start + 1]);
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// `<:name:id>` and `<a:name:id>` -> `:name:`, on receive
+pub fn replace_custom_emoji(content: String) -> String {
+    if !content.contains("<:") && !content.contains("<a:") {
+        return content;
+    }
+    let show_url = config::emoji_urls();
+    let mut result = String::with_capacity(content.len());
+    let mut rest = &*content;
+    while let Some(start) = rest.find('<') {
+        result.push_str(&rest[..start]);
+        let tag = &rest[start..];
+        match parse_custom_emoji(tag) {
+            Some((len, animated, name, id)) => {
+                if show_url {
+                    let ext = if animated { "gif" } else { "png" };
+                    result.push_str(&format!(":{}: (https://cdn.discordapp.com/emojis/{}.{})",
+                                             name,
+                                             id,
+                                             ext));
+                } else {
+                    result.push_str(&format!(":{}:", name));
+                }
+                rest = &tag[len..];
+            }
+            None => {
+                result.push('<');
+                rest = &tag[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// (length, animated, name, id)
+fn parse_custom_emoji(tag: &str) -> Option<(usize, bool, &str, &str)> {
+    let (animated, body) = if tag.starts_with("<:") {
+        (false, &tag[2..])
+    } else if tag.starts_with("<a:") {
+        (true, &tag[3..])
+    } else {
+        return None;
+    };
+    let end = tryopt!(body.find('>'));
+    let inner = &body[..end];
+    let colon = tryopt!(inner.find(':'));
+    let (name, id) = (&inner[..colon], &inner[colon + 1..]);
+    if name.is_empty() || !name.chars().all(is_shortcode_char) || id.is_empty() ||
+       !id.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+    Some((tag.len() - body.len() + end + 1, animated, name, id))
+}
+
+pub fn complete(channel: Option<&ChannelRef>, completion: &mut Completion) {
+    if let Some(channel) = channel {
+        for emoji in server_emojis(channel) {
+            completion.add(&format!(":{}:", emoji.name));
+        }
+    }
+    for &(name, _) in UNICODE_EMOJI {
+        completion.add(&format!(":{}:", name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use discord::model::ChannelId;
+
+    use fixtures;
+    use mock_host;
+    use super::*;
+
+    #[test]
+    fn leaves_tokens_alone() {
+        let _session = mock_host::setup();
+        let state = fixtures::state();
+        let channel = unwrap!(state.find_channel(ChannelId(fixtures::GENERAL)));
+        assert_eq!(replace_shortcodes(&channel, "<a:smile:1> :smile: <@2>:smile:".into()),
+                   "<a:smile:1> \u{1f604} <@2>\u{1f604}");
+        assert_eq!(replace_shortcodes(&channel, "1 < 2 :smile:".into()), "1 < 2 \u{1f604}");
+    }
+}
@@ -2,18 +2,96 @@ use discord::*;
 use discord::model::*;
 
 use ffi;
+use config;
+use mute;
+use notify::{self, Mention};
+use friends;
+use nicklist;
+use mentions;
+use cache;
+use modlog;
+use emoji::replace_custom_emoji;
+use mentions::replace_mentions;
 use connection::*;
 use message::*;
 use types::*;
+use util::tag;
+
+// State::update then on_event, for events that need to know what they
+// changed
+pub fn update(state: &mut State, discord: &Discord, event: &Event) {
+    let old_member = match *event {
+        Event::ServerMemberUpdate { server_id, ref user, .. } => {
+            state
+                .find_server(server_id)
+                .and_then(|server| server.members.iter().find(|x| x.user.id == user.id))
+                .cloned()
+        }
+        _ => None,
+    };
+    state.update(event);
+    match *event {
+        Event::ServerCreate(_) |
+        Event::ServerUpdate(_) |
+        Event::ServerRoleCreate(_, _) |
+        Event::ServerRoleUpdate(_, _) |
+        Event::ChannelCreate(_) |
+        Event::ChannelUpdate(_) => config::update_names(state),
+        _ => {}
+    }
+    mentions::update(state, event);
+    on_event(state, discord, event);
+    cache::update(state, event);
+    if let (Some(old), &Event::ServerMemberUpdate { server_id, .. }) = (old_member, event) {
+        on_member_update(state, discord, server_id, &old);
+    }
+}
+
+// Like IRC: rename the nick everywhere and say so
+fn on_member_update(state: &State, discord: &Discord, server_id: ServerId, old: &Member) {
+    let server = match state.find_server(server_id) {
+        Some(server) => server,
+        None => return,
+    };
+    let new = match server.members.iter().find(|x| x.user.id == old.user.id) {
+        Some(new) => new,
+        None => return,
+    };
+    modlog::roles_changed(state, server_id, old, new);
+    let (old_name, new_name) = (old.name(&NameFormat::none()), new.name(&NameFormat::none()));
+    for channel in &server.channels {
+        let chan = match ChannelData::from_channel(state,
+                                                   discord,
+                                                   ChannelRef::Public(server, channel),
+                                                   false) {
+            Some(chan) => chan,
+            None => continue,
+        };
+        // replaces the old entry; roles change the color
+        if !chan.is_lazy() || nicklist::contains(&chan.buffer, new.user.id) {
+            chan.add_member(new);
+        }
+        // only where they're listed, lazy nicklists leave most members out
+        if old_name != new_name && nicklist::contains(&chan.buffer, new.user.id) {
+            chan.buffer
+                .print_tags("irc_nick,no_highlight",
+                            &format!("{}\t{} is now known as {}",
+                                     ffi::prefix("network"),
+                                     old.name(&NameFormat::color()),
+                                     new.name(&NameFormat::color())));
+        }
+    }
+}
 
 pub fn on_event(state: &State, discord: &Discord, event: &Event) -> Option<()> {
     match *event {
         Event::MessageCreate(ref message) => {
             let channel =
                 tryopt!(ChannelData::from_discord_event(state, discord, message.channel_id));
-            let is_self = is_self_mentioned(&channel,
+            let mention = is_self_mentioned(&channel,
                                             message.mention_everyone,
                                             Some(&message.author),
+                                            Some(&message.content),
                                             Some(&message.mentions),
                                             Some(&message.mention_roles));
             let message = tryopt!(format_message(&channel,
@@ -21,9 +99,11 @@ pub fn on_event(state: &State, discord: &Discord, event: &Event) -> Option<()> {
                                                  Some(&message.author),
                                                  Some(&message.content),
                                                  Some(&message.attachments),
+                                                 Some(&message.embeds),
                                                  "",
-                                                 is_self));
+                                                 mention));
             message.print(&channel.buffer);
+            channel.add_speaker(&message.author);
         }
         Event::MessageUpdate {
             id,
@@ -34,12 +114,17 @@ pub fn on_event(state: &State, discord: &Discord, event: &Event) -> Option<()> {
             ref mentions,
             ref mention_roles,
             ref attachments,
+            ref embeds,
             ..
         } => {
+            if let (Some(content), Some(old)) = (content.as_ref(), cache::get(id)) {
+                modlog::edited(state, channel_id, &old, content);
+            }
             let channel = tryopt!(ChannelData::from_discord_event(state, discord, channel_id));
-            let is_self = is_self_mentioned(&channel,
+            let mention = is_self_mentioned(&channel,
                                             mention_everyone.unwrap_or(false),
                                             author.as_ref(),
+                                            content.as_ref().map(|x| &**x),
                                             mentions.as_ref(),
                                             mention_roles.as_ref());
             let message = tryopt!(format_message(&channel,
@@ -47,19 +132,46 @@ pub fn on_event(state: &State, discord: &Discord, event: &Event) -> Option<()> {
                                                  author.as_ref(),
                                                  content.as_ref().map(|x| &**x),
                                                  attachments.as_ref(),
+                                                 embeds.as_ref(),
                                                  "EDIT: ",
-                                                 is_self));
+                                                 mention));
             message.print(&channel.buffer);
         }
         Event::MessageDelete {
             message_id,
             channel_id,
         } => {
+            if let Some(old) = cache::get(message_id) {
+                modlog::deleted(state, &old);
+            }
             let channel = tryopt!(ChannelData::from_discord_event(state, discord, channel_id));
-            let message =
-                tryopt!(format_message(&channel, message_id, None, None, None, "DELETE: ", false));
+            let message = tryopt!(format_message(&channel,
+                                                 message_id,
+                                                 None,
+                                                 None,
+                                                 None,
+                                                 None,
+                                                 "DELETE: ",
+                                                 Mention::None));
             message.print(&channel.buffer);
-            on_delete(&channel, &message);
+        }
+        Event::MessageDeleteBulk { channel_id, ref ids } => {
+            let known = ids.iter().filter_map(|&id| cache::get(id)).collect::<Vec<_>>();
+            modlog::bulk_deleted(state, channel_id, &known, ids.len());
+            let channel = tryopt!(ChannelData::from_discord_event(state, discord, channel_id));
+            for &id in ids {
+                let message =
+                    format_message(&channel, id, None, None, None, None, "DELETE: ", Mention::None);
+                if let Some(message) = message {
+                    message.print(&channel.buffer);
+                }
+            }
+        }
+        Event::ReactionAdd(ref reaction) => {
+            on_reaction(state, discord, reaction, true);
+        }
+        Event::ReactionRemove(ref reaction) => {
+            on_reaction(state, discord, reaction, false);
         }
         Event::ServerCreate(PossibleServer::Online(ref server)) => {
             for channel in &server.channels {
@@ -69,10 +181,17 @@ pub fn on_event(state: &State, discord: &Discord, event: &Event) -> Option<()> {
                                           true);
             }
         }
+        Event::ServerBanAdd(server_id, ref user) => {
+            modlog::member_event(state, server_id, "ban", user);
+        }
+        Event::ServerBanRemove(server_id, ref user) => {
+            modlog::member_event(state, server_id, "unban", user);
+        }
         Event::ServerMemberUpdate { .. } => {
-            // ... this event is broken and I don't want to deal with it
+            // see update(), which knows the member from before
         }
         Event::ServerMemberAdd(server_id, ref member) => {
+            modlog::member_event(state, server_id, "join", &member.user);
             if let Some(server) = state.find_server(server_id) {
                 for channel in &server.channels {
                     if let Some(chan) = ChannelData::from_channel(state,
@@ -80,30 +199,21 @@ pub fn on_event(state: &State, discord: &Discord, event: &Event) -> Option<()> {
                                                                   ChannelRef::Public(server,
                                                                                      channel),
                                                                   false) {
-                        chan.add_member(member)
+                        chan.member_joined(member)
                     }
                 }
             }
         }
         Event::ServerMemberRemove(server_id, ref user) => {
+            modlog::member_event(state, server_id, "leave", user);
             if let Some(server) = state.find_server(server_id) {
-                // why the HECK is this a user and not a member!?!?!
-                let mut member = None;
-                for mem in &server.members {
-                    if mem.id() == user.id() {
-                        member = Some(mem);
-                        break;
-                    }
-                }
-                if let Some(member) = member {
-                    for channel in &server.channels {
-                        if let Some(chan) = ChannelData::from_channel(state,
-                                                                      discord,
-                                                                      ChannelRef::Public(server,
-                                                                                         channel),
-                                                                      false) {
-                            chan.remove_member(member)
-                        }
+                for channel in &server.channels {
+                    if let Some(chan) = ChannelData::from_channel(state,
+                                                                  discord,
+                                                                  ChannelRef::Public(server,
+                                                                                     channel),
+                                                                  false) {
+                        chan.remove_user(user)
                     }
                 }
             }
@@ -122,12 +232,27 @@ pub fn on_event(state: &State, discord: &Discord, event: &Event) -> Option<()> {
                                                                                      channel),
                                                                   false) {
                         for member in members {
-                            chan.add_member(member)
+                            chan.member_joined(member)
                         }
                     }
                 }
             }
         }
+        Event::ServerRoleUpdate(server_id, _) |
+        Event::ServerRoleDelete(server_id, _) => {
+            // nick colors come from roles
+            if let Some(server) = state.find_server(server_id) {
+                for channel in &server.channels {
+                    if let Some(chan) = ChannelData::from_channel(state,
+                                                                  discord,
+                                                                  ChannelRef::Public(server,
+                                                                                     channel),
+                                                                  false) {
+                        chan.sync_nicklist();
+                    }
+                }
+            }
+        }
         Event::ChannelCreate(ref channel) => {
             let channel_ref = match *channel {
                 Channel::Public(ref public) => {
@@ -154,36 +279,64 @@ pub fn on_event(state: &State, discord: &Discord, event: &Event) -> Option<()> {
                 Channel::Group(ref group) => ChannelRef::Group(group),
                 Channel::Private(ref private) => ChannelRef::Private(private),
             };
-            ChannelData::from_channel(state, discord, channel_ref, false);
+            if let Some(channel) = ChannelData::from_channel(state, discord, channel_ref, false) {
+                channel.sync_name();
+            }
+        }
+        Event::ChannelDelete(Channel::Group(ref group)) => {
+            // we left (or it went away), state has already forgotten it
+            let name = ChannelData::buffer_name(ChannelRef::Group(group));
+            if let Some(buffer) = ffi::Buffer::search(&name) {
+                buffer.close();
+            }
+        }
+        Event::ChannelRecipientAdd(channel_id, ref user) => {
+            let channel = tryopt!(ChannelData::from_discord_event(state, discord, channel_id));
+            channel.add_user(user);
+            channel.sync_name();
+            channel
+                .buffer
+                .print(&format!("{} joined the group", user.name(&NameFormat::none())));
+        }
+        Event::ChannelRecipientRemove(channel_id, ref user) => {
+            let channel = tryopt!(ChannelData::from_discord_event(state, discord, channel_id));
+            channel.remove_user(user);
+            channel.sync_name();
+            channel
+                .buffer
+                .print(&format!("{} left the group", user.name(&NameFormat::none())));
+        }
+        Event::UserServerSettingsUpdate(ref settings) => {
+            mute::apply_settings(state, settings, false);
+            notify::apply_settings(state, settings);
+        }
+        Event::RelationshipAdd(ref relationship) => {
+            if relationship.kind == RelationshipType::IncomingRequest {
+                ffi::MAIN_BUFFER.print(&format!("{}: {} sent you a friend request \
+                                                 (see /discord friends)",
+                                                ::weechat::COMMAND,
+                                                tag(&relationship.user)));
+            }
+            friends::refresh(state);
         }
-        Event::UserServerSettingsUpdate(ref settings) => ChannelData::mute_channels(settings),
+        Event::RelationshipRemove(_, _) => friends::refresh(state),
+        Event::PresenceUpdate { server_id: None, .. } => friends::refresh(state),
         Event::CallCreate(_) |
         Event::CallDelete(_) |
         Event::CallUpdate { .. } |
         Event::ChannelDelete(_) |
         Event::ChannelPinsAck { .. } |
         Event::ChannelPinsUpdate { .. } |
-        Event::ChannelRecipientAdd(_, _) |
-        Event::ChannelRecipientRemove(_, _) |
         Event::MessageAck { .. } |
-        Event::MessageDeleteBulk { .. } |
         Event::PresenceUpdate { .. } |
         Event::PresencesReplace(_) |
-        Event::ReactionAdd(_) |
-        Event::ReactionRemove(_) |
         Event::Ready(_) |
-        Event::RelationshipAdd(_) |
-        Event::RelationshipRemove(_, _) |
         Event::Resumed { .. } |
-        Event::ServerBanAdd(_, _) |
-        Event::ServerBanRemove(_, _) |
         Event::ServerCreate(PossibleServer::Offline(_)) |
         Event::ServerDelete(_) |
         Event::ServerEmojisUpdate(_, _) |
         Event::ServerIntegrationsUpdate(_) |
         Event::ServerRoleCreate(_, _) |
-        Event::ServerRoleDelete(_, _) |
-        Event::ServerRoleUpdate(_, _) |
         Event::ServerUpdate(_) |
         Event::TypingStart { .. } |
         Event::UserNoteUpdate(_, _) |
@@ -197,26 +350,234 @@ pub fn on_event(state: &State, discord: &Discord, event: &Event) -> Option<()> {
     Some(())
 }
 
-fn on_delete(channel: &ChannelData, message: &FormattedMessage) {
-    if let ChannelRef::Public(server, _) = channel.channel {
-        if let Some(dest_chan) = ffi::get_option(&format!("on_delete.{}", server.id.0))
-               .and_then(|id| id.parse::<u64>().ok())
-               .map(ChannelId) {
-            if channel.state.find_channel(dest_chan).is_none() {
-                return;
-            }
-            let message = format!("AUTO: Deleted message by {} in {}: {}",
-                                  message.author,
-                                  message.channel,
-                                  message.content);
-            let message = ffi::remove_color(&message);
-            let result = channel.discord.send_message(dest_chan, &message, "", false);
-            match result {
-                Ok(_) => (),
-                Err(err) => {
-                    ffi::MAIN_BUFFER.print(&format!("Failed to send on_delete message: {}", err))
-                }
-            }
+// Only for messages still in the cache, there's nothing to say otherwise
+fn on_reaction(state: &State, discord: &Discord, reaction: &Reaction, added: bool) -> Option<()> {
+    if !config::reactions() {
+        return None;
+    }
+    let message = tryopt!(cache::get(reaction.message_id));
+    let channel = tryopt!(ChannelData::from_discord_event(state, discord, reaction.channel_id));
+    let emoji = match reaction.emoji {
+        ReactionEmoji::Unicode(ref emoji) => emoji.clone(),
+        ReactionEmoji::Custom { ref name, .. } => format!(":{}:", name),
+    };
+    let format = NameFormat::color();
+    let user = match channel.channel {
+        ChannelRef::Public(server, _) => {
+            server
+                .members
+                .iter()
+                .find(|x| x.user.id == reaction.user_id)
+                .map(|x| x.name(&format))
         }
+        ChannelRef::Group(group) => {
+            group
+                .recipients
+                .iter()
+                .find(|x| x.id == reaction.user_id)
+                .map(|x| x.name(&format))
+        }
+        ChannelRef::Private(private) if private.recipient.id == reaction.user_id => {
+            Some(private.recipient.name(&format))
+        }
+        ChannelRef::Private(_) => None,
+    };
+    let me = state.user();
+    let user = if reaction.user_id == me.id {
+        me.name(&format)
+    } else {
+        tryopt!(user)
+    };
+    let excerpt = message.text().lines().next().unwrap_or("").to_owned();
+    let excerpt = replace_custom_emoji(replace_mentions(&channel.channel, &excerpt));
+    channel.buffer.print_tags("notify_none,no_highlight",
+                              &format!("{}\t{} {} {} {} {}: {}",
+                                       ffi::prefix("network"),
+                                       user,
+                                       if added { "reacted with" } else { "took back" },
+                                       emoji,
+                                       if added { "to" } else { "on" },
+                                       format.format("", &message.author),
+                                       excerpt));
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use discord::model::{ChannelId, Event, MessageId, UserId};
+
+    use config;
+    use connection::ChannelData;
+    use ffi::Buffer;
+    use fixtures::{self, ALICE, GENERAL, ME, ROLE};
+    use mock_host;
+    use super::*;
+
+    #[test]
+    fn messages_end_up_in_their_buffer() {
+        let session = mock_host::setup();
+        let (mut state, discord) = (fixtures::state(), fixtures::discord());
+        let hello = fixtures::message(100, ALICE, "hello <@1>", &[ME]);
+        // server channels aren't opened by messages
+        update(&mut state, &discord, &Event::MessageCreate(hello.clone()));
+        assert!(Buffer::search("10.11").is_none());
+
+        let buffer = fixtures::general_buffer(&state, &discord);
+        assert_eq!(session.host().nicks(&buffer), vec!["me", "alice"]);
+        update(&mut state, &discord, &Event::MessageCreate(hello));
+        update(&mut state,
+               &discord,
+               &Event::MessageDelete {
+                    channel_id: ChannelId(GENERAL),
+                    message_id: MessageId(100),
+                });
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].prefix, "alice");
+        assert_eq!(lines[0].message, "hello @me");
+        assert!(lines[0].has_tag("notify_highlight"));
+        assert!(lines[0].has_tag("discord_messageid_100"));
+        // the deleted message comes back from the message cache
+        assert_eq!(lines[1].prefix, "alice");
+        assert_eq!(lines[1].message, "DELETE: hello @me");
+    }
+
+    #[test]
+    fn ignored_users_are_hidden() {
+        let session = mock_host::setup();
+        let (state, discord) = (fixtures::state(), fixtures::discord());
+        let buffer = fixtures::general_buffer(&state, &discord);
+        config::set_ignored(None, &[UserId(ALICE)]);
+        let message = fixtures::message(100, ALICE, "one\ntwo", &[]);
+        on_event(&state, &discord, &Event::MessageCreate(message.clone()));
+        assert_eq!(session.host().messages(&buffer),
+                   vec!["(2 lines from an ignored user hidden)"]);
+        assert!(buffer.lines()[0].has_tag("no_highlight"));
+
+        session.host().set("weecord.look.ignored_messages", "hide");
+        on_event(&state, &discord, &Event::MessageCreate(message));
+        assert_eq!(buffer.lines().len(), 1);
+    }
+
+    #[test]
+    fn member_updates_rename_and_recolor() {
+        let session = mock_host::setup();
+        let (mut state, discord) = (fixtures::state(), fixtures::discord());
+        let buffer = fixtures::general_buffer(&state, &discord);
+        assert_eq!(session.host().nick_colors(&buffer, "alice"), vec![""]);
+        update(&mut state, &discord, &fixtures::member_update(Some("al"), &[ROLE]));
+        assert_eq!(session.host().nicks(&buffer), vec!["me", "al"]);
+        // mods are red
+        assert_eq!(session.host().nick_colors(&buffer, "al"), vec!["196"]);
+        assert_eq!(session.host().messages(&buffer),
+                   vec!["alice is now known as al"]);
+    }
+
+    #[test]
+    fn renames_are_announced_where_listed() {
+        let session = mock_host::setup();
+        session.host().set("weecord.look.nicklist_max", "1");
+        let (mut state, discord) = (fixtures::state(), fixtures::discord());
+        let buffer = fixtures::general_buffer(&state, &discord);
+        // alice isn't in the lazy nicklist
+        update(&mut state, &discord, &fixtures::member_update(Some("al"), &[]));
+        assert_eq!(session.host().nicks(&buffer), vec!["me"]);
+        assert!(session.host().messages(&buffer).is_empty());
+    }
+
+    #[test]
+    fn nicks_are_told_apart_by_user() {
+        let session = mock_host::setup();
+        let (mut state, discord) = (fixtures::state(), fixtures::discord());
+        let buffer = fixtures::general_buffer(&state, &discord);
+        // alice, red, goes by "me" too
+        update(&mut state, &discord, &fixtures::member_update(Some("me"), &[ROLE]));
+        assert_eq!(session.host().nick_colors(&buffer, "me"), vec!["", "196"]);
+        update(&mut state, &discord, &fixtures::member_remove(ALICE));
+        assert_eq!(session.host().nick_colors(&buffer, "me"), vec![""]);
+    }
+
+    #[test]
+    fn large_servers_get_lazy_nicklists() {
+        let session = mock_host::setup();
+        session.host().set("weecord.look.nicklist_max", "1");
+        let (mut state, discord) = (fixtures::state(), fixtures::discord());
+        let buffer = {
+            let channel = unwrap!(state.find_channel(ChannelId(GENERAL)));
+            let channel = unwrap!(ChannelData::from_channel(&state, &discord, channel, true));
+            assert_eq!(session.host().nicks(&channel.buffer), vec!["me"]);
+            // what switching to the buffer does; it's already full
+            channel.fill_nicklist();
+            channel.buffer
+        };
+        assert_eq!(session.host().nicks(&buffer), vec!["me"]);
+        let hello = fixtures::message_json(100, ALICE, "hello", &[]);
+        update(&mut state, &discord, &fixtures::event("MESSAGE_CREATE", hello));
+        assert_eq!(session.host().nicks(&buffer), vec!["me", "alice"]);
+    }
+
+    #[test]
+    fn old_messages_come_from_the_cache() {
+        let session = mock_host::setup();
+        let (mut state, discord) = (fixtures::state(), fixtures::discord());
+        let buffer = fixtures::general_buffer(&state, &discord);
+        let hello = fixtures::message_json(100, ALICE, "hello", &[]);
+        update(&mut state, &discord, &fixtures::event("MESSAGE_CREATE", hello));
+        // long gone from the buffer
+        buffer.clear();
+        let reaction = json!({
+            "channel_id": GENERAL.to_string(),
+            "message_id": "100",
+            "user_id": ME.to_string(),
+            "emoji": { "id": null, "name": "👍" },
+        });
+        update(&mut state, &discord, &fixtures::event("MESSAGE_REACTION_ADD", reaction));
+        let delete = json!({ "id": "100", "channel_id": GENERAL.to_string() });
+        update(&mut state, &discord, &fixtures::event("MESSAGE_DELETE", delete));
+        assert_eq!(session.host().messages(&buffer),
+                   vec!["me reacted with 👍 to alice: hello", "DELETE: hello"]);
+        assert_eq!(::cache::get(MessageId(100)), None);
+    }
+
+    #[test]
+    fn stored_messages_outlive_a_restart() {
+        let session = mock_host::setup();
+        session.host().set("weecord.network.message_store", "on");
+        let (mut state, discord) = (fixtures::state(), fixtures::discord());
+        // sent just now, old ids are past weecord.network.message_store_days
+        let now = unwrap1!(SystemTime::now().duration_since(UNIX_EPOCH)).as_secs();
+        let id = (now - 1420070400) * 1000 << 22;
+        let hello = fixtures::message_json(id, ALICE, "hello", &[]);
+        update(&mut state, &discord, &fixtures::event("MESSAGE_CREATE", hello));
+        ::store::stop();
+        ::cache::end();
+        assert_eq!(unwrap1!(::store::start()), 1);
+        let buffer = fixtures::general_buffer(&state, &discord);
+        let delete = json!({ "id": id.to_string(), "channel_id": GENERAL.to_string() });
+        update(&mut state, &discord, &fixtures::event("MESSAGE_DELETE", delete));
+        assert_eq!(session.host().messages(&buffer), vec!["hello", "DELETE: hello"]);
+        ::store::stop();
+        assert_eq!(unwrap1!(::store::start()), 0);
+    }
+
+    #[test]
+    fn moderation_events_are_logged() {
+        let session = mock_host::setup();
+        session.host().set("weecord.server.10.modlog", "buffer");
+        session.host().set("weecord.server.10.modlog_events", "delete,roles");
+        let (mut state, discord) = (fixtures::state(), fixtures::discord());
+        let hello = fixtures::message_json(100, ALICE, "hello", &[]);
+        update(&mut state, &discord, &fixtures::event("MESSAGE_CREATE", hello));
+        update(&mut state, &discord, &fixtures::member_update(None, &[ROLE]));
+        let delete = json!({ "id": "100", "channel_id": GENERAL.to_string() });
+        update(&mut state, &discord, &fixtures::event("MESSAGE_DELETE", delete));
+        // not in modlog_events
+        update(&mut state, &discord, &fixtures::member_remove(ALICE));
+        let buffer = unwrap!(Buffer::search(::modlog::BUFFER_NAME));
+        assert_eq!(session.host().messages(&buffer),
+                   vec!["Roles of alice#0002 changed: +mods",
+                        "AUTO: Deleted message by alice in general: hello"]);
+        assert_eq!(buffer.lines()[0].prefix, "Test");
     }
 }
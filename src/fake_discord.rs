@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::spawn;
+use hyper::method::Method;
+use hyper::server::{Listening, Request, Response, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use serde_json::{self, Value};
+use websocket;
+use websocket::Sender as WsSender;
+use websocket::Receiver as WsReceiver;
+use websocket::message::{Message, Type};
+
+// A local stand-in for Discord: a REST server and a gateway that play back
+// scripted events. Point weecord.network.api_url at api_url and the whole
+// plugin runs offline.
+pub struct FakeDiscord {
+    pub api_url: String,
+    rest: Arc<Mutex<Rest>>,
+    gateway: Sender<Input>,
+    // what the plugin sent over the gateway
+    sent: Arc<Mutex<Vec<Value>>>,
+    _listening: Listening,
+}
+
+// A REST request the plugin made
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub method: Method,
+    pub path: String,
+    pub body: Value,
+}
+
+struct Rest {
+    gateway_url: String,
+    // for what Discord echoes over the gateway
+    gateway: Sender<Input>,
+    calls: Vec<Call>,
+    // (method, path) -> response body
+    responses: BTreeMap<(String, String), Value>,
+    // path -> number of 429s left
+    rate_limits: BTreeMap<String, usize>,
+}
+
+enum Input {
+    Dispatch(String, Value),
+    Disconnect,
+    // from the nth gateway connection
+    Client(usize, Value),
+    Closed(usize),
+}
+
+// How long the plugin is told to wait on a 429
+const RETRY_AFTER_MS: u64 = 10;
+
+impl FakeDiscord {
+    // ready is the READY payload, e.g. fixtures::ready_json()
+    pub fn start(ready: Value) -> FakeDiscord {
+        let ws_server = unwrap1!(websocket::Server::bind("127.0.0.1:0"));
+        let gateway_url = format!("ws://{}", unwrap1!(ws_server.local_addr()));
+        let (send, recv) = channel();
+        let client_send = send.clone();
+        let rest_send = send.clone();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let gateway_sent = sent.clone();
+        spawn(move || run_gateway(ws_server, ready, recv, client_send, gateway_sent));
+
+        let rest = Arc::new(Mutex::new(Rest {
+                                           gateway_url: gateway_url,
+                                           gateway: rest_send,
+                                           calls: Vec::new(),
+                                           responses: BTreeMap::new(),
+                                           rate_limits: BTreeMap::new(),
+                                       }));
+        let handler_rest = rest.clone();
+        let server = unwrap1!(Server::http("127.0.0.1:0"));
+        let listening = unwrap1!(server.handle(move |request: Request, response: Response| {
+                                                   on_request(&handler_rest, request, response)
+                                               }));
+        FakeDiscord {
+            api_url: format!("http://{}/api", listening.socket),
+            rest: rest,
+            gateway: send,
+            sent: sent,
+            _listening: listening,
+        }
+    }
+
+    // Sends a gateway event, e.g. ("MESSAGE_CREATE", fixtures::message_json(..))
+    pub fn dispatch(&self, kind: &str, data: Value) {
+        let _ = self.gateway.send(Input::Dispatch(kind.into(), data));
+    }
+
+    // Closes the gateway connection; the plugin is expected to resume
+    pub fn disconnect(&self) {
+        let _ = self.gateway.send(Input::Disconnect);
+    }
+
+    // What a REST request gets instead of {}
+    pub fn respond(&self, method: Method, path: &str, body: Value) {
+        self.lock()
+            .responses
+            .insert((method.to_string(), path.into()), body);
+    }
+
+    // The next `times` requests to path get a 429
+    pub fn rate_limit(&self, path: &str, times: usize) {
+        self.lock().rate_limits.insert(path.into(), times);
+    }
+
+    // Every REST request so far, oldest first
+    pub fn calls(&self) -> Vec<Call> {
+        self.lock().calls.clone()
+    }
+
+    // Every gateway payload from the plugin, oldest first
+    pub fn sent(&self) -> Vec<Value> {
+        self.sent
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<Rest> {
+        self.rest
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Rest {
+    fn on_call(&mut self, call: Call) -> (StatusCode, Value) {
+        let path = call.path.clone();
+        let key = (call.method.to_string(), path.clone());
+        let body = call.body.clone();
+        self.calls.push(call);
+        if let Some(left) = self.rate_limits.get_mut(&path) {
+            if *left > 0 {
+                *left -= 1;
+                return (StatusCode::TooManyRequests,
+                        json!({
+                            "message": "You are being rate limited.",
+                            "retry_after": RETRY_AFTER_MS,
+                            "global": false,
+                        }));
+            }
+        }
+        if key.0 == "GET" && path == "/gateway" {
+            return (StatusCode::Ok, json!({ "url": self.gateway_url }));
+        }
+        if key.0 == "PATCH" && path == "/users/@me/settings" {
+            let _ = self.gateway
+                .send(Input::Dispatch("USER_SETTINGS_UPDATE".into(), body));
+        }
+        match self.responses.get(&key) {
+            Some(body) => (StatusCode::Ok, body.clone()),
+            None => (StatusCode::Ok, json!({})),
+        }
+    }
+}
+
+fn on_request(rest: &Mutex<Rest>, mut request: Request, mut response: Response) {
+    let path = match request.uri {
+        RequestUri::AbsolutePath(ref path) => path.trim_left_matches("/api").to_owned(),
+        _ => String::new(),
+    };
+    let mut body = String::new();
+    let _ = request.read_to_string(&mut body);
+    let call = Call {
+        method: request.method.clone(),
+        path: path,
+        body: serde_json::from_str(&body).unwrap_or(Value::Null),
+    };
+    let (status, reply) = rest.lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .on_call(call);
+    *response.status_mut() = status;
+    let _ = response.send(reply.to_string().as_bytes());
+}
+
+fn send<S: WsSender>(sender: &mut S, payload: Value) -> bool {
+    sender
+        .send_message(&Message::text(payload.to_string()))
+        .is_ok()
+}
+
+// Ids go over the wire as strings or numbers
+fn same_id(a: &Value, b: &Value) -> bool {
+    let id = |x: &Value| {
+        x.as_str()
+            .map(|x| x.to_owned())
+            .or_else(|| x.as_u64().map(|x| x.to_string()))
+    };
+    id(a).is_some() && id(a) == id(b)
+}
+
+// One client at a time: hello, READY (or RESUMED), then whatever the test
+// scripts, until a disconnect moves on to the next connection
+fn run_gateway(server: websocket::Server,
+               ready: Value,
+               input: Receiver<Input>,
+               client_send: Sender<Input>,
+               sent: Arc<Mutex<Vec<Value>>>) {
+    let mut seq = 0;
+    for (id, connection) in server.enumerate() {
+        let request = match connection.map(|x| x.read_request()) {
+            Ok(Ok(request)) => request,
+            _ => continue,
+        };
+        let client = match request.accept().send() {
+            Ok(client) => client,
+            Err(_) => continue,
+        };
+        let (mut sender, mut receiver) = client.split();
+        let client_send = client_send.clone();
+        let sent = sent.clone();
+        spawn(move || {
+            for message in receiver.incoming_messages() {
+                let message: Message = match message {
+                    Ok(message) => message,
+                    Err(_) => break,
+                };
+                if message.opcode == Type::Close {
+                    break;
+                }
+                if let Ok(payload) = serde_json::from_slice::<Value>(&message.payload) {
+                    sent.lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .push(payload.clone());
+                    if client_send.send(Input::Client(id, payload)).is_err() {
+                        return;
+                    }
+                }
+            }
+            let _ = client_send.send(Input::Closed(id));
+        });
+
+        send(&mut sender,
+             json!({ "op": 10, "d": { "heartbeat_interval": 41250, "_trace": [] } }));
+        // scripted events wait until the client has identified or resumed
+        let mut identified = false;
+        let mut pending = Vec::new();
+        loop {
+            match input.recv() {
+                Err(_) => return,
+                Ok(Input::Dispatch(kind, data)) => pending.push((kind, data)),
+                Ok(Input::Disconnect) => {
+                    let _ = sender.send_message(&Message::close_because(4000, "scripted".into()));
+                    break;
+                }
+                Ok(Input::Closed(closed)) => {
+                    if closed == id {
+                        break;
+                    }
+                }
+                Ok(Input::Client(from, _)) if from != id => (),
+                Ok(Input::Client(_, payload)) => {
+                    match payload.get("op").and_then(|x| x.as_u64()) {
+                        // heartbeat
+                        Some(1) => {
+                            send(&mut sender, json!({ "op": 11, "d": null }));
+                        }
+                        // identify
+                        Some(2) => {
+                            identified = true;
+                            pending.insert(0, ("READY".into(), ready.clone()));
+                        }
+                        // resume
+                        Some(6) => {
+                            identified = true;
+                            pending.insert(0, ("RESUMED".into(), json!({ "_trace": [] })));
+                        }
+                        // sync guilds: their members from READY
+                        Some(12) => {
+                            let ids = payload["d"].as_array().cloned().unwrap_or_default();
+                            for guild in ready["guilds"].as_array().into_iter().flat_map(|x| x) {
+                                if ids.iter().any(|id| same_id(id, &guild["id"])) {
+                                    pending.push(("GUILD_SYNC".into(),
+                                                  json!({
+                                                      "id": guild["id"],
+                                                      "large": guild["large"],
+                                                      "members": guild["members"],
+                                                      "presences": guild["presences"],
+                                                  })));
+                                }
+                            }
+                        }
+                        // request guild members: everyone from READY, in one chunk
+                        Some(8) => {
+                            for guild in ready["guilds"].as_array().into_iter().flat_map(|x| x) {
+                                pending.push(("GUILD_MEMBERS_CHUNK".into(),
+                                              json!({
+                                                  "guild_id": guild["id"],
+                                                  "members": guild["members"],
+                                              })));
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            if identified {
+                for (kind, data) in pending.drain(..) {
+                    seq += 1;
+                    send(&mut sender, json!({ "op": 0, "s": seq, "t": kind, "d": data }));
+                }
+            }
+        }
+    }
+}
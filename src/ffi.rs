@@ -1,356 +1,145 @@
 use libc::*;
-use std::ffi::*;
+use std::any::Any;
 use std::panic::*;
 
-#[derive(PartialEq, Eq, Hash)]
+use host::{host, Line};
+
+#[derive(PartialEq, Eq, Hash, Debug)]
 pub struct Buffer {
     ptr: *mut c_void,
 }
 
 pub const MAIN_BUFFER: Buffer = Buffer { ptr: 0 as *mut c_void };
 
-/*
 pub struct Completion {
     ptr: *mut c_void,
 }
-*/
 
-pub struct Hook {
+// A nicklist entry
+#[derive(Debug)]
+pub struct Nick {
     ptr: *mut c_void,
 }
 
-impl Drop for Hook {
-    fn drop(&mut self) {
-        extern "C" {
-            fn wdc_unhook(hook: *mut c_void);
-        }
-        unsafe {
-            wdc_unhook(self.ptr);
-        }
-    }
-}
-
-pub struct WeechatAny {
-    data: *mut c_void,
-    hdata: *mut c_void,
-}
-
-impl PartialEq for WeechatAny {
-    fn eq(&self, rhs: &Self) -> bool {
-        self.ptr() == rhs.ptr()
-    }
-}
-
-
-pub struct SharedString(pub String);
-
-fn strip_indexer_field(field: &str) -> &str {
-    if let Some(idx) = field.find('|') {
-        &field[(idx + 1)..]
-    } else {
-        field
-    }
+pub struct Hook {
+    ptr: *mut c_void,
+    _data: Option<Box<Any>>,
 }
 
-pub trait WeechatObject {
-    fn from_ptr_hdata(ptr: *mut c_void, hdata: *mut c_void) -> Self;
-    fn ptr(&self) -> *mut c_void;
-    fn hdata(&self) -> *mut c_void;
-
-    fn get<T: HDataGetResult>(&self, field: &str) -> Option<T> {
-        let field_type = T::weechat_type();
-        if field_type != "" {
-            let actual_type = hdata_get_var_type_string(self.hdata(), field);
-            if field_type != actual_type {
-                really_bad(format!("Field {} had type {} but we expected {}",
-                                   field,
-                                   actual_type,
-                                   field_type));
-            }
+impl Hook {
+    // data is whatever the host needs alive until the hook is removed
+    pub fn new(ptr: *mut c_void, data: Option<Box<Any>>) -> Hook {
+        Hook {
+            ptr: ptr,
+            _data: data,
         }
-        T::new::<Self>(self, field)
     }
 
-    fn get_idx<T: HDataGetResult>(&self, field: &str, index: usize) -> Option<T> {
-        self.get(&format!("{}|{}", index, field))
-    }
-
-    fn get_any(&self, field: &str) -> Option<WeechatAny> {
-        self.get(field)
+    pub fn ptr(&self) -> *mut c_void {
+        self.ptr
     }
 }
 
-impl WeechatObject for WeechatAny {
-    fn from_ptr_hdata(data: *mut c_void, hdata: *mut c_void) -> Self {
-        WeechatAny {
-            data: data,
-            hdata: hdata,
-        }
-    }
-
-    fn ptr(&self) -> *mut c_void {
-        self.data
-    }
-
-    fn hdata(&self) -> *mut c_void {
-        self.hdata
+impl Drop for Hook {
+    fn drop(&mut self) {
+        host().unhook(self);
     }
 }
 
-pub trait HDataGetResult: Sized {
-    fn new<T: WeechatObject + ?Sized>(parent: &T, field: &str) -> Option<Self>;
-    fn weechat_type() -> &'static str;
+pub fn really_bad(message: String) -> ! {
+    MAIN_BUFFER.print(&format!("{}: Internal error - {}", ::weechat::COMMAND, message));
+    panic!(message); // hopefully we hit a catch_unwind
 }
 
-impl<T: WeechatObject> HDataGetResult for T {
-    fn new<P: WeechatObject + ?Sized>(parent: &P, field: &str) -> Option<Self> {
-        let data = hdata_pointer(parent.hdata(), parent.ptr(), field);
-        let data = match data {
-            Some(data) => data,
-            None => return None,
-        };
-        let hdata_name = hdata_get_var_hdata(parent.hdata(), field);
-        let hdata = hdata_get(&hdata_name);
-        Some(Self::from_ptr_hdata(data, hdata))
-    }
-
-    fn weechat_type() -> &'static str {
-        "pointer"
+impl Buffer {
+    pub fn from_ptr(ptr: *mut c_void) -> Buffer {
+        Buffer { ptr: ptr }
     }
-}
 
-impl HDataGetResult for String {
-    fn new<P: WeechatObject + ?Sized>(parent: &P, field: &str) -> Option<Self> {
-        hdata_string(parent.hdata(), parent.ptr(), field)
+    pub fn ptr(&self) -> *mut c_void {
+        self.ptr
     }
 
-    fn weechat_type() -> &'static str {
-        "string"
+    pub fn new(name: &str, on_input: fn(Buffer, &str)) -> Option<Buffer> {
+        host().buffer_new(name, on_input)
     }
-}
 
-impl HDataGetResult for SharedString {
-    fn new<P: WeechatObject + ?Sized>(parent: &P, field: &str) -> Option<Self> {
-        hdata_string(parent.hdata(), parent.ptr(), field).map(SharedString)
+    pub fn search(name: &str) -> Option<Buffer> {
+        host().buffer_search(name)
     }
 
-    fn weechat_type() -> &'static str {
-        "shared_string"
+    pub fn print(&self, message: &str) {
+        host().print(self, message)
     }
-}
 
-impl HDataGetResult for i32 {
-    fn new<P: WeechatObject + ?Sized>(parent: &P, field: &str) -> Option<Self> {
-        hdata_integer(parent.hdata(), parent.ptr(), field).map(|x| x as i32)
+    pub fn print_tags(&self, tags: &str, message: &str) {
+        host().print_tags(self, tags, message)
     }
 
-    fn weechat_type() -> &'static str {
-        "integer"
+    pub fn print_date_tags(&self, date: i64, tags: &str, message: &str) {
+        host().print_date_tags(self, date, tags, message)
     }
-}
 
-pub fn really_bad(message: String) -> ! {
-    MAIN_BUFFER.print(&format!("{}: Internal error - {}", ::weechat::COMMAND, message));
-    panic!(message); // hopefully we hit a catch_unwind
-}
-
-impl Buffer {
-    pub fn new(name: &str, on_input: fn(Buffer, &str)) -> Option<Buffer> {
-        extern "C" {
-            fn wdc_buffer_new(name: *const c_char,
-                              pointer: *const c_void,
-                              input_callback: extern "C" fn(*const c_void,
-                                                            *mut c_void,
-                                                            *mut c_void,
-                                                            *const c_char)
-                                                            -> c_int,
-                              close_callback: extern "C" fn(*const c_void,
-                                                            *mut c_void,
-                                                            *mut c_void)
-                                                            -> c_int)
-                              -> *mut c_void;
-        }
-        extern "C" fn input_cb(pointer: *const c_void,
-                               data: *mut c_void,
-                               buffer: *mut c_void,
-                               input_data: *const c_char)
-                               -> c_int {
-            let _ = data;
-            wrap_panic(|| {
-                let buffer = Buffer { ptr: buffer };
-                let on_input: fn(Buffer, &str) = unsafe { ::std::mem::transmute(pointer) };
-                let input_data = unsafe { CStr::from_ptr(input_data).to_str() };
-                let input_data = match input_data {
-                    Ok(x) => x,
-                    Err(_) => return,
-                };
-                on_input(buffer, input_data);
-            });
-            0
-        }
-        extern "C" fn close_cb(pointer: *const c_void,
-                               data: *mut c_void,
-                               buffer: *mut c_void)
-                               -> c_int {
-            let _ = pointer;
-            let _ = data;
-            let _ = buffer;
-            0
-        }
-        unsafe {
-            let name = unwrap1!(CString::new(name));
-            let pointer = on_input as *const c_void;
-            let result = wdc_buffer_new(name.as_ptr(), pointer, input_cb, close_cb);
-            if result.is_null() {
-                None
-            } else {
-                Some(Buffer { ptr: result })
-            }
-        }
+    pub fn close(&self) {
+        host().buffer_close(self)
     }
 
-    pub fn search(name: &str) -> Option<Buffer> {
-        extern "C" {
-            fn wdc_buffer_search(name: *const c_char) -> *mut c_void;
-        }
-        unsafe {
-            let name_c = unwrap1!(CString::new(name));
-            let result = wdc_buffer_search(name_c.as_ptr());
-            if result.is_null() {
-                None
-            } else {
-                Some(Buffer { ptr: result })
-            }
-        }
+    pub fn clear(&self) {
+        host().buffer_clear(self)
     }
 
-    pub fn print(&self, message: &str) {
-        extern "C" {
-            fn wdc_print(buffer: *mut c_void, message: *const c_char);
-        }
-        unsafe {
-            let msg = unwrap1!(CString::new(message));
-            wdc_print(self.ptr, msg.as_ptr());
-        }
+    pub fn set(&self, property: &str, value: &str) {
+        host().buffer_set(self, property, value)
     }
 
-    pub fn print_tags(&self, tags: &str, message: &str) {
-        extern "C" {
-            fn wdc_print_tags(buffer: *mut c_void, tags: *const c_char, message: *const c_char);
-        }
-        unsafe {
-            let msg = unwrap1!(CString::new(message));
-            let tags = unwrap1!(CString::new(tags));
-            wdc_print_tags(self.ptr, tags.as_ptr(), msg.as_ptr());
-        }
+    pub fn get(&self, property: &str) -> Option<String> {
+        host().buffer_get(self, property)
     }
 
-    /*
-    pub fn load_weechat_backlog(&self) {
-        extern "C" {
-            fn wdc_load_backlog(sig_data: *mut c_void);
-        }
-        unsafe {
-            wdc_load_backlog(self.ptr as *mut c_void);
-        }
+    pub fn lines(&self) -> Vec<Line> {
+        host().buffer_lines(self)
     }
-    */
 
-    pub fn set(&self, property: &str, value: &str) {
-        extern "C" {
-            fn wdc_buffer_set(buffer: *mut c_void, property: *const c_char, value: *const c_char);
-        }
-        unsafe {
-            let property = unwrap1!(CString::new(property));
-            let value = unwrap1!(CString::new(value));
-            wdc_buffer_set(self.ptr, property.as_ptr(), value.as_ptr());
-        }
+    pub fn add_nick(&self, nick: &str, color: &str) -> Option<Nick> {
+        host().nick_add(self, nick, color)
     }
 
-    pub fn get(&self, property: &str) -> Option<String> {
-        extern "C" {
-            fn wdc_buffer_get(buffer: *mut c_void, property: *const c_char) -> *const c_char;
-        }
-        unsafe {
-            let property = unwrap1!(CString::new(property));
-            let value = wdc_buffer_get(self.ptr, property.as_ptr());
-            if value.is_null() {
-                None
-            } else {
-                Some(CStr::from_ptr(value).to_string_lossy().into_owned())
-            }
-        }
+    pub fn remove_all_nicks(&self) {
+        host().nick_remove_all(self)
     }
 
-    /*
-    pub fn nick_exists(&self, nick: &str) -> bool {
-        extern "C" {
-            fn wdc_nicklist_nick_exists(buffer: *const c_void, nick: *const c_char) -> c_int;
-        }
-        unsafe {
-            let nick = CString::new(nick).unwrap();
-            wdc_nicklist_nick_exists(self.ptr, nick.as_ptr()) != 0
-        }
+    pub fn remove_nick(&self, nick: &Nick) {
+        host().nick_remove(self, nick)
     }
-    */
+}
 
-    pub fn add_nick(&self, nick: &str) {
-        extern "C" {
-            fn wdc_nicklist_add_nick(buffer: *const c_void, nick: *const c_char);
-        }
-        unsafe {
-            let nick = CString::new(nick).unwrap();
-            wdc_nicklist_add_nick(self.ptr, nick.as_ptr());
-        }
+impl Nick {
+    pub fn from_ptr(ptr: *mut c_void) -> Nick {
+        Nick { ptr: ptr }
     }
 
-    pub fn remove_nick(&self, nick: &str) {
-        extern "C" {
-            fn wdc_nicklist_remove_nick(buffer: *const c_void, nick: *const c_char);
-        }
-        unsafe {
-            let nick = CString::new(nick).unwrap();
-            wdc_nicklist_remove_nick(self.ptr, nick.as_ptr());
-        }
+    pub fn ptr(&self) -> *mut c_void {
+        self.ptr
     }
 }
 
-/*
 impl Completion {
-    pub fn add(&mut self, word: &str) {
-        extern "C" {
-            fn wdc_hook_completion_add(gui_completion: *const c_void, word: *const c_char);
-        }
-        unsafe {
-            let word_c = CString::new(word).unwrap();
-            wdc_hook_completion_add(self.ptr, word_c.as_ptr());
-        }
+    pub fn from_ptr(ptr: *mut c_void) -> Completion {
+        Completion { ptr: ptr }
     }
-}
-*/
 
-impl WeechatObject for Buffer {
-    fn from_ptr_hdata(ptr: *mut c_void, hdata: *mut c_void) -> Self {
-        let result = Buffer { ptr: ptr };
-        if hdata != result.hdata() {
-            really_bad("Buffer hdata pointer was different!".into());
-        };
-        result
-    }
-
-    fn ptr(&self) -> *mut c_void {
+    pub fn ptr(&self) -> *mut c_void {
         self.ptr
     }
 
-    fn hdata(&self) -> *mut c_void {
-        hdata_get("buffer")
+    pub fn add(&mut self, word: &str) {
+        host().completion_add(self, word)
     }
 }
 
 pub struct PokeableFd {
     _hook: Hook,
     pipe: [c_int; 2],
-    _callback: Box<Box<FnMut()>>,
 }
 
 pub struct PokeableFdPoker {
@@ -358,43 +147,25 @@ pub struct PokeableFdPoker {
 }
 
 impl PokeableFd {
-    pub fn new<F: FnMut() + 'static>(callback: F) -> PokeableFd {
-        extern "C" {
-            fn wdc_hook_fd(fd: c_int,
-                           pointer: *const c_void,
-                           callback: extern "C" fn(*const c_void, *mut c_void, c_int) -> c_int)
-                           -> *mut c_void;
-        }
-        extern "C" fn callback_fn(pointer: *const c_void, data: *mut c_void, fd: c_int) -> c_int {
-            let _ = data;
-            let mut tmp = 0 as c_char;
-            unsafe { while read(fd, (&mut tmp) as *mut c_char as *mut c_void, 1) == 1 {} }
-            wrap_panic(|| {
-                           let callback = pointer as *mut Box<FnMut()>;
-                           (unsafe { &mut **callback })();
-                       });
-            0
-        }
+    pub fn new<F: FnMut() + 'static>(mut callback: F) -> PokeableFd {
         let mut pipe_fds = [0; 2];
         unsafe {
             pipe(&mut pipe_fds[0] as *mut c_int);
-            // O_NONBLOCK is used in callback_fn while draining the pipe
+            // O_NONBLOCK is used below while draining the pipe
             fcntl(pipe_fds[0],
                   F_SETFL,
                   fcntl(pipe_fds[0], F_GETFL) | O_NONBLOCK);
         }
-        let callback: Box<Box<FnMut()>> = Box::new(Box::new(callback));
-        let hook = unsafe {
-            // haha screw you borrowck
-            let callback = &*callback as *const _ as *const c_void;
-            let hook = wdc_hook_fd(pipe_fds[0], callback, callback_fn);
-            Hook { ptr: hook }
+        let read_fd = pipe_fds[0];
+        let on_poke = move || {
+            let mut tmp = 0 as c_char;
+            unsafe { while read(read_fd, (&mut tmp) as *mut c_char as *mut c_void, 1) == 1 {} }
+            callback();
         };
-        // TODO: Check if hook is nil
+        let hook = host().hook_fd(read_fd, Box::new(on_poke));
         PokeableFd {
-            _hook: hook,
+            _hook: unwrap!(hook),
             pipe: pipe_fds,
-            _callback: callback,
         }
     }
 
@@ -420,7 +191,18 @@ impl Drop for PokeableFd {
     }
 }
 
-fn wrap_panic<R, F: FnOnce() -> R + UnwindSafe>(f: F) -> Option<R> {
+pub struct Timer {
+    _hook: Hook,
+}
+
+impl Timer {
+    pub fn new<F: FnMut() + 'static>(interval_ms: i64, callback: F) -> Option<Timer> {
+        let hook = tryopt!(host().hook_timer(interval_ms, Box::new(callback)));
+        Some(Timer { _hook: hook })
+    }
+}
+
+pub fn wrap_panic<R, F: FnOnce() -> R + UnwindSafe>(f: F) -> Option<R> {
     let result = catch_unwind(f);
     match result {
         Ok(x) => Some(x),
@@ -461,7 +243,6 @@ pub extern "C" fn wdr_end() -> c_int {
 
 pub struct HookCommand {
     _hook: Hook,
-    _callback: Box<Box<FnMut(Buffer, &str)>>,
 }
 
 pub fn hook_command<F: FnMut(Buffer, &str) + 'static>(cmd: &str,
@@ -471,202 +252,227 @@ pub fn hook_command<F: FnMut(Buffer, &str) + 'static>(cmd: &str,
                                                       compl: &str,
                                                       func: F)
                                                       -> Option<HookCommand> {
-    type CB = FnMut(Buffer, &str);
-    extern "C" {
-        fn wdc_hook_command(command: *const c_char,
-                            description: *const c_char,
-                            args: *const c_char,
-                            args_description: *const c_char,
-                            completion: *const c_char,
-                            pointer: *const c_void,
-                            callback: extern "C" fn(*const c_void,
-                                                    *mut c_void,
-                                                    *mut c_void,
-                                                    c_int,
-                                                    *mut *mut c_char,
-                                                    *mut *mut c_char)
-                                                    -> c_int)
-                            -> *mut c_void;
-    }
-    extern "C" fn callback(pointer: *const c_void,
-                           data: *mut c_void,
-                           buffer: *mut c_void,
-                           argc: c_int,
-                           argv: *mut *mut c_char,
-                           argv_eol: *mut *mut c_char)
-                           -> c_int {
-        let _ = data;
-        let _ = argv;
-        wrap_panic(|| {
-            let pointer = pointer as *mut Box<CB>;
-            let buffer = Buffer { ptr: buffer };
-            if argc <= 1 {
-                (unsafe { &mut **pointer })(buffer, "");
-                return;
-            }
-            let args = unsafe { *argv_eol.offset(1) };
-            let args = unsafe { CStr::from_ptr(args).to_str() };
-            let args = match args {
-                Ok(x) => x,
-                Err(_) => return,
-            };
-            (unsafe { &mut **pointer })(buffer, args);
-        });
-        0
-    }
-    unsafe {
-        let cmd = unwrap1!(CString::new(cmd));
-        let desc = unwrap1!(CString::new(desc));
-        let args = unwrap1!(CString::new(args));
-        let argdesc = unwrap1!(CString::new(argdesc));
-        let compl = unwrap1!(CString::new(compl));
-        let custom_callback: Box<Box<CB>> = Box::new(Box::new(func));
-        let pointer = &*custom_callback as *const _ as *const c_void;
-        let hook = wdc_hook_command(cmd.as_ptr(),
-                                    desc.as_ptr(),
-                                    args.as_ptr(),
-                                    argdesc.as_ptr(),
-                                    compl.as_ptr(),
-                                    pointer,
-                                    callback);
-        if hook.is_null() {
-            None
-        } else {
-            Some(HookCommand {
-                     _hook: Hook { ptr: hook },
-                     _callback: custom_callback,
-                 })
+    let hook = tryopt!(host().hook_command(cmd, desc, args, argdesc, compl, Box::new(func)));
+    Some(HookCommand { _hook: hook })
+}
+
+pub struct HookCommandRun {
+    _hook: Hook,
+}
+
+// Called before a command runs, which then runs as usual
+pub fn hook_command_run<F: FnMut(Buffer, &str) + 'static>(command: &str,
+                                                          func: F)
+                                                          -> Option<HookCommandRun> {
+    let hook = tryopt!(host().hook_command_run(command, Box::new(func)));
+    Some(HookCommandRun { _hook: hook })
+}
+
+pub struct HookSignal {
+    _hook: Hook,
+}
+
+// callback gets the signal's data, for buffer_switch the buffer
+pub fn hook_signal<F: FnMut(*mut c_void) + 'static>(signal: &str,
+                                                     callback: F)
+                                                     -> Option<HookSignal> {
+    let hook = tryopt!(host().hook_signal(signal, Box::new(callback)));
+    Some(HookSignal { _hook: hook })
+}
+
+pub struct BarItem {
+    ptr: *mut c_void,
+    _data: Option<Box<Any>>,
+}
+
+impl BarItem {
+    pub fn from_ptr(ptr: *mut c_void, data: Option<Box<Any>>) -> BarItem {
+        BarItem {
+            ptr: ptr,
+            _data: data,
         }
     }
+
+    pub fn ptr(&self) -> *mut c_void {
+        self.ptr
+    }
+
+    pub fn new<F: Fn() -> String + 'static>(name: &str, func: F) -> Option<BarItem> {
+        host().bar_item_new(name, Box::new(func))
+    }
+
+    pub fn update(name: &str) {
+        host().bar_item_update(name)
+    }
 }
 
-pub fn info_get(info_name: &str, arguments: &str) -> Option<String> {
-    extern "C" {
-        fn wdc_info_get(info_name: *const c_char, arguments: *const c_char) -> *const c_char;
-    }
-    unsafe {
-        let info_name = unwrap1!(CString::new(info_name));
-        let arguments = unwrap1!(CString::new(arguments));
-        let result = wdc_info_get(info_name.as_ptr(), arguments.as_ptr());
-        if result.is_null() {
-            None
-        } else {
-            Some(CStr::from_ptr(result).to_string_lossy().into_owned())
-        }
+impl Drop for BarItem {
+    fn drop(&mut self) {
+        host().bar_item_remove(self);
     }
 }
 
-fn hdata_get(name: &str) -> *mut c_void {
-    extern "C" {
-        fn wdc_hdata_get(name: *const c_char) -> *mut c_void;
+pub fn info_get(info_name: &str, arguments: &str) -> Option<String> {
+    host().info_get(info_name, arguments)
+}
+
+pub fn color(name: &str) -> String {
+    host().color(name)
+}
+
+pub fn prefix(name: &str) -> String {
+    host().prefix(name)
+}
+
+// Return values of create/delete option callbacks, from weechat-plugin.h
+pub const CONFIG_OPTION_SET_OK_CHANGED: c_int = 2;
+pub const CONFIG_OPTION_SET_ERROR: c_int = 0;
+
+pub struct ConfigFile {
+    ptr: *mut c_void,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ConfigSection {
+    ptr: *mut c_void,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ConfigOption {
+    ptr: *mut c_void,
+}
+
+// (section, option name, value) -> CONFIG_OPTION_SET_*
+pub type CreateOptionFn = fn(ConfigSection, &str, &str) -> c_int;
+// /unset on an option of a dynamic section, which has to free it
+pub type DeleteOptionFn = fn(ConfigSection, ConfigOption);
+
+impl ConfigFile {
+    pub fn from_ptr(ptr: *mut c_void) -> ConfigFile {
+        ConfigFile { ptr: ptr }
     }
-    unsafe {
-        let name_c = unwrap1!(CString::new(name));
-        let data = wdc_hdata_get(name_c.as_ptr());
-        if data.is_null() {
-            really_bad(format!("hdata name {} was invalid", name));
-        }
-        data
+
+    pub fn ptr(&self) -> *mut c_void {
+        self.ptr
     }
-}
 
-fn hdata_pointer(hdata: *mut c_void, obj: *mut c_void, name: &str) -> Option<*mut c_void> {
-    extern "C" {
-        fn wdc_hdata_pointer(hdata: *mut c_void,
-                             obj: *mut c_void,
-                             name: *const c_char)
-                             -> *mut c_void;
+    pub fn new(name: &str, on_reload: fn(&ConfigFile) -> c_int) -> Option<ConfigFile> {
+        host().config_new(name, on_reload)
     }
-    unsafe {
-        let name = unwrap1!(CString::new(name));
-        let result = wdc_hdata_pointer(hdata, obj, name.as_ptr());
-        if result.is_null() { None } else { Some(result) }
+
+    pub fn new_section(&self,
+                       name: &str,
+                       dynamic: Option<(CreateOptionFn, DeleteOptionFn)>)
+                       -> Option<ConfigSection> {
+        host().config_new_section(self, name, dynamic)
     }
-}
 
-fn hdata_get_var_hdata(hdata: *mut c_void, name: &str) -> String {
-    extern "C" {
-        fn wdc_hdata_get_var_hdata(hdata: *mut c_void, name: *const c_char) -> *const c_char;
+    // (file, section, name, type, description, string_values, min, max, default, value)
+    pub fn new_option(&self,
+                      section: ConfigSection,
+                      name: &str,
+                      kind: &str,
+                      description: &str,
+                      string_values: &str,
+                      min: i32,
+                      max: i32,
+                      default: &str,
+                      value: &str,
+                      on_change: Option<fn(ConfigOption)>)
+                      -> Option<ConfigOption> {
+        host().config_new_option(self,
+                                 section,
+                                 name,
+                                 kind,
+                                 description,
+                                 string_values,
+                                 min,
+                                 max,
+                                 default,
+                                 value,
+                                 on_change)
     }
-    let name = strip_indexer_field(name);
-    unsafe {
-        let name_c = unwrap1!(CString::new(name));
-        let result = wdc_hdata_get_var_hdata(hdata, name_c.as_ptr());
-        if result.is_null() {
-            really_bad(format!("hdata field {} hdata was invalid", name));
-        }
-        CStr::from_ptr(result).to_string_lossy().into_owned()
+
+    pub fn search_option(&self, section: ConfigSection, name: &str) -> Option<ConfigOption> {
+        host().config_search_option(self, section, name)
     }
-}
 
-fn hdata_get_var_type_string(hdata: *mut c_void, name: &str) -> String {
-    extern "C" {
-        fn wdc_hdata_get_var_type_string(hdata: *mut c_void, name: *const c_char) -> *const c_char;
+    pub fn read(&self) -> c_int {
+        host().config_read(self)
     }
-    let name = strip_indexer_field(name);
-    unsafe {
-        let name_c = unwrap1!(CString::new(name));
-        let result = wdc_hdata_get_var_type_string(hdata, name_c.as_ptr());
-        if result.is_null() {
-            really_bad(format!("hdata field {} type was invalid", name));
-        }
-        CStr::from_ptr(result).to_string_lossy().into_owned()
+
+    pub fn reload(&self) -> c_int {
+        host().config_reload(self)
     }
-}
 
-fn hdata_integer(hdata: *mut c_void, data: *mut c_void, name: &str) -> Option<c_int> {
-    extern "C" {
-        fn wdc_hdata_integer(hdata: *mut c_void, data: *mut c_void, name: *const c_char) -> c_int;
+    pub fn write(&self) -> c_int {
+        host().config_write(self)
     }
-    unsafe {
-        let name = unwrap1!(CString::new(name));
-        Some(wdc_hdata_integer(hdata, data, name.as_ptr()))
+}
+
+impl Drop for ConfigFile {
+    fn drop(&mut self) {
+        host().config_free(self);
     }
 }
 
-fn hdata_string(hdata: *mut c_void, data: *mut c_void, name: &str) -> Option<String> {
-    extern "C" {
-        fn wdc_hdata_string(hdata: *mut c_void,
-                            data: *mut c_void,
-                            name: *const c_char)
-                            -> *const c_char;
+impl ConfigSection {
+    pub fn from_ptr(ptr: *mut c_void) -> ConfigSection {
+        ConfigSection { ptr: ptr }
     }
-    unsafe {
-        let name = unwrap1!(CString::new(name));
-        let result = wdc_hdata_string(hdata, data, name.as_ptr());
-        if result.is_null() {
-            None
-        } else {
-            Some(CStr::from_ptr(result).to_string_lossy().into_owned())
-        }
+
+    pub fn ptr(&self) -> *mut c_void {
+        self.ptr
+    }
+
+    pub fn free_options(&self) {
+        host().config_section_free_options(*self)
     }
 }
 
-pub fn get_option(name: &str) -> Option<String> {
-    extern "C" {
-        fn wdc_config_get_plugin(name: *const c_char) -> *const c_char;
+impl ConfigOption {
+    pub fn from_ptr(ptr: *mut c_void) -> ConfigOption {
+        ConfigOption { ptr: ptr }
     }
-    unsafe {
-        let name_c = unwrap1!(CString::new(name));
-        let result = wdc_config_get_plugin(name_c.as_ptr());
-        if result.is_null() {
-            None
-        } else {
-            Some(unwrap1!(CStr::from_ptr(result).to_str()).into())
-        }
+
+    pub fn ptr(&self) -> *mut c_void {
+        self.ptr
     }
-}
 
-pub fn set_option(name: &str, value: &str) -> String {
-    extern "C" {
-        fn wdc_config_set_plugin(name: *const c_char, value: *const c_char) -> c_int;
+    pub fn name(&self) -> String {
+        host().option_name(*self)
     }
-    let before = get_option(name);
-    let result = unsafe {
-        let name_c = unwrap1!(CString::new(name));
-        let value_c = unwrap1!(CString::new(value));
-        wdc_config_set_plugin(name_c.as_ptr(), value_c.as_ptr())
-    };
+
+    pub fn string(&self) -> String {
+        host().option_string(*self)
+    }
+
+    pub fn integer(&self) -> i32 {
+        host().option_integer(*self)
+    }
+
+    pub fn boolean(&self) -> bool {
+        host().option_boolean(*self)
+    }
+
+    // The color code, ready to be printed
+    pub fn color(&self) -> String {
+        host().option_color(*self)
+    }
+
+    // Returns a message for the user describing what happened
+    pub fn set(&self, value: &str) -> String {
+        let before = self.string();
+        let result = host().option_set(*self, value);
+        set_result_message(&self.name(), value, result, Some(before))
+    }
+
+    pub fn free(self) {
+        host().option_free(self)
+    }
+}
+
+fn set_result_message(name: &str, value: &str, result: c_int, before: Option<String>) -> String {
     match (result, before) {
         (0, Some(before)) => {
             format!("option {} successfully changed from {} to {}",
@@ -687,67 +493,28 @@ pub fn set_option(name: &str, value: &str) -> String {
     }
 }
 
+// All plugins.var.weecord.* options as (name without prefix, value).
+// Only used to migrate to weecord.conf.
+pub fn plugin_options() -> Vec<(String, String)> {
+    host().plugin_options()
+}
+
+pub fn unset_plugin_option(name: &str) {
+    host().unset_plugin_option(name)
+}
+
 pub fn remove_color(string: &str) -> String {
-    extern "C" {
-        fn wdc_string_remove_color(string: *const c_char) -> *mut c_char;
-    }
-    unsafe {
-        let string_c = unwrap1!(CString::new(string));
-        let result_c = wdc_string_remove_color(string_c.as_ptr());
-        let result = CStr::from_ptr(result_c).to_str().unwrap().into();
-        free(result_c as *mut c_void);
-        result
-    }
+    host().remove_color(string)
+}
+
+pub struct HookCompletion {
+    _hook: Hook,
 }
 
-/*
 pub fn hook_completion<F: Fn(Buffer, Completion) + 'static>(name: &str,
                                                             description: &str,
                                                             callback: F)
-                                                            -> Option<Hook> {
-    type CB = Fn(Buffer, Completion);
-    extern "C" {
-        fn wdc_hook_completion(completion_item: *const c_char,
-                               description: *const c_char,
-                               callback_pointer: *const c_void,
-                               callback: extern "C" fn(*const c_void,
-                                                       *mut c_void,
-                                                       *const c_char,
-                                                       *mut c_void,
-                                                       *mut c_void)
-                                                       -> c_int)
-                               -> *mut c_void;
-    }
-    extern "C" fn callback_func(pointer: *const c_void,
-                                data: *mut c_void,
-                                completion_item: *const c_char,
-                                buffer: *mut c_void,
-                                completion: *mut c_void)
-                                -> c_int {
-        let _ = data;
-        let _ = completion_item;
-        wrap_panic(|| {
-                       let buffer = Buffer { ptr: buffer };
-                       let completion = Completion { ptr: completion };
-                       let pointer = pointer as *const Box<CB>;
-                       (unsafe { &**pointer })(buffer, completion);
-                   });
-        0
-    }
-    let callback: Box<Box<CB>> = Box::new(Box::new(callback));
-    unsafe {
-        let name_c = unwrap1!(CString::new(name));
-        let description_c = unwrap1!(CString::new(description));
-        let callback = Box::into_raw(callback) as *const _ as *const c_void; // TODO: Memory leak
-        let result = wdc_hook_completion(name_c.as_ptr(),
-                                         description_c.as_ptr(),
-                                         callback,
-                                         callback_func);
-        if result.is_null() {
-            None
-        } else {
-            Some(Hook { ptr: result })
-        }
-    }
+                                                            -> Option<HookCompletion> {
+    let hook = tryopt!(host().hook_completion(name, description, Box::new(callback)));
+    Some(HookCompletion { _hook: hook })
 }
-*/
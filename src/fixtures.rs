@@ -0,0 +1,190 @@
+use discord::{Discord, State};
+use discord::model::{ChannelId, Event, GatewayEvent, Message, ReadyEvent};
+use serde_json::{self, Value};
+
+use connection::ChannelData;
+use ffi::Buffer;
+
+// A small Discord account for tests: us, alice, and one server with a text
+// channel both of us are in.
+pub const ME: u64 = 1;
+pub const ALICE: u64 = 2;
+pub const SERVER: u64 = 10;
+pub const GENERAL: u64 = 11;
+pub const ROLE: u64 = 12;
+
+pub fn user(id: u64, name: &str) -> Value {
+    json!({
+        "id": id.to_string(),
+        "username": name,
+        "discriminator": format!("{:04}", id),
+        "avatar": null,
+        "bot": false,
+    })
+}
+
+fn member(id: u64, nick: Option<&str>) -> Value {
+    json!({
+        "user": user(id, name(id)),
+        "nick": nick,
+        "roles": [],
+        "joined_at": "2017-05-01T12:34:56.789000+00:00",
+        "deaf": false,
+        "mute": false,
+    })
+}
+
+pub fn server() -> Value {
+    json!({
+        "id": SERVER.to_string(),
+        "name": "Test",
+        "icon": null,
+        "splash": null,
+        "owner_id": ALICE.to_string(),
+        "region": "us-east",
+        "afk_timeout": 300,
+        "afk_channel_id": null,
+        "embed_enabled": false,
+        "embed_channel_id": null,
+        "verification_level": 0,
+        "default_message_notifications": 0,
+        "mfa_level": 0,
+        "large": false,
+        "joined_at": "2017-05-01T12:34:56.789000+00:00",
+        "member_count": 2,
+        "features": [],
+        "emojis": [],
+        "voice_states": [],
+        "presences": [],
+        "roles": [{
+            "id": ROLE.to_string(),
+            "name": "mods",
+            "color": 0xff0000,
+            "hoist": false,
+            "managed": false,
+            "mentionable": true,
+            "position": 1,
+            "permissions": 0,
+        }],
+        "members": [member(ME, None), member(ALICE, None)],
+        "channels": [{
+            "id": GENERAL.to_string(),
+            "guild_id": SERVER.to_string(),
+            "name": "general",
+            "type": 0,
+            "position": 0,
+            "permission_overwrites": [],
+            "topic": null,
+            "last_message_id": null,
+        }],
+    })
+}
+
+// What the gateway sends
+pub fn ready_json() -> Value {
+    json!({
+        "v": 6,
+        "user": {
+            "id": ME.to_string(),
+            "username": "me",
+            "discriminator": "0001",
+            "avatar": null,
+            "email": null,
+            "verified": true,
+            "bot": false,
+            "mfa_enabled": false,
+        },
+        "session_id": "session",
+        "user_settings": null,
+        "read_state": null,
+        "private_channels": [],
+        "presences": [],
+        "relationships": [],
+        "guilds": [server()],
+        "user_guild_settings": null,
+        "notes": null,
+        "friend_suggestion_count": null,
+        "shard": null,
+        "_trace": [],
+    })
+}
+
+pub fn ready() -> ReadyEvent {
+    unwrap1!(serde_json::from_value(ready_json()))
+}
+
+pub fn state() -> State {
+    State::new(ready())
+}
+
+pub fn discord() -> Discord {
+    unwrap1!(Discord::from_user_token("test"))
+}
+
+fn name(id: u64) -> &'static str {
+    match id {
+        ME => "me",
+        ALICE => "alice",
+        _ => "someone",
+    }
+}
+
+// A message in #general
+pub fn message_json(id: u64, author: u64, content: &str, mentions: &[u64]) -> Value {
+    json!({
+        "id": id.to_string(),
+        "channel_id": GENERAL.to_string(),
+        "author": user(author, name(author)),
+        "content": content,
+        "timestamp": "2017-05-01T12:34:56.789000+00:00",
+        "edited_timestamp": null,
+        "tts": false,
+        "pinned": false,
+        "type": 0,
+        "nonce": null,
+        "mention_everyone": false,
+        "mentions": mentions.iter().map(|&id| user(id, name(id))).collect::<Vec<_>>(),
+        "mention_roles": [],
+        "reactions": [],
+        "attachments": [],
+        "embeds": [],
+    })
+}
+
+pub fn message(id: u64, author: u64, content: &str, mentions: &[u64]) -> Message {
+    unwrap1!(serde_json::from_value(message_json(id, author, content, mentions)))
+}
+
+// A gateway dispatch, decoded like discord-rs does
+pub fn event(kind: &str, data: Value) -> Event {
+    let payload = json!({ "op": 0, "s": 1, "t": kind, "d": data });
+    match unwrap1!(GatewayEvent::decode(payload)) {
+        GatewayEvent::Dispatch(_, event) => event,
+        _ => panic!("not a dispatch: {}", kind),
+    }
+}
+
+// Alice's nick or roles changed
+pub fn member_update(nick: Option<&str>, roles: &[u64]) -> Event {
+    event("GUILD_MEMBER_UPDATE",
+          json!({
+              "guild_id": SERVER.to_string(),
+              "roles": roles.iter().map(|x| x.to_string()).collect::<Vec<_>>(),
+              "user": user(ALICE, "alice"),
+              "nick": nick,
+          }))
+}
+
+pub fn member_remove(id: u64) -> Event {
+    event("GUILD_MEMBER_REMOVE",
+          json!({
+              "guild_id": SERVER.to_string(),
+              "user": user(id, name(id)),
+          }))
+}
+
+// Opens #general
+pub fn general_buffer(state: &State, discord: &Discord) -> Buffer {
+    let channel = unwrap!(state.find_channel(ChannelId(GENERAL)));
+    unwrap!(ChannelData::from_channel(state, discord, channel, true)).buffer
+}
@@ -0,0 +1,155 @@
+use discord::State;
+use discord::model::{Relationship, RelationshipType, User};
+use hyper::method::Method;
+use serde_json::{Map, Value};
+
+use ffi::Buffer;
+use presence::status_name;
+use rest;
+use util::{split_arg, tag};
+
+const BUFFER_NAME: &'static str = "friends";
+
+const USAGE: &'static str = "Usage: /discord friends [accept|decline|remove <user>] \
+                             [add <name#1234>]";
+
+
+fn format_friend(state: &State, relationship: &Relationship) -> String {
+    match state
+              .presences()
+              .iter()
+              .find(|p| p.user_id == relationship.id) {
+        Some(presence) => {
+            let game = presence
+                .game
+                .as_ref()
+                .map(|g| format!(" - playing {}", g.name))
+                .unwrap_or_default();
+            format!("  {} ({}){}",
+                    tag(&relationship.user),
+                    status_name(presence.status),
+                    game)
+        }
+        None => format!("  {} (offline)", tag(&relationship.user)),
+    }
+}
+
+fn render(state: &State, buffer: &Buffer) {
+    buffer.clear();
+    let sections = [(RelationshipType::Friends, "Friends"),
+                    (RelationshipType::IncomingRequest, "Incoming requests (accept/decline)"),
+                    (RelationshipType::OutgoingRequest, "Outgoing requests (remove to cancel)"),
+                    (RelationshipType::Blocked, "Blocked")];
+    for &(ref kind, title) in &sections {
+        let mut relationships = state
+            .relationships()
+            .iter()
+            .filter(|r| r.kind == *kind)
+            .collect::<Vec<_>>();
+        relationships.sort_by_key(|r| r.user.name.to_lowercase());
+        buffer.print(&format!("{} ({})", title, relationships.len()));
+        for relationship in relationships {
+            if *kind == RelationshipType::Friends {
+                buffer.print(&format_friend(state, relationship));
+            } else {
+                buffer.print(&format!("  {}", tag(&relationship.user)));
+            }
+        }
+    }
+}
+
+fn input(buffer: Buffer, input: &str) {
+    ::connection::friends_command(&buffer, input);
+}
+
+pub fn open(state: &State) {
+    let buffer = match Buffer::search(BUFFER_NAME) {
+        Some(buffer) => buffer,
+        None => {
+            let buffer = unwrap!(Buffer::new(BUFFER_NAME, input));
+            buffer.set("short_name", "friends");
+            buffer.set("title",
+                       "Discord friends - type accept, decline, remove <user> or add \
+                        <name#1234>");
+            buffer
+        }
+    };
+    render(state, &buffer);
+    buffer.set("display", "1");
+}
+
+// Relationships or a friend's presence changed
+pub fn refresh(state: &State) {
+    if let Some(buffer) = Buffer::search(BUFFER_NAME) {
+        render(state, &buffer);
+    }
+}
+
+fn find<'a>(state: &'a State, name: &str) -> Option<&'a Relationship> {
+    let name = name.trim_left_matches('@');
+    state
+        .relationships()
+        .iter()
+        .find(|r| tag(&r.user) == name || r.user.name == name)
+}
+
+fn add_friend(token: &str, name: &str) -> Result<(), String> {
+    let (username, discriminator) = match name.rfind('#') {
+        Some(idx) => (&name[..idx], &name[idx + 1..]),
+        None => return Err("Friend requests need the full name#1234".into()),
+    };
+    let discriminator = discriminator
+        .parse::<u64>()
+        .map_err(|_| format!("Invalid discriminator: {}", discriminator))?;
+    let mut body = Map::new();
+    body.insert("username".into(), Value::String(username.into()));
+    body.insert("discriminator".into(), Value::from(discriminator));
+    rest::request(token,
+                  Method::Post,
+                  "/users/@me/relationships",
+                  Some(Value::Object(body)))
+        .map(|_| ())
+}
+
+pub fn friends_command(state: &State, token: &str, buffer: &Buffer, args: &str) {
+    let (action, name) = split_arg(args);
+    if action.is_empty() {
+        open(state);
+        return;
+    }
+    if name.is_empty() {
+        buffer.print(USAGE);
+        return;
+    }
+    // the gateway sends RelationshipAdd/Remove on success, which redraws
+    let result = match &*action {
+        "add" => add_friend(token, name),
+        "accept" | "decline" | "remove" => {
+            let relationship = match find(state, name) {
+                Some(relationship) => relationship,
+                None => {
+                    buffer.print(&format!("No friend or request from {}", name));
+                    return;
+                }
+            };
+            let path = format!("/users/@me/relationships/{}", relationship.id.0);
+            if action == "accept" {
+                if relationship.kind != RelationshipType::IncomingRequest {
+                    buffer.print(&format!("{} didn't send a friend request", name));
+                    return;
+                }
+                rest::request(token, Method::Put, &path, Some(Value::Object(Map::new())))
+                    .map(|_| ())
+            } else {
+                rest::request(token, Method::Delete, &path, None).map(|_| ())
+            }
+        }
+        _ => {
+            buffer.print(USAGE);
+            return;
+        }
+    };
+    if let Err(err) = result {
+        buffer.print(&format!("Unable to {} {}: {}", action, name, err));
+    }
+}
@@ -0,0 +1,118 @@
+use discord::{ChannelRef, State};
+use discord::model::{Group, User, UserId};
+use hyper::method::Method;
+use serde_json::{Map, Value};
+
+use connection::ChannelData;
+use ffi::Buffer;
+use message;
+use rest;
+use types::*;
+use util::split_arg;
+
+const USAGE: &'static str = "Usage: /discord group create <user> [<user>...], /discord group \
+                             add|remove <user>, /discord group rename <name>, /discord group \
+                             leave";
+
+fn find_user(state: &State, name: &str) -> Result<UserId, String> {
+    let name = name.trim_left_matches('@');
+    message::all_names_everywhere(state,
+                                  |found, user| if found == name { Some(user.id) } else { None })
+        .ok_or_else(|| format!("User not found: {}", name))
+}
+
+fn find_recipient<'a>(group: &'a Group, name: &str) -> Result<&'a User, String> {
+    let name = name.trim_left_matches('@');
+    group
+        .recipients
+        .iter()
+        .find(|u| u.name(&NameFormat::none()) == name || u.name == name)
+        .ok_or_else(|| format!("{} isn't in this group", name))
+}
+
+fn create(state: &State, token: &str, args: &str) -> Result<(), String> {
+    let mut recipients = Vec::new();
+    let mut rest = args;
+    while !rest.is_empty() {
+        let (name, next) = split_arg(rest);
+        recipients.push(Value::String(format!("{}", find_user(state, &name)?.0)));
+        rest = next;
+    }
+    if recipients.is_empty() {
+        return Err(USAGE.into());
+    }
+    let mut body = Map::new();
+    body.insert("recipients".into(), Value::Array(recipients));
+    // the buffer opens on the ChannelCreate that follows
+    rest::request(token,
+                  Method::Post,
+                  "/users/@me/channels",
+                  Some(Value::Object(body)))
+        .map(|_| ())
+}
+
+fn manage(token: &str,
+          state: &State,
+          group: &Group,
+          action: &str,
+          args: &str)
+          -> Result<(), String> {
+    let channel = group.channel_id.0;
+    let result = match action {
+        "add" => {
+            let user = find_user(state, args)?;
+            rest::request(token,
+                          Method::Put,
+                          &format!("/channels/{}/recipients/{}", channel, user.0),
+                          None)
+        }
+        "remove" => {
+            let user = find_recipient(group, args)?;
+            rest::request(token,
+                          Method::Delete,
+                          &format!("/channels/{}/recipients/{}", channel, user.id.0),
+                          None)
+        }
+        "rename" => {
+            let mut body = Map::new();
+            body.insert("name".into(),
+                        if args.is_empty() {
+                            Value::Null
+                        } else {
+                            Value::String(args.into())
+                        });
+            rest::request(token,
+                          Method::Patch,
+                          &format!("/channels/{}", channel),
+                          Some(Value::Object(body)))
+        }
+        "leave" => {
+            rest::request(token, Method::Delete, &format!("/channels/{}", channel), None)
+        }
+        _ => return Err(USAGE.into()),
+    };
+    result.map(|_| ())
+}
+
+pub fn group_command(state: &State, token: &str, buffer: &Buffer, args: &str) {
+    let (action, args) = split_arg(args);
+    let result = if action.is_empty() {
+        Err(USAGE.into())
+    } else if action == "create" {
+        create(state, token, args)
+    } else {
+        match ChannelData::from_buffer_impl(state, buffer) {
+            Some(ChannelRef::Group(group)) => {
+                if args.is_empty() && (action == "add" || action == "remove") {
+                    Err(USAGE.into())
+                } else {
+                    manage(token, state, group, &action, args)
+                }
+            }
+            _ => Err("Must be run from a group buffer (except create)".into()),
+        }
+    };
+    if let Err(err) = result {
+        buffer.print(&err);
+    }
+}
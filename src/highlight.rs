@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use discord::ChannelRef;
+use discord::model::{LiveServer, Role, RoleId, ServerId};
+use regex::Regex;
+
+use config;
+use ffi::*;
+use notify::Mention;
+
+// highlight_regex compiled once per server, None when it's empty or
+// invalid. Kept up to date by check_regex.
+static mut REGEXES: *mut HashMap<ServerId, Option<Regex>> = 0 as *mut _;
+
+fn regexes() -> &'static mut HashMap<ServerId, Option<Regex>> {
+    unsafe {
+        if REGEXES.is_null() {
+            REGEXES = Box::into_raw(Box::new(HashMap::new()));
+        }
+        &mut *REGEXES
+    }
+}
+
+fn compile(pattern: &str) -> Option<Regex> {
+    if pattern.is_empty() {
+        None
+    } else {
+        Regex::new(pattern).ok()
+    }
+}
+
+fn server_regex(server_id: ServerId) -> Option<&'static Regex> {
+    regexes()
+        .entry(server_id)
+        .or_insert_with(|| config::highlight_regex(server_id).and_then(|x| compile(&x)))
+        .as_ref()
+}
+
+pub fn end() {
+    unsafe {
+        if !REGEXES.is_null() {
+            let _ = Box::from_raw(REGEXES);
+            REGEXES = ::std::ptr::null_mut();
+        }
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|x| x.trim().to_lowercase())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Case insensitive, and only whole words: "cat" doesn't match "concatenate"
+fn contains_word(content: &str, word: &str) -> bool {
+    let mut rest = content;
+    while let Some(start) = rest.find(word) {
+        let end = start + word.len();
+        let before_ok = !rest[..start].chars().next_back().map_or(false, is_word_char);
+        let after_ok = !rest[end..].chars().next().map_or(false, is_word_char);
+        if before_ok && after_ok {
+            return true;
+        }
+        rest = &rest[end..];
+    }
+    false
+}
+
+fn matches_rules(server: &LiveServer, content: &str) -> bool {
+    if let Some(words) = config::highlight_words(server.id) {
+        let content = content.to_lowercase();
+        if split_list(&words)
+               .iter()
+               .any(|word| contains_word(&content, word)) {
+            return true;
+        }
+    }
+    // invalid patterns are reported when the option is set
+    server_regex(server.id).map_or(false, |regex| regex.is_match(content))
+}
+
+fn is_role_suppressed(server: &LiveServer, role: &Role) -> bool {
+    let roles = match config::no_highlight_roles(server.id) {
+        Some(roles) => roles,
+        None => return false,
+    };
+    let id = format!("{}", role.id.0);
+    let name = role.name.to_lowercase();
+    split_list(&roles)
+        .iter()
+        .any(|x| *x == id || *x == name || x.trim_left_matches('@') == name)
+}
+
+pub fn role_mention(server: &LiveServer, role_id: RoleId) -> Mention {
+    match server.roles.iter().find(|r| r.id == role_id) {
+        Some(role) if is_role_suppressed(server, role) => Mention::None,
+        _ => Mention::Direct,
+    }
+}
+
+// @everyone and @here, Discord only tells us that one of them was used
+pub fn everyone_mention(channel: &ChannelRef, content: Option<&str>) -> Mention {
+    let server = match *channel {
+        ChannelRef::Public(server, _) => server,
+        _ => return Mention::Everyone,
+    };
+    let max_members = config::everyone_max_members();
+    if max_members > 0 && server.member_count > max_members {
+        return Mention::None;
+    }
+    let is_here = content.map_or(false, |x| x.contains("@here") && !x.contains("@everyone"));
+    if is_here && config::no_highlight_here(server.id) {
+        return Mention::None;
+    }
+    Mention::Everyone
+}
+
+// Highlight words and regexes, for messages that don't mention us otherwise
+pub fn content_mention(channel: &ChannelRef, content: Option<&str>) -> Mention {
+    match (*channel, content) {
+        (ChannelRef::Public(server, _), Some(content)) if matches_rules(server, content) => {
+            Mention::Direct
+        }
+        _ => Mention::None,
+    }
+}
+
+// A server's highlight_regex changed. Options are keyed by name, so every
+// regex gets compiled again the next time it's needed.
+pub fn check_regex(option: ConfigOption) {
+    if let Err(err) = Regex::new(&option.string()) {
+        MAIN_BUFFER.print(&format!("{}: invalid regex in {}: {}",
+                                   ::weechat::COMMAND,
+                                   option.name(),
+                                   err));
+    }
+    regexes().clear();
+}
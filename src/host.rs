@@ -0,0 +1,125 @@
+use libc::{c_int, c_void};
+
+use ffi::{BarItem, Buffer, Completion, ConfigFile, ConfigOption, ConfigSection, CreateOptionFn,
+          DeleteOptionFn, Hook, Nick};
+
+// A printed line, as far as we care
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub prefix: String,
+    pub message: String,
+    pub tags: Vec<String>,
+}
+
+impl Line {
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+// Everything weecord uses from WeeChat. The plugin goes through the C shims
+// (WeechatHost), tests through an in-memory MockHost. The wrappers in ffi.rs
+// are what the rest of the code uses.
+pub trait Host {
+    fn buffer_new(&self, name: &str, on_input: fn(Buffer, &str)) -> Option<Buffer>;
+    fn buffer_search(&self, name: &str) -> Option<Buffer>;
+    fn buffer_close(&self, buffer: &Buffer);
+    fn buffer_clear(&self, buffer: &Buffer);
+    fn buffer_set(&self, buffer: &Buffer, property: &str, value: &str);
+    fn buffer_get(&self, buffer: &Buffer, property: &str) -> Option<String>;
+    // Oldest first
+    fn buffer_lines(&self, buffer: &Buffer) -> Vec<Line>;
+    fn print(&self, buffer: &Buffer, message: &str);
+    fn print_tags(&self, buffer: &Buffer, tags: &str, message: &str);
+    // For lines from the past, date is a unix time
+    fn print_date_tags(&self, buffer: &Buffer, date: i64, tags: &str, message: &str);
+
+    // An empty color picks WeeChat's color for the nick. Names needn't be
+    // unique, so nicks are removed by what nick_add returned.
+    fn nick_add(&self, buffer: &Buffer, nick: &str, color: &str) -> Option<Nick>;
+    fn nick_remove(&self, buffer: &Buffer, nick: &Nick);
+    fn nick_remove_all(&self, buffer: &Buffer);
+
+    // The callbacks live as long as the returned Hook (or BarItem)
+    fn hook_command(&self,
+                    command: &str,
+                    description: &str,
+                    args: &str,
+                    args_description: &str,
+                    completion: &str,
+                    callback: Box<FnMut(Buffer, &str)>)
+                    -> Option<Hook>;
+    fn hook_command_run(&self, command: &str, callback: Box<FnMut(Buffer, &str)>)
+                        -> Option<Hook>;
+    fn hook_completion(&self,
+                       name: &str,
+                       description: &str,
+                       callback: Box<Fn(Buffer, Completion)>)
+                       -> Option<Hook>;
+    fn completion_add(&self, completion: &Completion, word: &str);
+    fn hook_fd(&self, fd: c_int, callback: Box<FnMut()>) -> Option<Hook>;
+    fn hook_timer(&self, interval_ms: i64, callback: Box<FnMut()>) -> Option<Hook>;
+    // Only for signals whose data is a pointer, such as buffer_switch
+    fn hook_signal(&self, signal: &str, callback: Box<FnMut(*mut c_void)>) -> Option<Hook>;
+    fn unhook(&self, hook: &Hook);
+    fn bar_item_new(&self, name: &str, callback: Box<Fn() -> String>) -> Option<BarItem>;
+    fn bar_item_update(&self, name: &str);
+    fn bar_item_remove(&self, item: &BarItem);
+
+    fn config_new(&self, name: &str, on_reload: fn(&ConfigFile) -> c_int) -> Option<ConfigFile>;
+    fn config_new_section(&self,
+                          file: &ConfigFile,
+                          name: &str,
+                          dynamic: Option<(CreateOptionFn, DeleteOptionFn)>)
+                          -> Option<ConfigSection>;
+    fn config_new_option(&self,
+                         file: &ConfigFile,
+                         section: ConfigSection,
+                         name: &str,
+                         kind: &str,
+                         description: &str,
+                         string_values: &str,
+                         min: i32,
+                         max: i32,
+                         default: &str,
+                         value: &str,
+                         on_change: Option<fn(ConfigOption)>)
+                         -> Option<ConfigOption>;
+    fn config_search_option(&self,
+                            file: &ConfigFile,
+                            section: ConfigSection,
+                            name: &str)
+                            -> Option<ConfigOption>;
+    fn config_read(&self, file: &ConfigFile) -> c_int;
+    fn config_reload(&self, file: &ConfigFile) -> c_int;
+    fn config_write(&self, file: &ConfigFile) -> c_int;
+    fn config_free(&self, file: &ConfigFile);
+    fn config_section_free_options(&self, section: ConfigSection);
+    fn option_name(&self, option: ConfigOption) -> String;
+    fn option_string(&self, option: ConfigOption) -> String;
+    fn option_integer(&self, option: ConfigOption) -> i32;
+    fn option_boolean(&self, option: ConfigOption) -> bool;
+    fn option_color(&self, option: ConfigOption) -> String;
+    // 0 changed, 1 same value, 2 not found, 3 error
+    fn option_set(&self, option: ConfigOption, value: &str) -> c_int;
+    fn option_free(&self, option: ConfigOption);
+    fn plugin_options(&self) -> Vec<(String, String)>;
+    fn unset_plugin_option(&self, name: &str);
+
+    fn info_get(&self, info_name: &str, arguments: &str) -> Option<String>;
+    fn color(&self, name: &str) -> String;
+    // weechat_prefix: "error", "network", "action", "join" or "quit"
+    fn prefix(&self, name: &str) -> String;
+    fn remove_color(&self, string: &str) -> String;
+}
+
+#[cfg(not(test))]
+pub fn host() -> &'static Host {
+    static HOST: ::weechat_host::WeechatHost = ::weechat_host::WeechatHost;
+    &HOST
+}
+
+#[cfg(test)]
+pub fn host() -> &'static Host {
+    ::mock_host::get()
+}
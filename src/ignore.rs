@@ -0,0 +1,129 @@
+use discord::{ChannelRef, State};
+use discord::model::{RelationshipType, ServerId, User, UserId};
+
+use config;
+use connection::ChannelData;
+use ffi::Buffer;
+use message;
+use types::*;
+use util::{split_arg, tag};
+
+const USAGE: &'static str = "Usage: /discord ignore [-server] <user>, /discord unignore \
+                             [-server] <user>, /discord ignore to list";
+
+// Blocked in Discord, or on one of our ignore lists
+pub fn is_ignored(state: &State, channel: &ChannelRef, user_id: UserId) -> bool {
+    if state
+           .relationships()
+           .iter()
+           .any(|r| r.id == user_id && r.kind == RelationshipType::Blocked) {
+        return true;
+    }
+    if config::ignored(None).contains(&user_id) {
+        return true;
+    }
+    match *channel {
+        ChannelRef::Public(server, _) => config::ignored(Some(server.id)).contains(&user_id),
+        _ => false,
+    }
+}
+
+fn find_user(state: &State, name: &str) -> Option<(UserId, String)> {
+    let name = name.trim_left_matches('@');
+    message::all_names_everywhere(state, |found, user| if found == name {
+        Some((user.id, tag(user)))
+    } else {
+        None
+    })
+}
+
+fn describe(state: &State, user_id: UserId) -> String {
+    let user = message::all_names_everywhere(state, |_, user: &User| if user.id == user_id {
+        Some(tag(user))
+    } else {
+        None
+    });
+    user.unwrap_or_else(|| format!("{}", user_id.0))
+}
+
+fn list(state: &State, buffer: &Buffer) {
+    let mut any = false;
+    for relationship in state.relationships() {
+        if relationship.kind == RelationshipType::Blocked {
+            buffer.print(&format!("{} (blocked in Discord)", tag(&relationship.user)));
+            any = true;
+        }
+    }
+    for user_id in config::ignored(None) {
+        buffer.print(&describe(state, user_id));
+        any = true;
+    }
+    for server in state.servers() {
+        for user_id in config::ignored(Some(server.id)) {
+            buffer.print(&format!("{} (in {})",
+                                  describe(state, user_id),
+                                  server.name(&NameFormat::none())));
+            any = true;
+        }
+    }
+    if !any {
+        buffer.print("Nobody is ignored");
+    }
+}
+
+pub fn ignore_command(state: &State, buffer: &Buffer, args: &str, ignore: bool) {
+    if ignore && args.trim().is_empty() {
+        list(state, buffer);
+        return;
+    }
+    let (in_server, args) = if args.starts_with("-server") {
+        (true, &args["-server".len()..])
+    } else {
+        (false, args)
+    };
+    let (name, rest) = split_arg(args);
+    if name.is_empty() || !rest.is_empty() {
+        buffer.print(USAGE);
+        return;
+    }
+    let server: Option<ServerId> = if in_server {
+        match ChannelData::from_buffer_impl(state, buffer) {
+            Some(ChannelRef::Public(server, _)) => Some(server.id),
+            _ => {
+                buffer.print("-server must be used from a server channel buffer");
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let (user_id, description) = match find_user(state, &name) {
+        Some(user) => user,
+        None => {
+            buffer.print(&format!("User not found: {}", name));
+            return;
+        }
+    };
+    let mut ignored = config::ignored(server);
+    let scope = if server.is_some() {
+        " in this server"
+    } else {
+        ""
+    };
+    if ignore {
+        if ignored.contains(&user_id) {
+            buffer.print(&format!("{} is already ignored{}", description, scope));
+            return;
+        }
+        ignored.push(user_id);
+        buffer.print(&format!("Ignoring {}{}", description, scope));
+    } else {
+        if !ignored.contains(&user_id) {
+            buffer.print(&format!("{} isn't ignored{}", description, scope));
+            return;
+        }
+        ignored.retain(|&x| x != user_id);
+        buffer.print(&format!("Stopped ignoring {}{}", description, scope));
+    }
+    config::set_ignored(server, &ignored);
+}
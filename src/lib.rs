@@ -1,14 +1,50 @@
 extern crate discord;
+extern crate flate2;
+extern crate hyper;
 extern crate libc;
+extern crate regex;
+#[cfg_attr(test, macro_use)]
+extern crate serde_json;
+extern crate websocket;
 
 #[macro_use]
 mod macros;
 mod ffi;
+mod host;
+#[cfg(not(test))]
+mod weechat_host;
+#[cfg(test)]
+mod mock_host;
+#[cfg(test)]
+mod fixtures;
+#[cfg(test)]
+mod fake_discord;
+mod config;
 mod types;
 mod util;
 mod connection;
 mod message;
 mod event_proc;
+mod transfer;
+mod embed;
+mod emoji;
+mod cache;
+mod store;
+mod modlog;
+mod mentions;
+mod rename;
+mod rest;
+mod record;
+mod mute;
+mod nicklist;
+mod members;
+mod notify;
+mod highlight;
+mod ignore;
+mod friends;
+mod presence;
+mod whois;
+mod group;
 
 use ffi::*;
 use connection::*;
@@ -23,53 +59,213 @@ Discord from the comfort of your favorite command-line IRC client!
 Source code available at https://github.com/khyperia/weechat-discord
 
 How does channel muting work?
-If plugins.var.weecord.mute.<channel_id> is set to the literal \"1\", \
-then that buffer will not be opened. When a Discord channel is muted \
-(in the official client), weechat-discord detects this and automatically \
-sets this setting for you. If you would like to override this behavior \
-and un-mute the channel, set the setting to \"0\". (Do not unset it, as it \
-will just get automatically filled in again)
-
-Options used:
-
-plugins.var.weecord.token = <discord_token>
-plugins.var.weecord.rename.<id> = <string>
-plugins.var.weecord.mute.<channel_id> = (0|1)
-plugins.var.weecord.on_delete.<server_id> = <channel_id>
+If weecord.channel.<server>.#<channel>.mute (or weecord.server.<server>.mute) \
+is on, then those buffers will not be opened. When a channel or server is \
+muted or unmuted in the official client, weechat-discord follows along and \
+sets these options for you, but only lifts the mutes that came from there \
+(muted_by_discord). /discord mute and unmute set them too, \
+optionally for a limited time, and with weecord.network.sync_mutes on \
+they are sent back to Discord as well.
+
+Options live in weecord.conf, see /set weecord.* for their descriptions. \
+Per-server, per-channel and rename options are created on demand, named \
+after what they're for: servers by name, channels as <server>.#<channel>, \
+roles as <server>.@<role> and users as @<name>#1234. Options follow along \
+when a server, channel or role is renamed. Dots and spaces in names become \
+underscores. Private channels, groups, names that are already taken and \
+anything set before weechat-discord first connects go by id instead:
+
+weecord.server.<server>.on_delete = <channel_id>
+weecord.server.<server>.modlog = <target>,<target>
+weecord.server.<server>.modlog_events = <event>,<event>
+weecord.server.<server>.mute = (on|off)
+weecord.server.<server>.mute_until = <unix time>
+weecord.server.<server>.muted_by_discord = (on|off)
+weecord.server.<server>.notify = (all|mentions|nothing)
+weecord.server.<server>.suppress_everyone = (on|off)
+weecord.server.<server>.highlight_words = <word>,<word>
+weecord.server.<server>.highlight_regex = <regex>
+weecord.server.<server>.no_highlight_roles = <role>,<role>
+weecord.server.<server>.no_highlight_here = (on|off)
+weecord.server.<server>.ignore = <user_id>,<user_id>
+weecord.channel.<server>.#<channel>.mute = (on|off)
+weecord.channel.<server>.#<channel>.mute_until = <unix time>
+weecord.channel.<server>.#<channel>.muted_by_discord = (on|off)
+weecord.channel.<server>.#<channel>.notify = (all|mentions|nothing)
+weecord.rename.<server|server.#channel|server.@role|@user#1234> = <string>
+
+The notify options mirror the notification settings of the official client \
+and decide the hotlist level: \"mentions\" puts other messages on the \
+hotlist as low, \"nothing\" keeps the buffer off it. Without them, the \
+server's default notification setting applies. Besides mentions, \
+messages matching a server's highlight_words or highlight_regex highlight \
+too; mentions of the no_highlight_roles (by name or id) don't. @everyone \
+and @here are ignored on servers with more than \
+weecord.look.everyone_max_members members.
+
+Messages from users blocked in Discord, or ignored with /discord ignore, \
+are replaced by a line count (or hidden, see weecord.look.ignored_messages).
+
+Add the weecord_status item to a bar (for example with /set \
+weechat.bar.status.items) to see your own status. With \
+weecord.network.idle_on_away on, /away sets it to idle until you're back.
+
+Old plugins.var.weecord.* options are moved to weecord.conf on load. \
+Renames are easiest set with /discord rename.
+
+Emoji:
+Custom emoji are shown as :name:. When sending, :name: is replaced by the \
+server's custom emoji of that name, or the matching unicode emoji. Add \
+%(weecord_emoji) to weechat.completion.default_template to complete them.
+
+Large servers:
+Public channels of servers with more than weecord.look.nicklist_max members \
+get a lazy nicklist: it's filled with online members when you switch to the \
+buffer, and people who talk are added as they do. With \
+weecord.network.lazy_members on, members aren't downloaded on connect at \
+all. Instead the server's online members are synced again when someone \
+unknown talks or is online in the buffer you switch to, or when completing \
+nicks; they arrive with the next gateway event. Add %(weecord_members) to \
+weechat.completion.default_template to complete every member of the server \
+anyway.
+
+Moderation log:
+weecord.server.<server>.modlog sends edits, deletes, bulk deletes, \
+joins, leaves, bans, unbans and role changes on that server to a channel \
+(by id), the modlog buffer (\"buffer\") or a file (\"file:<path>\"). \
+modlog_events limits which (edit, delete, bulk_delete, join, leave, ban, \
+unban, roles). The lines are formatted by the weecord.modlog.* options, \
+with ${server}, ${author}, ${channel}, ${content}, ${before}, ${after}, \
+${count}, ${user} and ${changes} filled in. Channels are posted to in \
+the background. on_delete still sends deletes to its channel, whatever \
+modlog_events says.
 ";
     pub const ARGS: &'static str = "\
                      connect
                      disconnect
-                     token <token>";
+                     token <token>
+                     query <user> [<message>]
+                     whois <user>
+                     upload <path> [<comment>]
+                     download <message_id|^[N]> [<dir>]
+                     rename <@user|#channel|server|@role> <name>
+                     rename -clear <@user|#channel|server|@role>
+                     mute [#channel|server [<name>]] [<duration>]
+                     mute -list
+                     unmute [#channel|server [<name>]]
+                     ignore [-server] <user>
+                     unignore [-server] <user>
+                     friends [accept|decline|remove <user>]
+                     friends add <name#1234>
+                     group create <user> [<user>...]
+                     group add|remove <user>
+                     group rename [<name>]
+                     group leave
+                     status [online|idle|dnd|invisible]
+                     game [<text>]
+                     custom_status [<text>]
+                     debug record [-redact] <file>|-stop
+                     debug replay <file>";
     pub const ARGDESC: &'static str = "\
 connect: sign in to discord and open chat buffers
 disconnect: sign out of Discord
 token: set Discord login token
-query: open PM buffer with user
+query: open (and switch to) a PM buffer with a user, given by name, \
+name#1234, id or mention, and optionally send a first message
+whois: show a user's name, id, status, and nick, roles and join date in \
+every mutual server
+upload: send a file (and optional comment) to the current channel
+download: save the attachments of a message in the current channel, \
+given by id or as ^N for the Nth most recent message with attachments
+rename: display a user, channel, server or role under a different name. \
+Names are looked up in the current buffer's server; use \"server\" for \
+the current server. Quote names containing spaces.
+mute: close and stop opening the current channel, a #channel of the \
+current server, or a whole server. A duration such as 30m, 2h or 1d \
+lifts the mute automatically. -list shows what is muted
+unmute: undo a mute and reopen the buffers
+ignore: hide messages from a user, everywhere or with -server only in the \
+current server. Without arguments, lists ignored and blocked users
+unignore: show a user's messages again
+friends: open the friends buffer (friends with their status, pending \
+requests and blocked users), or accept/decline a request, send one with \
+add, or remove a friend. The same commands can be typed in the buffer
+group: create a group DM, or (in a group buffer) add or remove \
+recipients, rename it (no name clears it) or leave it
+status: show or set your status
+game: set the game you're playing, or clear it
+custom_status: set the custom status text, or clear it
+debug record: save raw gateway events to a file, for bug reports. The \
+token is left out, and with -redact message contents too. Reconnects if \
+connected, -stop stops recording
+debug replay: play a recording back while disconnected, without network
 
 Example:
   /discord token 123456789ABCDEF
   /discord connect
   /discord query khyperia
+  /discord query khyperia#1234 hello
+  /discord upload ~/screenshot.png look at this
+  /discord download ^
+  /discord rename @khyperia kh
+  /discord rename -clear #general
+  /discord mute #random 2h
+  /discord unmute server
+  /discord ignore -server spammer
+  /discord friends add khyperia#1234
+  /discord status dnd
   /discord disconnect
 ";
     pub const COMPLETIONS: &'static str = "\
-connect || disconnect || token || debug replace || query";
+connect || disconnect || token || debug replace|record|replay %(filename) || query %(nicks) || whois %(nicks) || upload %(filename) || download || rename -clear|%(nicks) || mute -list|server || unmute server || ignore -server|%(nicks) %(nicks) || unignore -server|%(nicks) %(nicks) || friends accept|decline|remove|add || group create|add|remove|rename|leave %(nicks) || status online|idle|dnd|invisible || game || custom_status";
 }
 
 // *DO NOT* touch this outside of init/end
 static mut MAIN_COMMAND_HOOK: *mut HookCommand = 0 as *mut _;
+static mut EMOJI_COMPLETION_HOOK: *mut HookCompletion = 0 as *mut _;
+static mut MEMBERS_COMPLETION_HOOK: *mut HookCompletion = 0 as *mut _;
+static mut BUFFER_SWITCH_HOOK: *mut HookSignal = 0 as *mut _;
+static mut AWAY_HOOK: *mut HookCommandRun = 0 as *mut _;
+static mut STATUS_BAR_ITEM: *mut BarItem = 0 as *mut _;
 
 // Called when plugin is loaded in Weechat
 pub fn init() -> Option<()> {
+    tryopt!(config::init());
+    if config::message_store() {
+        if let Err(err) = store::start() {
+            command_print(&format!("Unable to load stored messages: {}", err));
+        }
+    }
     let hook = tryopt!(ffi::hook_command(weechat::COMMAND,
                                          weechat::DESCRIPTION,
                                          weechat::ARGS,
                                          weechat::ARGDESC,
                                          weechat::COMPLETIONS,
                                          move |buffer, input| run_command(&buffer, input)));
+    let emoji_hook = tryopt!(ffi::hook_completion("weecord_emoji",
+                                                  "Discord emoji",
+                                                  move |buffer, mut completion| {
+                                                      emoji_completion(&buffer, &mut completion)
+                                                  }));
+    let members_hook = tryopt!(ffi::hook_completion("weecord_members",
+                                                    "Discord server members",
+                                                    move |buffer, mut completion| {
+                                                        members_completion(&buffer,
+                                                                           &mut completion)
+                                                    }));
+    let switch_hook = tryopt!(ffi::hook_signal("buffer_switch", move |ptr| {
+        buffer_switched(&Buffer::from_ptr(ptr))
+    }));
+    let away_hook = tryopt!(ffi::hook_command_run("/away*",
+                                                  move |_, command| away_command(command)));
+    let status_item = tryopt!(BarItem::new(presence::BAR_ITEM_NAME, status_bar_item));
     unsafe {
         MAIN_COMMAND_HOOK = Box::into_raw(Box::new(hook));
+        EMOJI_COMPLETION_HOOK = Box::into_raw(Box::new(emoji_hook));
+        MEMBERS_COMPLETION_HOOK = Box::into_raw(Box::new(members_hook));
+        BUFFER_SWITCH_HOOK = Box::into_raw(Box::new(switch_hook));
+        AWAY_HOOK = Box::into_raw(Box::new(away_hook));
+        STATUS_BAR_ITEM = Box::into_raw(Box::new(status_item));
     };
     Some(())
 }
@@ -79,27 +275,39 @@ pub fn end() -> Option<()> {
     unsafe {
         let _ = Box::from_raw(MAIN_COMMAND_HOOK);
         MAIN_COMMAND_HOOK = ::std::ptr::null_mut();
+        let _ = Box::from_raw(EMOJI_COMPLETION_HOOK);
+        EMOJI_COMPLETION_HOOK = ::std::ptr::null_mut();
+        let _ = Box::from_raw(MEMBERS_COMPLETION_HOOK);
+        MEMBERS_COMPLETION_HOOK = ::std::ptr::null_mut();
+        let _ = Box::from_raw(BUFFER_SWITCH_HOOK);
+        BUFFER_SWITCH_HOOK = ::std::ptr::null_mut();
+        let _ = Box::from_raw(AWAY_HOOK);
+        AWAY_HOOK = ::std::ptr::null_mut();
+        let _ = Box::from_raw(STATUS_BAR_ITEM);
+        STATUS_BAR_ITEM = ::std::ptr::null_mut();
     };
+    store::stop();
+    modlog::end();
+    nicklist::end();
+    mentions::end();
+    highlight::end();
+    cache::end();
+    config::end();
     Some(())
 }
 
-fn user_set_option(name: &str, value: &str) {
-    command_print(&ffi::set_option(name, value));
-}
-
 fn command_print(message: &str) {
     MAIN_BUFFER.print(&format!("{}: {}", &weechat::COMMAND, message));
 }
 
 fn run_command(buffer: &Buffer, command: &str) {
-    // TODO: Add rename command
     if command == "" {
         command_print("see /help discord for more information")
     } else if command == "connect" {
-        match ffi::get_option("token") {
+        match config::token() {
             Some(t) => MyConnection::create(t),
             None => {
-                command_print("Error: plugins.var.weecord.token unset. Run:");
+                command_print("Error: weecord.network.token unset. Run:");
                 command_print("/discord token 123456789ABCDEF");
                 return;
             }
@@ -109,12 +317,74 @@ fn run_command(buffer: &Buffer, command: &str) {
         command_print("disconnected");
     } else if command.starts_with("token ") {
         let token = &command["token ".len()..];
-        user_set_option("token", token.trim_matches('"'));
+        command_print(&config::set_token(token.trim_matches('"')));
     } else if command.starts_with("query ") {
         query_command(buffer, &command["query ".len()..]);
+    } else if command.starts_with("whois ") {
+        whois_command(buffer, &command["whois ".len()..]);
+    } else if command.starts_with("upload ") {
+        upload_command(buffer, &command["upload ".len()..]);
+    } else if command.starts_with("download ") {
+        download_command(buffer, &command["download ".len()..]);
+    } else if command.starts_with("rename ") {
+        rename_command(buffer, &command["rename ".len()..]);
+    } else if command == "mute" || command == "unmute" {
+        mute_command(buffer, "", command == "mute");
+    } else if command.starts_with("mute ") {
+        mute_command(buffer, &command["mute ".len()..], true);
+    } else if command.starts_with("unmute ") {
+        mute_command(buffer, &command["unmute ".len()..], false);
+    } else if command == "ignore" {
+        ignore_command(buffer, "", true);
+    } else if command.starts_with("ignore ") {
+        ignore_command(buffer, &command["ignore ".len()..], true);
+    } else if command.starts_with("unignore ") {
+        ignore_command(buffer, &command["unignore ".len()..], false);
+    } else if command == "friends" {
+        friends_command(buffer, "");
+    } else if command.starts_with("friends ") {
+        friends_command(buffer, &command["friends ".len()..]);
+    } else if command.starts_with("group ") {
+        group_command(buffer, &command["group ".len()..]);
+    } else if command == "status" {
+        status_command(buffer, "");
+    } else if command.starts_with("status ") {
+        status_command(buffer, &command["status ".len()..]);
+    } else if command == "game" {
+        game_command(buffer, "");
+    } else if command.starts_with("game ") {
+        game_command(buffer, &command["game ".len()..]);
+    } else if command == "custom_status" {
+        custom_status_command(buffer, "");
+    } else if command.starts_with("custom_status ") {
+        custom_status_command(buffer, &command["custom_status ".len()..]);
     } else if command.starts_with("debug ") {
-        debug_command(&command["debug ".len()..]);
+        debug_command(buffer, &command["debug ".len()..]);
     } else {
         command_print("unknown command");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use config;
+    use ffi::MAIN_BUFFER;
+    use mock_host;
+    use presence;
+
+    #[test]
+    fn discord_command() {
+        let session = mock_host::setup_plugin();
+        let host = session.host();
+        assert!(host.run_command(&MAIN_BUFFER, "/discord token \"abc\""));
+        assert_eq!(config::token(), Some("abc".into()));
+        host.run_command(&MAIN_BUFFER, "/discord bogus");
+        assert_eq!(host.messages(&MAIN_BUFFER).last().map(|x| &**x),
+                   Some("discord: unknown command"));
+        host.run_command(&MAIN_BUFFER, "/discord whois alice");
+        assert_eq!(host.messages(&MAIN_BUFFER).last().map(|x| &**x),
+                   Some("Discord is not connected"));
+        assert_eq!(host.bar_item(presence::BAR_ITEM_NAME), Some(String::new()));
+        assert!(!host.run_command(&MAIN_BUFFER, "/away gone"));
+    }
+}
@@ -0,0 +1,48 @@
+use std::collections::HashSet;
+use std::sync::mpsc::Sender;
+use discord::model::{ServerId, UserId};
+
+use config;
+
+// Asks for the members weecord.network.lazy_members left out as they're
+// needed, by having the listening thread sync their server again (op 12,
+// the only member request discord-rs makes for user accounts). The online
+// members come back as GUILD_SYNC, once the thread gets its next event.
+// Each user, and completion in each server, only asks once per connection.
+pub struct MemberRequests {
+    send: Sender<ServerId>,
+    users: HashSet<(ServerId, UserId)>,
+    completed: HashSet<ServerId>,
+}
+
+impl MemberRequests {
+    pub fn new(send: Sender<ServerId>) -> MemberRequests {
+        MemberRequests {
+            send: send,
+            users: HashSet::new(),
+            completed: HashSet::new(),
+        }
+    }
+
+    // Speakers and online users that aren't known members yet
+    pub fn users(&mut self, server_id: ServerId, user_ids: &[UserId]) {
+        if !config::lazy_members() {
+            return;
+        }
+        let mut new = false;
+        for &id in user_ids {
+            new |= self.users.insert((server_id, id));
+        }
+        if new {
+            let _ = self.send.send(server_id);
+        }
+    }
+
+    // Completing nicks in a server; the members are offered the next time
+    // round
+    pub fn completing(&mut self, server_id: ServerId) {
+        if config::lazy_members() && self.completed.insert(server_id) {
+            let _ = self.send.send(server_id);
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use discord::{ChannelRef, State};
+use discord::model::*;
+
+use types::*;
+
+// Who's who for mentions: what <@id> and friends show as, and which @name
+// or #name is sent as a mention. Server indexes are built on first use and
+// then kept up to date by event_proc::update; private channels and groups
+// are small enough to not bother.
+pub struct Index {
+    // user name and member name, for <@id> and <@!id>
+    users: HashMap<UserId, (String, String)>,
+    roles: HashMap<RoleId, String>,
+    channels: HashMap<ChannelId, String>,
+    // "@name" or "#name" -> mentions, the first one wins
+    names: HashMap<String, Vec<String>>,
+    // in bytes, never shrinks
+    longest: usize,
+}
+
+static mut INDEXES: *mut HashMap<ServerId, Index> = 0 as *mut _;
+
+fn indexes() -> &'static mut HashMap<ServerId, Index> {
+    unsafe {
+        if INDEXES.is_null() {
+            INDEXES = Box::into_raw(Box::new(HashMap::new()));
+        }
+        &mut *INDEXES
+    }
+}
+
+fn server_index(server: &LiveServer) -> &'static Index {
+    indexes()
+        .entry(server.id)
+        .or_insert_with(|| Index::from_server(server))
+}
+
+fn with_index<T, F: FnOnce(&Index) -> T>(channel: &ChannelRef, f: F) -> T {
+    match *channel {
+        ChannelRef::Public(server, _) => f(server_index(server)),
+        ChannelRef::Private(private) => f(&Index::from_users(Some(&private.recipient))),
+        ChannelRef::Group(group) => f(&Index::from_users(&group.recipients)),
+    }
+}
+
+// <@id>, <@!id>, <@&id> and <#id> to names, colored
+pub fn replace_mentions(channel: &ChannelRef, content: &str) -> String {
+    if !content.contains('<') {
+        return content.into();
+    }
+    with_index(channel, |index| index.receive(content, &NameFormat::color_prefix()))
+}
+
+// @name and #name to mentions, when they're a whole word
+pub fn replace_mentions_send(channel: &ChannelRef, content: &str) -> String {
+    if !content.contains('@') && !content.contains('#') {
+        return content.into();
+    }
+    with_index(channel, |index| index.send(content))
+}
+
+// What a member goes by on the server, without going through its members
+pub fn member_name(server: &LiveServer, user: UserId) -> Option<String> {
+    server_index(server)
+        .users
+        .get(&user)
+        .map(|&(_, ref member_name)| member_name.clone())
+}
+
+// Every name and its mention, for /discord debug replace
+pub fn names(channel: &ChannelRef) -> Vec<(String, String)> {
+    let mut names = with_index(channel, |index| {
+        index
+            .names
+            .iter()
+            .filter_map(|(name, mentions)| mentions.first().map(|x| (name.clone(), x.clone())))
+            .collect::<Vec<_>>()
+    });
+    names.sort();
+    names
+}
+
+// After State::update has seen the event
+pub fn update(state: &State, event: &Event) {
+    match *event {
+        Event::Ready(_) |
+        Event::ServerCreate(_) |
+        Event::ServerDelete(_) => clear(),
+        Event::ServerMemberAdd(server_id, ref member) => {
+            if let Some(index) = indexes().get_mut(&server_id) {
+                index.add_member(member);
+            }
+        }
+        Event::ServerMemberUpdate { server_id, ref user, .. } => {
+            let member = state
+                .find_server(server_id)
+                .and_then(|server| server.members.iter().find(|x| x.user.id == user.id));
+            if let (Some(index), Some(member)) = (indexes().get_mut(&server_id), member) {
+                index.add_member(member);
+            }
+        }
+        Event::ServerMemberRemove(server_id, ref user) => {
+            if let Some(index) = indexes().get_mut(&server_id) {
+                index.remove_user(user.id);
+            }
+        }
+        Event::ServerMembersChunk(server_id, ref members) |
+        Event::ServerSync {
+            server_id,
+            ref members,
+            ..
+        } => {
+            if let Some(index) = indexes().get_mut(&server_id) {
+                for member in members {
+                    index.add_member(member);
+                }
+            }
+        }
+        Event::ServerRoleCreate(server_id, ref role) |
+        Event::ServerRoleUpdate(server_id, ref role) => {
+            if let Some(index) = indexes().get_mut(&server_id) {
+                index.add_role(role);
+            }
+        }
+        Event::ServerRoleDelete(server_id, role_id) => {
+            if let Some(index) = indexes().get_mut(&server_id) {
+                index.remove_role(role_id);
+            }
+        }
+        Event::ChannelCreate(Channel::Public(ref channel)) |
+        Event::ChannelUpdate(Channel::Public(ref channel)) => {
+            if let Some(index) = indexes().get_mut(&channel.server_id) {
+                index.add_channel(channel);
+            }
+        }
+        Event::ChannelDelete(Channel::Public(ref channel)) => {
+            if let Some(index) = indexes().get_mut(&channel.server_id) {
+                index.remove_channel(channel.id);
+            }
+        }
+        // only full users, which is how name changes come
+        Event::PresenceUpdate { ref presence, .. } => {
+            if let Some(ref user) = presence.user {
+                rename_user(state, user.id, &user.name);
+            }
+        }
+        Event::UserUpdate(ref user) => rename_user(state, user.id, &user.username),
+        _ => (),
+    }
+}
+
+// On every server they're on
+fn rename_user(state: &State, user_id: UserId, name: &str) {
+    for server in state.servers() {
+        let member = server.members.iter().find(|x| x.user.id == user_id);
+        if let (Some(index), Some(member)) = (indexes().get_mut(&server.id), member) {
+            let mut member = member.clone();
+            member.user.name = name.into();
+            index.add_member(&member);
+        }
+    }
+}
+
+// Renames change every name, start over
+pub fn clear() {
+    indexes().clear();
+}
+
+pub fn end() {
+    unsafe {
+        if !INDEXES.is_null() {
+            let _ = Box::from_raw(INDEXES);
+            INDEXES = ::std::ptr::null_mut();
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl Index {
+    fn new() -> Index {
+        Index {
+            users: HashMap::new(),
+            roles: HashMap::new(),
+            channels: HashMap::new(),
+            names: HashMap::new(),
+            longest: 0,
+        }
+    }
+
+    fn from_server(server: &LiveServer) -> Index {
+        let mut index = Index::new();
+        for member in &server.members {
+            index.add_member(member);
+        }
+        for role in &server.roles {
+            index.add_role(role);
+        }
+        for channel in &server.channels {
+            index.add_channel(channel);
+        }
+        index
+    }
+
+    fn from_users<'a, I: IntoIterator<Item = &'a User>>(users: I) -> Index {
+        let mut index = Index::new();
+        for user in users {
+            let name = user.name(&NameFormat::none());
+            index.add_user(user, name);
+        }
+        index
+    }
+
+    fn add_name(&mut self, name: String, mention: String) {
+        if name.len() > self.longest {
+            self.longest = name.len();
+        }
+        self.names.entry(name).or_insert_with(Vec::new).push(mention);
+    }
+
+    fn remove_name(&mut self, name: &str, mention: &str) {
+        let empty = match self.names.get_mut(name) {
+            Some(mentions) => {
+                mentions.retain(|x| x != mention);
+                mentions.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.names.remove(name);
+        }
+    }
+
+    // Also for members whose nick changed
+    fn add_member(&mut self, member: &Member) {
+        let name = member.name(&NameFormat::none());
+        self.add_user(&member.user, name);
+    }
+
+    fn add_user(&mut self, user: &User, member_name: String) {
+        self.remove_user(user.id);
+        let user_name = user.name(&NameFormat::none());
+        self.add_name(format!("@{}", user_name), format!("<@{}>", user.id.0));
+        self.add_name(format!("@{}", member_name), format!("<@!{}>", user.id.0));
+        self.users.insert(user.id, (user_name, member_name));
+    }
+
+    fn remove_user(&mut self, user: UserId) {
+        if let Some((user_name, member_name)) = self.users.remove(&user) {
+            self.remove_name(&format!("@{}", user_name), &format!("<@{}>", user.0));
+            self.remove_name(&format!("@{}", member_name), &format!("<@!{}>", user.0));
+        }
+    }
+
+    fn add_role(&mut self, role: &Role) {
+        self.remove_role(role.id);
+        let name = role.name(&NameFormat::none());
+        self.add_name(format!("@{}", name), format!("<@&{}>", role.id.0));
+        self.roles.insert(role.id, name);
+    }
+
+    fn remove_role(&mut self, role: RoleId) {
+        if let Some(name) = self.roles.remove(&role) {
+            self.remove_name(&format!("@{}", name), &format!("<@&{}>", role.0));
+        }
+    }
+
+    fn add_channel(&mut self, channel: &PublicChannel) {
+        self.remove_channel(channel.id);
+        let name = channel.name(&NameFormat::none());
+        self.add_name(format!("#{}", name), format!("<#{}>", channel.id.0));
+        self.channels.insert(channel.id, name);
+    }
+
+    fn remove_channel(&mut self, channel: ChannelId) {
+        if let Some(name) = self.channels.remove(&channel) {
+            self.remove_name(&format!("#{}", name), &format!("<#{}>", channel.0));
+        }
+    }
+
+    fn receive(&self, content: &str, format: &NameFormat) -> String {
+        let mut result = String::with_capacity(content.len());
+        let mut rest = content;
+        while let Some(idx) = rest.find('<') {
+            result.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+            match self.mention_at(rest, format) {
+                Some((len, name)) => {
+                    result.push_str(&name);
+                    rest = &rest[len..];
+                }
+                None => {
+                    result.push('<');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        result.push_str(rest);
+        result
+    }
+
+    // The length of the mention text starts with, and the name it shows as.
+    // Unknown ids are left alone.
+    fn mention_at(&self, text: &str, format: &NameFormat) -> Option<(usize, String)> {
+        let end = tryopt!(text.find('>'));
+        let inner = &text[1..end];
+        let (kind, id) = if inner.starts_with("@!") || inner.starts_with("@&") {
+            inner.split_at(2)
+        } else if inner.starts_with('@') || inner.starts_with('#') {
+            inner.split_at(1)
+        } else {
+            return None;
+        };
+        let id = tryopt!(id.parse::<u64>().ok());
+        let name = match kind {
+            "@" => self.users.get(&UserId(id)).map(|x| format.format("@", &x.0)),
+            "@!" => self.users.get(&UserId(id)).map(|x| format.format("@", &x.1)),
+            "@&" => self.roles.get(&RoleId(id)).map(|x| format.format("@", x)),
+            _ => self.channels.get(&ChannelId(id)).map(|x| format.format("#", x)),
+        };
+        name.map(|name| (end + 1, name))
+    }
+
+    fn send(&self, content: &str) -> String {
+        let mut result = String::with_capacity(content.len());
+        let mut copied = 0;
+        let mut previous = None;
+        for (idx, c) in content.char_indices() {
+            let at_word_start = !previous.map_or(false, is_word);
+            previous = Some(c);
+            if idx < copied || (c != '@' && c != '#') || !at_word_start {
+                continue;
+            }
+            if let Some((end, mention)) = self.name_at(content, idx) {
+                result.push_str(&content[copied..idx]);
+                result.push_str(mention);
+                copied = end;
+            }
+        }
+        result.push_str(&content[copied..]);
+        result
+    }
+
+    // The longest name at start that ends at a word boundary, and its mention
+    fn name_at(&self, content: &str, start: usize) -> Option<(usize, &str)> {
+        let rest = &content[start..];
+        let ends = rest.char_indices()
+            .map(|(idx, _)| idx)
+            .skip(1)
+            .chain(Some(rest.len()))
+            .take_while(|&idx| idx <= self.longest)
+            .filter(|&idx| !rest[idx..].chars().next().map_or(false, is_word))
+            .collect::<Vec<_>>();
+        for &end in ends.iter().rev() {
+            if let Some(mention) = self.names.get(&rest[..end]).and_then(|x| x.first()) {
+                return Some((start + end, mention));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use discord::model::{ChannelId, ServerId};
+
+    use fixtures::{self, ALICE, GENERAL, SERVER};
+    use mock_host;
+    use super::*;
+
+    #[test]
+    fn only_whole_mentions_are_replaced() {
+        let _session = mock_host::setup();
+        let state = fixtures::state();
+        let channel = unwrap!(state.find_channel(ChannelId(GENERAL)));
+        assert_eq!(replace_mentions_send(&channel, "mail@alice.com, @alicex, @alice."),
+                   "mail@alice.com, @alicex, <@2>.");
+        assert_eq!(replace_mentions_send(&channel, "@mods: #general!"),
+                   "<@&12>: <#11>!");
+        assert_eq!(replace_mentions(&channel, "<@2 <@2> <:emoji:5> <@99> <#11"),
+                   "<@2 @alice <:emoji:5> <@99> <#11");
+    }
+
+    #[test]
+    fn indexes_follow_member_events() {
+        let _session = mock_host::setup();
+        let mut state = fixtures::state();
+        {
+            let channel = unwrap!(state.find_channel(ChannelId(GENERAL)));
+            assert_eq!(replace_mentions_send(&channel, "@al"), "@al");
+        }
+        assert!(indexes().contains_key(&ServerId(SERVER)));
+        let event = fixtures::member_update(Some("al"), &[]);
+        state.update(&event);
+        update(&state, &event);
+        let channel = unwrap!(state.find_channel(ChannelId(GENERAL)));
+        assert_eq!(replace_mentions_send(&channel, "@al @alice"), "<@!2> <@2>");
+        assert_eq!(replace_mentions(&channel, "<@!2>"), "@al");
+    }
+
+    #[test]
+    fn indexes_follow_user_renames() {
+        let _session = mock_host::setup();
+        let mut state = fixtures::state();
+        {
+            let channel = unwrap!(state.find_channel(ChannelId(GENERAL)));
+            assert_eq!(replace_mentions(&channel, "<@2>"), "@alice");
+        }
+        let event = fixtures::event("PRESENCE_UPDATE",
+                                    json!({
+                                        "guild_id": SERVER.to_string(),
+                                        "roles": [],
+                                        "user": fixtures::user(ALICE, "alicia"),
+                                        "status": "online",
+                                        "game": null,
+                                    }));
+        state.update(&event);
+        update(&state, &event);
+        let channel = unwrap!(state.find_channel(ChannelId(GENERAL)));
+        assert_eq!(replace_mentions(&channel, "<@2>"), "@alicia");
+        assert_eq!(replace_mentions_send(&channel, "@alicia @alice"), "<@2> @alice");
+    }
+}
@@ -1,13 +1,21 @@
 use discord::*;
 use discord::model::*;
+use serde_json::Value;
 
 use types::*;
-use ffi;
 use ffi::*;
 use connection::*;
+use transfer::format_size;
+use embed::format_embeds;
+use emoji::{replace_custom_emoji, replace_shortcodes};
+use mentions::{replace_mentions, replace_mentions_send};
+use notify::{self, Mention};
+use highlight;
+use ignore;
+use config;
+use cache;
 
 pub struct FormattedMessage {
-    pub channel: String,
     pub author: String,
     pub prefix: &'static str,
     pub content: String,
@@ -24,45 +32,56 @@ impl FormattedMessage {
 pub fn is_self_mentioned(channel: &ChannelData,
                          mention_everyone: bool,
                          author: Option<&User>,
+                         content: Option<&str>,
                          mentions: Option<&Vec<User>>,
                          roles: Option<&Vec<RoleId>>)
-                         -> bool {
+                         -> Mention {
     let me = channel.state.user();
     if author.map(|a| a.id()) == Some(me.id()) {
-        return false;
-    }
-    if mention_everyone {
-        return true;
+        return Mention::None;
     }
     if let Some(mentions) = mentions {
         for mention in mentions {
             if me.id == mention.id {
-                return true;
+                return Mention::Direct;
             }
         }
     }
+    let everyone = if mention_everyone {
+        highlight::everyone_mention(&channel.channel, content)
+    } else {
+        Mention::None
+    };
+    // highlight words and regexes count as much as a mention
+    let rules = highlight::content_mention(&channel.channel, content);
+    let fallback = if rules == Mention::Direct {
+        rules
+    } else {
+        everyone
+    };
     let server = match channel.channel {
         ChannelRef::Public(server, _) => server,
-        _ => return false,
+        _ => return fallback,
     };
     let roles = if let Some(roles) = roles {
         roles
     } else {
-        return false;
+        return fallback;
     };
     for member in &server.members {
         if member.user.id == me.id {
             for member_role in &member.roles {
                 for role in roles {
-                    if member_role.0 == role.0 {
-                        return true;
+                    if member_role.0 == role.0 &&
+                       highlight::role_mention(server, *role) == Mention::Direct {
+                        return Mention::Direct;
                     }
                 }
             }
             break;
         }
     }
-    false
+    fallback
 }
 
 pub fn all_names_everywhere<T, F: FnMut(String, &User) -> Option<T>>(state: &State,
@@ -94,113 +113,87 @@ pub fn all_names_everywhere<T, F: FnMut(String, &User) -> Option<T>>(state: &Sta
     None
 }
 
-pub fn all_names(chan_ref: &ChannelRef, format: &NameFormat) -> Vec<(String, String)> {
-    let mut names = Vec::new();
-    match *chan_ref {
-        ChannelRef::Private(private) => {
-            names.push((private.recipient.name(format), format!("{}", private.recipient.mention())))
-        }
-        ChannelRef::Group(group) => {
-            for recipient in &group.recipients {
-                names.push((recipient.name(format), format!("{}", recipient.mention())))
-            }
-        }
-        ChannelRef::Public(server, _) => {
-            for member in &server.members {
-                let mut mention = format!("{}", member.user.mention());
-                // order of push matters (stable sort for nick/user names the same)
-                names.push((member.user.name(format), mention.clone()));
-                mention.insert(2, '!');
-                names.push((member.name(format), mention));
-            }
-            for role in &server.roles {
-                names.push((role.name(format), format!("{}", role.mention())));
-            }
-            for chan in &server.channels {
-                names.push((chan.name(format), format!("{}", chan.mention())));
-            }
-        }
-    }
-    // sort by descending length order. Rust sort is stable sort.
-    names.sort_by(|&(ref a, _), &(ref b, _)| b.len().cmp(&a.len()));
-    names
-}
-
-fn replace_mentions_send(channel: &ChannelRef, mut content: String) -> String {
-    for (name, mention) in all_names(channel, &NameFormat::prefix()) {
-        if content.contains(&*name) {
-            content = content.replace(&*name, &mention);
-        }
-    }
-    content
+// The id in a `<@id>` mention, or a query that's all digits
+pub fn query_id(query: &str) -> Option<UserId> {
+    let query = query.trim();
+    let id = if query.starts_with("<@") && query.ends_with('>') {
+        query[2..query.len() - 1].trim_left_matches('!')
+    } else {
+        query
+    };
+    id.parse::<u64>().ok().map(UserId)
 }
 
-fn replace_mentions(channel: &ChannelRef, mut content: String) -> String {
-    for (name, mention) in all_names(channel, &NameFormat::color_prefix()) {
-        // check contains to reduce allocations
-        if content.contains(&mention) {
-            content = content.replace(&mention, &name);
+fn users_where<F: Fn(&str, &User) -> bool>(state: &State, matches: F) -> Vec<User> {
+    let mut found: Vec<User> = Vec::new();
+    all_names_everywhere(state, |found_name, user| {
+        if matches(&found_name, user) && !found.iter().any(|u| u.id == user.id) {
+            found.push(user.clone());
         }
-    }
-    content
+        None::<()>
+    });
+    found
 }
 
-fn find_tag<T, F: Fn(String) -> Option<T>>(line_data: &ffi::WeechatAny, pred: F) -> Option<T> {
-    let tagcount: i32 = unwrap!(line_data.get("tags_count"));
-    let tagcount = tagcount as usize;
-    for i in 0..tagcount {
-        let tag: String = unwrap!(line_data.get_idx::<ffi::SharedString>("tags_array", i)).0;
-        if let Some(result) = pred(tag) {
-            return Some(result);
+// A user by name, nick, `name#1234`, id or `<@id>` mention. Several users
+// can share a name, so all of them are returned. Names that are all digits
+// are tried as an id first.
+pub fn find_users(state: &State, query: &str) -> Vec<User> {
+    let query = query.trim();
+    if let Some(id) = query_id(query) {
+        let found = users_where(state, |_, user| user.id == id);
+        if !found.is_empty() || query.starts_with("<@") {
+            return found;
         }
     }
-    None
+    let query = query.trim_left_matches('@');
+    let (name, discriminator) = match query.rfind('#') {
+        Some(idx) => (&query[..idx], query[idx + 1..].parse::<u16>().ok()),
+        None => (query, None),
+    };
+    users_where(state, |found_name, user| match discriminator {
+        Some(discriminator) => user.name == name && user.discriminator == discriminator,
+        None => found_name == name,
+    })
 }
 
-// returns: (Prefix, Message)
-fn find_old_msg(buffer: &Buffer, message_id: MessageId) -> Option<(String, String)> {
-    let searchterm = format!("discord_messageid_{}", message_id.0);
-    let mut result = None;
-    if let Some(mut line) = unwrap!(buffer.get_any("lines")).get_any("first_line") {
-        loop {
-            let data = unwrap!(line.get_any("data"));
-            let found_tag = find_tag(&data, |tag| if tag == searchterm { Some(()) } else { None });
-            if let Some(()) = found_tag {
-                let prefix = unwrap!(data.get::<ffi::SharedString>("prefix")).0;
-                let message = unwrap!(data.get("message"));
-                result = Some(match result {
-                    Some((prefix, previous)) => (prefix, format!("{}\n{}", previous, message)),
-                    None => (prefix, message),
-                });
-            }
-            if let Some(next) = line.get_any("next_line") {
-                line = next;
-            } else {
-                break;
-            }
-        }
-    }
-    result
+pub fn format_attachment(attachment: &Attachment) -> String {
+    format!("{} ({}) {}",
+            attachment.filename,
+            format_size(attachment.size),
+            attachment.proxy_url)
 }
 
+// What's missing comes from the message cache. returns: (Author, Content)
 pub fn resolve_message(author: Option<&User>,
                        content: Option<&str>,
-                       buffer: &Buffer,
                        channel_ref: &ChannelRef,
                        message_id: MessageId)
                        -> Option<(String, String)> {
     let author_format = NameFormat::color();
-    if let (Some(author), Some(content)) = (author, content) {
-        let content = replace_mentions(channel_ref, content.into());
-        // Check for member-defined name instead of user name
-        if let ChannelRef::Public(server, _) = *channel_ref {
-            if let Some(member) = server.members.iter().find(|m| m.id() == author.id()) {
-                return Some((member.name(&author_format), content.into()));
-            }
-        }
-        Some((author.name(&author_format), content.into()))
+    let cached = if author.is_none() || content.is_none() {
+        Some(tryopt!(cache::get(message_id)))
     } else {
-        find_old_msg(buffer, message_id)
+        None
+    };
+    let content = match (content, cached.as_ref()) {
+        (Some(content), _) => content.to_owned(),
+        (None, Some(cached)) => cached.text(),
+        (None, None) => return None,
+    };
+    let content = replace_custom_emoji(replace_mentions(channel_ref, &content));
+    let author_id = tryopt!(author.map(|x| x.id).or(cached.as_ref().map(|x| x.author_id)));
+    // Check for member-defined name instead of user name
+    if let ChannelRef::Public(server, _) = *channel_ref {
+        if let Some(member) = server.members.iter().find(|m| m.user.id == author_id) {
+            return Some((member.name(&author_format), content));
+        }
+    }
+    match (author, cached) {
+        (Some(author), _) => Some((author.name(&author_format), content)),
+        // gone from the server
+        (None, Some(cached)) => Some((author_format.format("", &cached.author), content)),
+        (None, None) => None,
     }
 }
 
@@ -209,28 +202,31 @@ pub fn format_message(channel: &ChannelData,
                       author: Option<&User>,
                       content: Option<&str>,
                       attachments: Option<&Vec<Attachment>>,
+                      embeds: Option<&Vec<Value>>,
                       prefix: &'static str,
-                      self_mentioned: bool)
+                      mention: Mention)
                       -> Option<FormattedMessage> {
-    let is_private = if let ChannelRef::Public(_, _) = channel.channel {
-        false
-    } else {
-        true
-    };
+    // deletes only know the author from the cache
+    let author_id = author
+        .map(|a| a.id)
+        .or_else(|| cache::get(message_id).map(|x| x.author_id));
+    let ignored = author_id.map_or(false,
+                                   |a| ignore::is_ignored(channel.state, &channel.channel, a));
+    // 0 is "hide"
+    if ignored && config::ignored_messages() == 0 {
+        return None;
+    }
     let (author, content) = tryopt!(resolve_message(author,
                                                     content,
-                                                    &channel.buffer,
                                                     &channel.channel,
                                                     message_id));
     let tags = {
         let mut tags = Vec::new();
-        if self_mentioned {
-            tags.push("notify_highlight".into());
-        } else if is_private {
-            tags.push("notify_private".into());
+        if ignored {
+            tags.push("notify_none,no_highlight".into());
         } else {
-            tags.push("notify_message".into());
-        };
+            tags.push(notify::tags(&channel.channel, mention).into());
+        }
         tags.push(format!("nick_{}", author));
         tags.push(format!("discord_messageid_{}", message_id.0));
         tags.join(",".into())
@@ -242,13 +238,23 @@ pub fn format_message(channel: &ChannelData,
         }
         if let Some(attachments) = attachments {
             for attachment in attachments {
-                content_list.push(attachment.proxy_url.clone());
+                content_list.push(format_attachment(attachment));
             }
         }
-        content_list.join("\n")
+        if let Some(embeds) = embeds {
+            content_list.extend(format_embeds(&channel.channel, embeds));
+        }
+        let content = content_list.join("\n");
+        if ignored {
+            let lines = content.lines().count();
+            format!("({} line{} from an ignored user hidden)",
+                    lines,
+                    if lines == 1 { "" } else { "s" })
+        } else {
+            content
+        }
     };
     Some(FormattedMessage {
-             channel: channel.channel.name(&NameFormat::none()),
              author: author,
              prefix: prefix,
              content: content,
@@ -257,5 +263,70 @@ pub fn format_message(channel: &ChannelData,
 }
 
 pub fn format_message_send(channel_ref: &ChannelRef, message: String) -> String {
-    replace_mentions_send(channel_ref, message)
+    replace_shortcodes(channel_ref, replace_mentions_send(channel_ref, &message))
+}
+
+#[cfg(test)]
+mod tests {
+    use discord::model::ChannelId;
+
+    use fixtures;
+    use mock_host;
+    use super::*;
+
+    #[test]
+    fn replaces_mentions_both_ways() {
+        let session = mock_host::setup();
+        let state = fixtures::state();
+        let channel = unwrap!(state.find_channel(ChannelId(fixtures::GENERAL)));
+        assert_eq!(replace_mentions(&channel, "<@2> <@!2> in <#11>"),
+                   "@alice @alice in #general");
+        assert_eq!(format_message_send(&channel, "@alice, see #general".into()),
+                   "<@2>, see <#11>");
+        session.host().set("weecord.rename.2", "al");
+        assert_eq!(replace_mentions(&channel, "hi <@2>"), "hi @al");
+    }
+
+    #[test]
+    fn highlight_rules_beat_everyone() {
+        let session = mock_host::setup();
+        let (state, discord) = (fixtures::state(), fixtures::discord());
+        let channel = unwrap!(state.find_channel(ChannelId(fixtures::GENERAL)));
+        let channel = unwrap!(ChannelData::from_channel(&state, &discord, channel, true));
+        let alice = fixtures::message(100, fixtures::ALICE, "", &[]).author;
+        let mention = |content| {
+            is_self_mentioned(&channel, true, Some(&alice), Some(content), None, None)
+        };
+        assert!(mention("@everyone caats") == Mention::Everyone);
+        session.host().set("weecord.server.10.highlight_regex", "ca+ts");
+        assert!(mention("@everyone caats") == Mention::Direct);
+        // recompiled when it changes
+        session.host().set("weecord.server.10.highlight_regex", "dogs");
+        assert!(mention("@everyone caats") == Mention::Everyone);
+        // and forgotten when it's unset
+        session.host().set("weecord.server.10.highlight_regex", "ca+ts");
+        assert!(mention("@everyone caats") == Mention::Direct);
+        assert!(session.host().unset("weecord.server.10.highlight_regex"));
+        assert!(mention("@everyone caats") == Mention::Everyone);
+    }
+
+    #[test]
+    fn finds_users_by_id_then_name() {
+        let _session = mock_host::setup();
+        let mut state = fixtures::state();
+        state.update(&fixtures::member_update(Some("42"), &[]));
+        let ids = |query| {
+            find_users(&state, query)
+                .iter()
+                .map(|user| user.id.0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids("2"), vec![fixtures::ALICE]);
+        assert_eq!(ids("<@!2>"), vec![fixtures::ALICE]);
+        assert_eq!(ids("alice#0002"), vec![fixtures::ALICE]);
+        assert_eq!(ids("alice#0003"), Vec::<u64>::new());
+        // nobody has that id, but it's her nick
+        assert_eq!(ids("42"), vec![fixtures::ALICE]);
+        assert_eq!(ids("<@42>"), Vec::<u64>::new());
+    }
 }
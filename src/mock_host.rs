@@ -0,0 +1,878 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{Mutex, MutexGuard, Once, ONCE_INIT};
+use libc::{c_int, c_void};
+
+use config;
+use ffi::{BarItem, Buffer, Completion, ConfigFile, ConfigOption, ConfigSection, CreateOptionFn,
+          DeleteOptionFn, Hook, Nick, CONFIG_OPTION_SET_OK_CHANGED};
+use host::{Host, Line};
+
+// An in-memory WeeChat for tests: buffers keep their lines, nicklists and
+// properties, options are validated like WeeChat does, and hooks are only
+// run when a test asks (input, run_command, fire_timers, ...).
+
+pub struct MockBuffer {
+    pub name: String,
+    pub properties: HashMap<String, String>,
+    pub lines: Vec<Line>,
+    pub nicks: Vec<MockNick>,
+    on_input: Option<fn(Buffer, &str)>,
+}
+
+pub struct MockNick {
+    id: usize,
+    pub name: String,
+    // empty for WeeChat's color
+    pub color: String,
+}
+
+type CommandCb = Rc<RefCell<Box<FnMut(Buffer, &str)>>>;
+type PlainCb = Rc<RefCell<Box<FnMut()>>>;
+
+enum MockHook {
+    Command(String, CommandCb),
+    CommandRun(String, CommandCb),
+    Completion(String, Rc<Box<Fn(Buffer, Completion)>>),
+    Fd(PlainCb),
+    Timer(PlainCb),
+    Signal(String, Rc<RefCell<Box<FnMut(*mut c_void)>>>),
+}
+
+struct MockSection {
+    file: usize,
+    name: String,
+    dynamic: Option<(CreateOptionFn, DeleteOptionFn)>,
+}
+
+struct MockOption {
+    section: usize,
+    name: String,
+    kind: String,
+    string_values: Vec<String>,
+    min: i32,
+    max: i32,
+    value: String,
+    on_change: Option<fn(ConfigOption)>,
+}
+
+impl MockOption {
+    // The value as WeeChat would store it, None if it's invalid
+    fn validate(&self, value: &str) -> Option<String> {
+        match &*self.kind {
+            "boolean" => {
+                match value {
+                    "on" | "true" | "yes" | "1" => Some("on".into()),
+                    "off" | "false" | "no" | "0" => Some("off".into()),
+                    "toggle" => Some(if self.value == "on" { "off" } else { "on" }.into()),
+                    _ => None,
+                }
+            }
+            "integer" if !self.string_values.is_empty() => {
+                if self.string_values.iter().any(|x| x == value) {
+                    Some(value.into())
+                } else {
+                    None
+                }
+            }
+            "integer" => {
+                match value.parse::<i32>() {
+                    Ok(x) if x >= self.min && x <= self.max => Some(x.to_string()),
+                    _ => None,
+                }
+            }
+            _ => Some(value.into()),
+        }
+    }
+}
+
+pub struct MockHost {
+    next_id: Cell<usize>,
+    buffers: RefCell<BTreeMap<usize, MockBuffer>>,
+    hooks: RefCell<BTreeMap<usize, MockHook>>,
+    bar_items: RefCell<BTreeMap<usize, (String, Rc<Box<Fn() -> String>>)>>,
+    completions: RefCell<HashMap<usize, Vec<String>>>,
+    configs: RefCell<BTreeMap<usize, String>>,
+    sections: RefCell<BTreeMap<usize, MockSection>>,
+    options: RefCell<BTreeMap<usize, MockOption>>,
+    plugin_options: RefCell<Vec<(String, String)>>,
+}
+
+fn id_of(ptr: *mut c_void) -> usize {
+    ptr as usize
+}
+
+fn ptr_of(id: usize) -> *mut c_void {
+    id as *mut c_void
+}
+
+// "author\tcontent" -> prefix and message, like WeeChat splits them
+fn split_prefix(message: &str) -> (String, String) {
+    match message.find('\t') {
+        Some(idx) => (message[..idx].into(), message[idx + 1..].into()),
+        None => (String::new(), message.into()),
+    }
+}
+
+impl MockHost {
+    fn new() -> MockHost {
+        let host = MockHost {
+            // 0 is the core buffer (MAIN_BUFFER)
+            next_id: Cell::new(1),
+            buffers: RefCell::new(BTreeMap::new()),
+            hooks: RefCell::new(BTreeMap::new()),
+            bar_items: RefCell::new(BTreeMap::new()),
+            completions: RefCell::new(HashMap::new()),
+            configs: RefCell::new(BTreeMap::new()),
+            sections: RefCell::new(BTreeMap::new()),
+            options: RefCell::new(BTreeMap::new()),
+            plugin_options: RefCell::new(Vec::new()),
+        };
+        host.buffers
+            .borrow_mut()
+            .insert(0,
+                    MockBuffer {
+                        name: "weechat".into(),
+                        properties: HashMap::new(),
+                        lines: Vec::new(),
+                        nicks: Vec::new(),
+                        on_input: None,
+                    });
+        host
+    }
+
+    fn next_id(&self) -> usize {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    fn add_hook(&self, hook: MockHook) -> Option<Hook> {
+        let id = self.next_id();
+        self.hooks.borrow_mut().insert(id, hook);
+        Some(Hook::new(ptr_of(id), None))
+    }
+
+    fn with_buffer<R, F: FnOnce(&mut MockBuffer) -> R>(&self, buffer: &Buffer, f: F) -> Option<R> {
+        self.buffers
+            .borrow_mut()
+            .get_mut(&id_of(buffer.ptr()))
+            .map(f)
+    }
+
+    fn with_option<R, F: FnOnce(&MockOption) -> R>(&self, option: ConfigOption, f: F) -> R {
+        let options = self.options.borrow();
+        match options.get(&id_of(option.ptr())) {
+            Some(option) => f(option),
+            None => panic!("unknown option {:?}", option),
+        }
+    }
+
+    pub fn nicks(&self, buffer: &Buffer) -> Vec<String> {
+        self.with_buffer(buffer, |b| b.nicks.iter().map(|x| x.name.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    // The colors of every nick called name, "" for WeeChat's own color
+    pub fn nick_colors(&self, buffer: &Buffer, name: &str) -> Vec<String> {
+        self.with_buffer(buffer, |b| {
+            b.nicks
+                .iter()
+                .filter(|x| x.name == name)
+                .map(|x| x.color.clone())
+                .collect()
+        })
+            .unwrap_or_default()
+    }
+
+    // Messages without their prefix, for when only the text matters
+    pub fn messages(&self, buffer: &Buffer) -> Vec<String> {
+        self.buffer_lines(buffer)
+            .into_iter()
+            .map(|line| line.message)
+            .collect()
+    }
+
+    // Typed into a buffer: commands go to the hooks, the rest to the buffer
+    pub fn input(&self, buffer: &Buffer, text: &str) {
+        if text.starts_with('/') {
+            self.run_command(buffer, text);
+            return;
+        }
+        let on_input = self.with_buffer(buffer, |b| b.on_input);
+        if let Some(Some(on_input)) = on_input {
+            on_input(Buffer::from_ptr(buffer.ptr()), text);
+        }
+    }
+
+    // Returns false if no hook knows the command
+    pub fn run_command(&self, buffer: &Buffer, command: &str) -> bool {
+        let (name, args) = match command.find(' ') {
+            Some(idx) => (&command[1..idx], command[idx + 1..].trim_left()),
+            None => (&command[1..], ""),
+        };
+        let mut before = Vec::new();
+        let mut handler = None;
+        for hook in self.hooks.borrow().values() {
+            match *hook {
+                MockHook::CommandRun(ref pattern, ref callback) => {
+                    let matches = if pattern.ends_with('*') {
+                        command.starts_with(&pattern[..pattern.len() - 1])
+                    } else {
+                        command == pattern
+                    };
+                    if matches {
+                        before.push(callback.clone());
+                    }
+                }
+                MockHook::Command(ref hooked, ref callback) if hooked == name => {
+                    handler = Some(callback.clone());
+                }
+                _ => (),
+            }
+        }
+        for callback in before {
+            (&mut *callback.borrow_mut())(Buffer::from_ptr(buffer.ptr()), command);
+        }
+        match handler {
+            Some(callback) => {
+                (&mut *callback.borrow_mut())(Buffer::from_ptr(buffer.ptr()), args);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The words a completion item offers in a buffer
+    pub fn complete(&self, buffer: &Buffer, item: &str) -> Vec<String> {
+        let callback = self.hooks
+            .borrow()
+            .values()
+            .filter_map(|hook| match *hook {
+                            MockHook::Completion(ref name, ref callback) if name == item => {
+                                Some(callback.clone())
+                            }
+                            _ => None,
+                        })
+            .next();
+        let callback = match callback {
+            Some(callback) => callback,
+            None => return Vec::new(),
+        };
+        let id = self.next_id();
+        self.completions.borrow_mut().insert(id, Vec::new());
+        callback(Buffer::from_ptr(buffer.ptr()), Completion::from_ptr(ptr_of(id)));
+        self.completions
+            .borrow_mut()
+            .remove(&id)
+            .unwrap_or_default()
+    }
+
+    fn plain_callbacks(&self, timers: bool) -> Vec<PlainCb> {
+        self.hooks
+            .borrow()
+            .values()
+            .filter_map(|hook| match (hook, timers) {
+                            (&MockHook::Timer(ref callback), true) |
+                            (&MockHook::Fd(ref callback), false) => Some(callback.clone()),
+                            _ => None,
+                        })
+            .collect()
+    }
+
+    pub fn fire_timers(&self) {
+        for callback in self.plain_callbacks(true) {
+            (&mut *callback.borrow_mut())();
+        }
+    }
+
+    // As if every hooked fd became readable
+    pub fn fire_fds(&self) {
+        for callback in self.plain_callbacks(false) {
+            (&mut *callback.borrow_mut())();
+        }
+    }
+
+    // Like WeeChat sending a signal, data being a pointer
+    pub fn send_signal(&self, signal: &str, data: *mut c_void) {
+        let callbacks = self.hooks
+            .borrow()
+            .values()
+            .filter_map(|hook| match *hook {
+                            MockHook::Signal(ref name, ref callback) if name == signal => {
+                                Some(callback.clone())
+                            }
+                            _ => None,
+                        })
+            .collect::<Vec<_>>();
+        for callback in callbacks {
+            (&mut *callback.borrow_mut())(data);
+        }
+    }
+
+    pub fn bar_item(&self, name: &str) -> Option<String> {
+        let callback = self.bar_items
+            .borrow()
+            .values()
+            .find(|&&(ref item, _)| item == name)
+            .map(|&(_, ref callback)| callback.clone());
+        callback.map(|callback| callback())
+    }
+
+    fn find_section(&self, file: &str, section: &str) -> Option<usize> {
+        let configs = self.configs.borrow();
+        self.sections
+            .borrow()
+            .iter()
+            .find(|&(_, s)| s.name == section && configs.get(&s.file).map(|x| &**x) == Some(file))
+            .map(|(&id, _)| id)
+    }
+
+    // Like /set file.section.option value, creating dynamic options
+    pub fn set(&self, full_name: &str, value: &str) -> c_int {
+        let mut split = full_name.splitn(3, '.');
+        let (file, section, name) = match (split.next(), split.next(), split.next()) {
+            (Some(file), Some(section), Some(name)) => (file, section, name),
+            _ => return 2,
+        };
+        let section = match self.find_section(file, section) {
+            Some(section) => section,
+            None => return 2,
+        };
+        let existing = self.options
+            .borrow()
+            .iter()
+            .find(|&(_, o)| o.section == section && o.name == name)
+            .map(|(&id, _)| id);
+        if let Some(option) = existing {
+            return self.option_set(ConfigOption::from_ptr(ptr_of(option)), value);
+        }
+        let dynamic = self.sections.borrow()[&section].dynamic;
+        match dynamic {
+            Some((create, _)) => {
+                if create(ConfigSection::from_ptr(ptr_of(section)), name, value) ==
+                   CONFIG_OPTION_SET_OK_CHANGED {
+                    0
+                } else {
+                    3
+                }
+            }
+            None => 2,
+        }
+    }
+
+    // Like /unset file.section.option on a dynamic option, false if there's
+    // no such option
+    pub fn unset(&self, full_name: &str) -> bool {
+        let mut split = full_name.splitn(3, '.');
+        let (file, section, name) = match (split.next(), split.next(), split.next()) {
+            (Some(file), Some(section), Some(name)) => (file, section, name),
+            _ => return false,
+        };
+        let section = match self.find_section(file, section) {
+            Some(section) => section,
+            None => return false,
+        };
+        let option = self.options
+            .borrow()
+            .iter()
+            .find(|&(_, o)| o.section == section && o.name == name)
+            .map(|(&id, _)| ConfigOption::from_ptr(ptr_of(id)));
+        let dynamic = self.sections.borrow()[&section].dynamic;
+        match (option, dynamic) {
+            (Some(option), Some((_, delete))) => {
+                delete(ConfigSection::from_ptr(ptr_of(section)), option);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn get(&self, full_name: &str) -> Option<String> {
+        let mut split = full_name.splitn(3, '.');
+        let (file, section, name) = match (split.next(), split.next(), split.next()) {
+            (Some(file), Some(section), Some(name)) => (file, section, name),
+            _ => return None,
+        };
+        let section = tryopt!(self.find_section(file, section));
+        self.options
+            .borrow()
+            .values()
+            .find(|o| o.section == section && o.name == name)
+            .map(|o| o.value.clone())
+    }
+
+    pub fn add_plugin_option(&self, name: &str, value: &str) {
+        self.plugin_options
+            .borrow_mut()
+            .push((name.into(), value.into()));
+    }
+}
+
+impl Host for MockHost {
+    fn buffer_new(&self, name: &str, on_input: fn(Buffer, &str)) -> Option<Buffer> {
+        if self.buffer_search(name).is_some() {
+            return None;
+        }
+        let id = self.next_id();
+        let mut properties = HashMap::new();
+        properties.insert("name".into(), name.into());
+        self.buffers
+            .borrow_mut()
+            .insert(id,
+                    MockBuffer {
+                        name: name.into(),
+                        properties: properties,
+                        lines: Vec::new(),
+                        nicks: Vec::new(),
+                        on_input: Some(on_input),
+                    });
+        Some(Buffer::from_ptr(ptr_of(id)))
+    }
+
+    fn buffer_search(&self, name: &str) -> Option<Buffer> {
+        self.buffers
+            .borrow()
+            .iter()
+            .find(|&(&id, b)| id != 0 && b.name == name)
+            .map(|(&id, _)| Buffer::from_ptr(ptr_of(id)))
+    }
+
+    fn buffer_close(&self, buffer: &Buffer) {
+        if id_of(buffer.ptr()) != 0 {
+            // what WeeChat's close callback does
+            ::nicklist::forget(buffer);
+            self.buffers.borrow_mut().remove(&id_of(buffer.ptr()));
+        }
+    }
+
+    fn buffer_clear(&self, buffer: &Buffer) {
+        self.with_buffer(buffer, |b| b.lines.clear());
+    }
+
+    fn buffer_set(&self, buffer: &Buffer, property: &str, value: &str) {
+        let property = if property.starts_with("localvar_set_") {
+            format!("localvar_{}", &property["localvar_set_".len()..])
+        } else {
+            property.into()
+        };
+        self.with_buffer(buffer, |b| b.properties.insert(property, value.into()));
+    }
+
+    fn buffer_get(&self, buffer: &Buffer, property: &str) -> Option<String> {
+        self.with_buffer(buffer, |b| b.properties.get(property).cloned())
+            .and_then(|x| x)
+    }
+
+    fn buffer_lines(&self, buffer: &Buffer) -> Vec<Line> {
+        self.with_buffer(buffer, |b| b.lines.clone())
+            .unwrap_or_default()
+    }
+
+    fn print(&self, buffer: &Buffer, message: &str) {
+        self.print_tags(buffer, "", message)
+    }
+
+    // Lines don't keep their date
+    fn print_date_tags(&self, buffer: &Buffer, _date: i64, tags: &str, message: &str) {
+        self.print_tags(buffer, tags, message)
+    }
+
+    fn print_tags(&self, buffer: &Buffer, tags: &str, message: &str) {
+        let (prefix, message) = split_prefix(message);
+        let tags = tags.split(',')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.into())
+            .collect();
+        self.with_buffer(buffer, |b| {
+            b.lines
+                .push(Line {
+                          prefix: prefix,
+                          message: message,
+                          tags: tags,
+                      })
+        });
+    }
+
+    fn nick_add(&self, buffer: &Buffer, nick: &str, color: &str) -> Option<Nick> {
+        let id = self.next_id();
+        self.with_buffer(buffer, |b| {
+            b.nicks.push(MockNick {
+                             id: id,
+                             name: nick.into(),
+                             color: color.into(),
+                         })
+        })
+            .map(|_| Nick::from_ptr(ptr_of(id)))
+    }
+
+    fn nick_remove(&self, buffer: &Buffer, nick: &Nick) {
+        self.with_buffer(buffer, |b| b.nicks.retain(|x| x.id != id_of(nick.ptr())));
+    }
+
+    fn nick_remove_all(&self, buffer: &Buffer) {
+        self.with_buffer(buffer, |b| b.nicks.clear());
+    }
+
+    fn hook_command(&self,
+                    command: &str,
+                    description: &str,
+                    args: &str,
+                    args_description: &str,
+                    completion: &str,
+                    callback: Box<FnMut(Buffer, &str)>)
+                    -> Option<Hook> {
+        let _ = (description, args, args_description, completion);
+        self.add_hook(MockHook::Command(command.into(), Rc::new(RefCell::new(callback))))
+    }
+
+    fn hook_command_run(&self, command: &str, callback: Box<FnMut(Buffer, &str)>)
+                        -> Option<Hook> {
+        self.add_hook(MockHook::CommandRun(command.into(), Rc::new(RefCell::new(callback))))
+    }
+
+    fn hook_completion(&self,
+                       name: &str,
+                       description: &str,
+                       callback: Box<Fn(Buffer, Completion)>)
+                       -> Option<Hook> {
+        let _ = description;
+        self.add_hook(MockHook::Completion(name.into(), Rc::new(callback)))
+    }
+
+    fn completion_add(&self, completion: &Completion, word: &str) {
+        if let Some(words) = self.completions
+               .borrow_mut()
+               .get_mut(&id_of(completion.ptr())) {
+            words.push(word.into());
+        }
+    }
+
+    fn hook_fd(&self, fd: c_int, callback: Box<FnMut()>) -> Option<Hook> {
+        let _ = fd;
+        self.add_hook(MockHook::Fd(Rc::new(RefCell::new(callback))))
+    }
+
+    fn hook_timer(&self, interval_ms: i64, callback: Box<FnMut()>) -> Option<Hook> {
+        let _ = interval_ms;
+        self.add_hook(MockHook::Timer(Rc::new(RefCell::new(callback))))
+    }
+
+    fn hook_signal(&self, signal: &str, callback: Box<FnMut(*mut c_void)>) -> Option<Hook> {
+        self.add_hook(MockHook::Signal(signal.into(), Rc::new(RefCell::new(callback))))
+    }
+
+    fn unhook(&self, hook: &Hook) {
+        // the callback may be running, so drop it outside of the borrow
+        let removed = self.hooks.borrow_mut().remove(&id_of(hook.ptr()));
+        drop(removed);
+    }
+
+    fn bar_item_new(&self, name: &str, callback: Box<Fn() -> String>) -> Option<BarItem> {
+        let id = self.next_id();
+        self.bar_items
+            .borrow_mut()
+            .insert(id, (name.into(), Rc::new(callback)));
+        Some(BarItem::from_ptr(ptr_of(id), None))
+    }
+
+    fn bar_item_update(&self, name: &str) {
+        let _ = name;
+    }
+
+    fn bar_item_remove(&self, item: &BarItem) {
+        let removed = self.bar_items.borrow_mut().remove(&id_of(item.ptr()));
+        drop(removed);
+    }
+
+    fn config_new(&self, name: &str, on_reload: fn(&ConfigFile) -> c_int) -> Option<ConfigFile> {
+        let _ = on_reload;
+        let id = self.next_id();
+        self.configs.borrow_mut().insert(id, name.into());
+        Some(ConfigFile::from_ptr(ptr_of(id)))
+    }
+
+    fn config_new_section(&self,
+                          file: &ConfigFile,
+                          name: &str,
+                          dynamic: Option<(CreateOptionFn, DeleteOptionFn)>)
+                          -> Option<ConfigSection> {
+        let id = self.next_id();
+        self.sections
+            .borrow_mut()
+            .insert(id,
+                    MockSection {
+                        file: id_of(file.ptr()),
+                        name: name.into(),
+                        dynamic: dynamic,
+                    });
+        Some(ConfigSection::from_ptr(ptr_of(id)))
+    }
+
+    fn config_new_option(&self,
+                         file: &ConfigFile,
+                         section: ConfigSection,
+                         name: &str,
+                         kind: &str,
+                         description: &str,
+                         string_values: &str,
+                         min: i32,
+                         max: i32,
+                         default: &str,
+                         value: &str,
+                         on_change: Option<fn(ConfigOption)>)
+                         -> Option<ConfigOption> {
+        let _ = description;
+        if self.config_search_option(file, section, name).is_some() {
+            return None;
+        }
+        let mut option = MockOption {
+            section: id_of(section.ptr()),
+            name: name.into(),
+            kind: kind.into(),
+            string_values: string_values
+                .split('|')
+                .filter(|x| !x.is_empty())
+                .map(|x| x.into())
+                .collect(),
+            min: min,
+            max: max,
+            value: String::new(),
+            on_change: on_change,
+        };
+        option.value = tryopt!(option.validate(value).or_else(|| option.validate(default)));
+        let id = self.next_id();
+        self.options.borrow_mut().insert(id, option);
+        Some(ConfigOption::from_ptr(ptr_of(id)))
+    }
+
+    fn config_search_option(&self,
+                            file: &ConfigFile,
+                            section: ConfigSection,
+                            name: &str)
+                            -> Option<ConfigOption> {
+        let _ = file;
+        self.options
+            .borrow()
+            .iter()
+            .find(|&(_, o)| o.section == id_of(section.ptr()) && o.name == name)
+            .map(|(&id, _)| ConfigOption::from_ptr(ptr_of(id)))
+    }
+
+    fn config_read(&self, file: &ConfigFile) -> c_int {
+        let _ = file;
+        0
+    }
+
+    fn config_reload(&self, file: &ConfigFile) -> c_int {
+        let _ = file;
+        0
+    }
+
+    fn config_write(&self, file: &ConfigFile) -> c_int {
+        let _ = file;
+        0
+    }
+
+    fn config_free(&self, file: &ConfigFile) {
+        let file = id_of(file.ptr());
+        self.configs.borrow_mut().remove(&file);
+        let sections = self.sections
+            .borrow()
+            .iter()
+            .filter(|&(_, s)| s.file == file)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        self.sections
+            .borrow_mut()
+            .retain(|id, _| !sections.contains(id));
+        self.options
+            .borrow_mut()
+            .retain(|_, o| !sections.contains(&o.section));
+    }
+
+    fn config_section_free_options(&self, section: ConfigSection) {
+        let section = id_of(section.ptr());
+        self.options
+            .borrow_mut()
+            .retain(|_, o| o.section != section);
+    }
+
+    fn option_name(&self, option: ConfigOption) -> String {
+        self.with_option(option, |o| o.name.clone())
+    }
+
+    fn option_string(&self, option: ConfigOption) -> String {
+        self.with_option(option, |o| o.value.clone())
+    }
+
+    fn option_integer(&self, option: ConfigOption) -> i32 {
+        self.with_option(option, |o| if o.string_values.is_empty() {
+            o.value.parse().unwrap_or(0)
+        } else {
+            o.string_values
+                .iter()
+                .position(|x| *x == o.value)
+                .unwrap_or(0) as i32
+        })
+    }
+
+    fn option_boolean(&self, option: ConfigOption) -> bool {
+        self.with_option(option, |o| o.value == "on")
+    }
+
+    fn option_color(&self, option: ConfigOption) -> String {
+        let _ = option;
+        String::new()
+    }
+
+    fn option_set(&self, option: ConfigOption, value: &str) -> c_int {
+        let on_change = {
+            let mut options = self.options.borrow_mut();
+            let mock = match options.get_mut(&id_of(option.ptr())) {
+                Some(mock) => mock,
+                None => return 2,
+            };
+            let value = match mock.validate(value) {
+                Some(value) => value,
+                None => return 3,
+            };
+            if value == mock.value {
+                return 1;
+            }
+            mock.value = value;
+            mock.on_change
+        };
+        if let Some(on_change) = on_change {
+            on_change(option);
+        }
+        0
+    }
+
+    fn option_free(&self, option: ConfigOption) {
+        self.options.borrow_mut().remove(&id_of(option.ptr()));
+    }
+
+    fn plugin_options(&self) -> Vec<(String, String)> {
+        self.plugin_options.borrow().clone()
+    }
+
+    fn unset_plugin_option(&self, name: &str) {
+        self.plugin_options
+            .borrow_mut()
+            .retain(|&(ref x, _)| x != name);
+    }
+
+    fn info_get(&self, info_name: &str, arguments: &str) -> Option<String> {
+        let _ = arguments;
+        match info_name {
+            "weechat_data_dir" => Some(data_dir().to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+
+    fn color(&self, name: &str) -> String {
+        let _ = name;
+        String::new()
+    }
+
+    fn prefix(&self, name: &str) -> String {
+        let _ = name;
+        String::new()
+    }
+
+    fn remove_color(&self, string: &str) -> String {
+        string.into()
+    }
+}
+
+static mut HOST: *mut MockHost = 0 as *mut _;
+static mut LOCK: *const Mutex<()> = 0 as *const _;
+static LOCK_INIT: Once = ONCE_INIT;
+
+pub fn get() -> &'static MockHost {
+    unsafe {
+        if HOST.is_null() {
+            panic!("WeeChat used outside of mock_host::setup");
+        }
+        &*HOST
+    }
+}
+
+// Keeps the mock (and weecord.conf) alive for one test
+pub struct Session {
+    plugin: bool,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Session {
+    pub fn host(&self) -> &'static MockHost {
+        get()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if self.plugin {
+            // a test that connected leaves no connection behind
+            ::connection::MyConnection::drop();
+            ::record::stop();
+            ::end();
+        } else {
+            ::nicklist::end();
+            ::mentions::end();
+            ::highlight::end();
+            ::cache::end();
+            ::store::stop();
+            ::modlog::end();
+            config::end();
+        }
+        unsafe {
+            let _ = Box::from_raw(HOST);
+            HOST = ::std::ptr::null_mut();
+        }
+    }
+}
+
+// Empty for every session, so what a test stores doesn't outlive it
+fn data_dir() -> PathBuf {
+    env::temp_dir().join("weecord_test_data")
+}
+
+// CONFIG, MAGIC and friends are process wide, so tests take turns
+fn start(plugin: bool) -> Session {
+    LOCK_INIT.call_once(|| unsafe { LOCK = Box::into_raw(Box::new(Mutex::new(()))) });
+    let lock = unsafe { &*LOCK }
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let _ = fs::remove_dir_all(data_dir());
+    unwrap1!(fs::create_dir_all(data_dir()));
+    unsafe {
+        HOST = Box::into_raw(Box::new(MockHost::new()));
+    }
+    let mut session = Session {
+        plugin: false,
+        _lock: lock,
+    };
+    if plugin {
+        unwrap!(::init());
+        session.plugin = true;
+    } else {
+        unwrap!(config::init());
+    }
+    session
+}
+
+// A mock with weecord.conf loaded
+pub fn setup() -> Session {
+    start(false)
+}
+
+// A mock with the whole plugin loaded, /discord and all
+pub fn setup_plugin() -> Session {
+    start(true)
+}
@@ -0,0 +1,330 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::mpsc::*;
+use std::thread::spawn;
+use std::time::{SystemTime, UNIX_EPOCH};
+use discord::{ChannelRef, State};
+use discord::model::*;
+
+use cache::CachedMessage;
+use config;
+use ffi::{self, Buffer, PokeableFd, MAIN_BUFFER};
+use record;
+use rest;
+use transfer::expand_home;
+use types::*;
+use util::tag;
+
+// Moderation events of a server, written wherever weecord.server.<id>.modlog
+// says, as weecord.modlog.<event> formats them. weecord.server.<id>.on_delete
+// is the older way of logging deletes to a channel, and still works.
+pub const BUFFER_NAME: &'static str = "modlog";
+
+// Discord refuses longer messages
+const MAX_MESSAGE_CHARS: usize = 2000;
+
+// Longest 429 the posting thread waits out
+const MAX_WAIT_MS: u64 = 10000;
+
+struct Post {
+    api_url: String,
+    token: String,
+    channel_id: ChannelId,
+    text: String,
+}
+
+// Channel targets are posted to in order by one thread, so WeeChat doesn't
+// hang and rate limits are waited out. Failures come back through the pipe.
+struct Poster {
+    send: Sender<Post>,
+    errors: Receiver<String>,
+    _poke_fd: PokeableFd,
+}
+
+static mut POSTER: *mut Poster = 0 as *mut _;
+
+fn poster() -> &'static Poster {
+    unsafe {
+        if POSTER.is_null() {
+            let (send, recv) = channel::<Post>();
+            let (error_send, errors) = channel();
+            let poke_fd = PokeableFd::new(on_poke);
+            let poker = poke_fd.get_poker();
+            spawn(move || for post in recv {
+                      let result = rest::send_message_to(&post.api_url,
+                                                         &post.token,
+                                                         post.channel_id,
+                                                         &post.text,
+                                                         MAX_WAIT_MS);
+                      if let Err(err) = result {
+                          let err = format!("Failed to write to the moderation log {}: {}",
+                                            post.channel_id.0,
+                                            err);
+                          // the plugin is gone, nobody to tell
+                          if error_send.send(err).is_err() {
+                              break;
+                          }
+                          poker.poke();
+                      }
+                  });
+            POSTER = Box::into_raw(Box::new(Poster {
+                                                send: send,
+                                                errors: errors,
+                                                _poke_fd: poke_fd,
+                                            }));
+        }
+        &*POSTER
+    }
+}
+
+fn on_poke() {
+    unsafe {
+        if !POSTER.is_null() {
+            while let Ok(err) = (*POSTER).errors.try_recv() {
+                MAIN_BUFFER.print(&err);
+            }
+        }
+    }
+}
+
+// The thread stops once it's done with what's queued
+pub fn end() {
+    unsafe {
+        if !POSTER.is_null() {
+            let _ = Box::from_raw(POSTER);
+            POSTER = ::std::ptr::null_mut();
+        }
+    }
+}
+
+fn enabled(server_id: ServerId, event: &str) -> bool {
+    config::modlog_events(server_id).map_or(true, |events| events.iter().any(|x| x == event))
+}
+
+fn log(state: &State, server: &LiveServer, event: &str, vars: &[(&str, &str)]) {
+    let mut targets = if enabled(server.id, event) {
+        config::modlog(server.id)
+    } else {
+        Vec::new()
+    };
+    // on_delete doesn't care about modlog_events
+    if event == "delete" {
+        if let Some(channel_id) = config::on_delete(server.id) {
+            let target = format!("{}", channel_id.0);
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    // replayed events stay local
+    if record::replaying() {
+        targets.retain(|x| x.parse::<u64>().is_err());
+    }
+    if targets.is_empty() {
+        return;
+    }
+    let server_name = server.name(&NameFormat::none());
+    let mut all_vars = vec![("server", &*server_name)];
+    all_vars.extend_from_slice(vars);
+    let text = ffi::remove_color(&expand(&config::modlog_template(event), &all_vars));
+    for target in targets {
+        let result = if target == "buffer" {
+            to_buffer(&server_name, &text);
+            Ok(())
+        } else if target.starts_with("file:") {
+            to_file(&target["file:".len()..], &server_name, &text)
+        } else {
+            to_channel(state, &target, &text)
+        };
+        if let Err(err) = result {
+            MAIN_BUFFER.print(&format!("Failed to write to the moderation log {}: {}",
+                                       target,
+                                       err));
+        }
+    }
+}
+
+// ${name} to its value; unknown names, and whatever the values contain, are
+// left alone
+pub fn expand(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}')
+            .and_then(|end| vars.iter().find(|x| x.0 == &rest[2..end]).map(|x| (end, x.1)));
+        match value {
+            Some((end, value)) => {
+                result.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push_str("${");
+                rest = &rest[2..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn to_buffer(server_name: &str, text: &str) {
+    let buffer = match Buffer::search(BUFFER_NAME) {
+        Some(buffer) => buffer,
+        None => {
+            let buffer = unwrap!(Buffer::new(BUFFER_NAME, |_, _| {}));
+            buffer.set("short_name", "modlog");
+            buffer.set("title", "Discord moderation log");
+            buffer
+        }
+    };
+    buffer.print_tags("notify_message,no_highlight",
+                      &format!("{}\t{}", server_name, text));
+}
+
+fn to_file(path: &str, server_name: &str, text: &str) -> Result<(), String> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(expand_home(path))
+        .map_err(|err| format!("{}", err))?;
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    // one line per event, whatever was in the message
+    writeln!(file, "{} [{}] {}", time, server_name, text.replace('\n', " "))
+        .map_err(|err| format!("{}", err))
+}
+
+// Queued, see Poster
+fn to_channel(state: &State, target: &str, text: &str) -> Result<(), String> {
+    let channel_id = ChannelId(target.parse().map_err(|_| "not a channel id".to_owned())?);
+    if state.find_channel(channel_id).is_none() {
+        return Err("no such channel".into());
+    }
+    let token = config::token().ok_or_else(|| "no token".to_owned())?;
+    let text = match text.char_indices().nth(MAX_MESSAGE_CHARS) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    };
+    let post = Post {
+        api_url: config::api_url(),
+        token: token,
+        channel_id: channel_id,
+        text: text.into(),
+    };
+    poster().send.send(post).map_err(|_| "the posting thread is gone".to_owned())
+}
+
+fn server_of<'a>(state: &'a State, channel_id: ChannelId) -> Option<(&'a LiveServer, String)> {
+    match state.find_channel(channel_id) {
+        Some(ChannelRef::Public(server, channel)) => {
+            Some((server, channel.name(&NameFormat::none())))
+        }
+        _ => None,
+    }
+}
+
+// Before the cache forgets the old content
+pub fn edited(state: &State,
+              channel_id: ChannelId,
+              old: &CachedMessage,
+              content: &str) {
+    if old.content == content {
+        // embeds showing up
+        return;
+    }
+    if let Some((server, channel)) = server_of(state, channel_id) {
+        log(state,
+            server,
+            "edit",
+            &[("author", &*old.author),
+              ("channel", &*channel),
+              ("before", &*old.content),
+              ("after", content)]);
+    }
+}
+
+pub fn deleted(state: &State, message: &CachedMessage) {
+    if let Some((server, channel)) = server_of(state, message.channel_id) {
+        log(state,
+            server,
+            "delete",
+            &[("author", &*message.author),
+              ("channel", &*channel),
+              ("content", &*message.text())]);
+    }
+}
+
+// One entry for all of them, with a line for each message the cache knows
+pub fn bulk_deleted(state: &State,
+                    channel_id: ChannelId,
+                    messages: &[CachedMessage],
+                    count: usize) {
+    if let Some((server, channel)) = server_of(state, channel_id) {
+        let content = messages
+            .iter()
+            .map(|x| format!("{}: {}", x.author, x.text()))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        log(state,
+            server,
+            "bulk_delete",
+            &[("channel", &*channel),
+              ("count", &*count.to_string()),
+              ("content", &*content)]);
+    }
+}
+
+// join, leave, ban or unban
+pub fn member_event(state: &State, server_id: ServerId, event: &str, user: &User) {
+    if let Some(server) = state.find_server(server_id) {
+        log(state, server, event, &[("user", &*tag(user))]);
+    }
+}
+
+// "+added, -removed"
+pub fn roles_changed(state: &State, server_id: ServerId, old: &Member, new: &Member) {
+    let server = match state.find_server(server_id) {
+        Some(server) => server,
+        None => return,
+    };
+    let name = |id: &RoleId| {
+        server
+            .roles
+            .iter()
+            .find(|x| x.id == *id)
+            .map_or_else(|| format!("{}", id.0), |x| x.name(&NameFormat::none()))
+    };
+    let added = new.roles
+        .iter()
+        .filter(|x| !old.roles.contains(*x))
+        .map(|x| format!("+{}", name(x)));
+    let removed = old.roles
+        .iter()
+        .filter(|x| !new.roles.contains(*x))
+        .map(|x| format!("-{}", name(x)));
+    let changes = added.chain(removed).collect::<Vec<_>>();
+    if changes.is_empty() {
+        return;
+    }
+    let user = tag(&new.user);
+    log(state,
+        server,
+        "roles",
+        &[("user", &*user), ("changes", &*changes.join(", "))]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+
+    #[test]
+    fn expands_known_names_once() {
+        assert_eq!(expand("${user} said ${content} ${nope} ${", &[("user", "alice"),
+                                                                  ("content", "${user}")]),
+                   "alice said ${user} ${nope} ${");
+    }
+}
@@ -0,0 +1,407 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use discord::{ChannelRef, State};
+use discord::model::{ChannelId, LiveServer, ServerId, UserServerSettings};
+use hyper::method::Method;
+use serde_json::{Map, Value};
+
+use config;
+use connection::ChannelData;
+use ffi::*;
+use rest;
+use types::*;
+use util::split_arg;
+
+const USAGE: &'static str = "Usage: /discord mute [#channel|server [<name>]] [<duration>], \
+                             /discord unmute [#channel|server [<name>]], /discord mute -list";
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+// "90s", "30m", "2h", "1d" -> seconds
+fn parse_duration(arg: &str) -> Option<u64> {
+    if arg.is_empty() {
+        return None;
+    }
+    let (number, unit) = arg.split_at(arg.len() - 1);
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number.parse::<u64>().ok().map(|x| x * multiplier)
+}
+
+fn muted_now(muted: Option<bool>, is_server: bool, id: u64) -> bool {
+    muted.unwrap_or(false) && config::mute_until(is_server, id).map_or(true, |until| now() < until)
+}
+
+pub fn is_server_muted(server_id: ServerId) -> bool {
+    muted_now(config::server_muted(server_id), true, server_id.0)
+}
+
+pub fn is_channel_muted(channel_id: ChannelId) -> bool {
+    muted_now(config::channel_muted(channel_id), false, channel_id.0)
+}
+
+pub fn is_muted(channel: ChannelRef) -> bool {
+    if let ChannelRef::Public(server, _) = channel {
+        if is_server_muted(server.id) {
+            return true;
+        }
+    }
+    is_channel_muted(channel.id())
+}
+
+fn muted(is_server: bool, id: u64) -> Option<bool> {
+    if is_server {
+        config::server_muted(ServerId(id))
+    } else {
+        config::channel_muted(ChannelId(id))
+    }
+}
+
+fn set_muted(is_server: bool, id: u64, muted: bool) -> String {
+    if is_server {
+        config::set_server_muted(ServerId(id), muted)
+    } else {
+        config::set_channel_muted(ChannelId(id), muted)
+    }
+}
+
+// Discord's word on one server or channel. It mutes what it mutes (on
+// connect only what the user hasn't decided on yet), but only lifts the
+// mutes it made, not those of /discord mute.
+fn follow(is_server: bool, id: u64, muted_there: bool, initial: bool) {
+    let current = muted(is_server, id);
+    if muted_there && current != Some(true) && !(initial && current.is_some()) {
+        MAIN_BUFFER.print(&set_muted(is_server, id, true));
+        config::set_muted_by_discord(is_server, id, true);
+    } else if !muted_there && current == Some(true) && config::muted_by_discord(is_server, id) {
+        // before unmuting, which reopens the buffers
+        config::set_muted_by_discord(is_server, id, false);
+        MAIN_BUFFER.print(&set_muted(is_server, id, false));
+    }
+}
+
+// The settings of a server (or of private channels, for the @me "server")
+// from Ready, or a live change in the official client
+pub fn apply_settings(state: &State, settings: &UserServerSettings, initial: bool) {
+    let server = settings
+        .server_id
+        .and_then(|id| state.servers().iter().find(|s| s.id == id));
+    if let Some(server_id) = settings.server_id {
+        follow(true, server_id.0, settings.muted, initial);
+    }
+    let overridden = |channel_id: ChannelId| {
+        settings
+            .channel_overrides
+            .iter()
+            .any(|o| o.channel_id == channel_id && o.muted)
+    };
+    match server {
+        // channels without an override aren't muted there
+        Some(server) => {
+            for channel in &server.channels {
+                follow(false, channel.id.0, overridden(channel.id), initial);
+            }
+        }
+        None => {
+            for channel in &settings.channel_overrides {
+                follow(false, channel.channel_id.0, channel.muted, initial);
+            }
+        }
+    }
+}
+
+// unix seconds -> "2017-05-01T12:34:56+00:00"
+fn format_time(time: u64) -> String {
+    let (days, seconds) = (time / (24 * 60 * 60), time % (24 * 60 * 60));
+    // from days since 0000-03-01, with years starting in March so leap days
+    // come last
+    let days = days + 719468;
+    let (era, day_of_era) = (days / 146097, days % 146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 -
+                       day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = if month < 10 {
+        (era * 400 + year_of_era, month + 3)
+    } else {
+        (era * 400 + year_of_era + 1, month - 9)
+    };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
+            year,
+            month,
+            day,
+            seconds / (60 * 60),
+            seconds / 60 % 60,
+            seconds % 60)
+}
+
+// Called periodically to lift mutes made with a duration, here or in the
+// official client
+pub fn expire_mutes(state: &State) {
+    let now = now();
+    let expired = |is_server, id| config::mute_until(is_server, id).map_or(false, |x| x <= now);
+    let expire_channel = |channel_id: ChannelId| if expired(false, channel_id.0) {
+                             config::set_mute_until(false, channel_id.0, None);
+                             MAIN_BUFFER.print(&config::set_channel_muted(channel_id, false));
+                         };
+    for server in state.servers() {
+        if expired(true, server.id.0) {
+            config::set_mute_until(true, server.id.0, None);
+            MAIN_BUFFER.print(&config::set_server_muted(server.id, false));
+        }
+        for channel in &server.channels {
+            expire_channel(channel.id);
+        }
+    }
+    for private in state.private_channels() {
+        expire_channel(private.id);
+    }
+    for group in state.groups().values() {
+        expire_channel(group.channel_id);
+    }
+}
+
+enum Target<'a> {
+    Server(&'a LiveServer),
+    // server is None for private channels and groups
+    Channel(Option<&'a LiveServer>, ChannelRef<'a>),
+}
+
+fn parse_optional_duration(arg: &str) -> Result<Option<u64>, String> {
+    if arg.is_empty() {
+        Ok(None)
+    } else {
+        parse_duration(arg)
+            .map(Some)
+            .ok_or_else(|| format!("Invalid duration: {} (try 30m, 2h or 1d)", arg))
+    }
+}
+
+// (target, duration in seconds)
+fn resolve<'a>(state: &'a State,
+               buffer: &Buffer,
+               args: &str)
+               -> Result<(Target<'a>, Option<u64>), String> {
+    let current = ChannelData::from_buffer_impl(state, buffer);
+    let current_server = match current {
+        Some(ChannelRef::Public(server, _)) => Some(server),
+        _ => None,
+    };
+    let (first, rest) = split_arg(args);
+    if first == "server" {
+        let (name, rest) = split_arg(rest);
+        let only_duration = rest.is_empty() && parse_duration(&name).is_some();
+        let (server, duration) = if name.is_empty() || only_duration {
+            (current_server.ok_or("Not in a server buffer, give a server name")?, &*name)
+        } else {
+            let server = state
+                .servers()
+                .iter()
+                .find(|s| s.name(&NameFormat::none()) == name || s.name == name)
+                .ok_or_else(|| format!("Server not found: {}", name))?;
+            (server, rest)
+        };
+        return Ok((Target::Server(server), parse_optional_duration(duration)?));
+    }
+    if first.starts_with('#') {
+        let server = current_server.ok_or("#channel mutes must be run from a server buffer")?;
+        let name = &first[1..];
+        let channel = server
+            .channels
+            .iter()
+            .find(|c| c.name(&NameFormat::none()) == name || c.name == name)
+            .ok_or_else(|| format!("Channel not found: {}", first))?;
+        return Ok((Target::Channel(Some(server), ChannelRef::Public(server, channel)),
+                   parse_optional_duration(rest)?));
+    }
+    if !rest.is_empty() {
+        return Err(USAGE.into());
+    }
+    let current = current.ok_or("Not in a Discord channel buffer")?;
+    Ok((Target::Channel(current_server, current), parse_optional_duration(&first)?))
+}
+
+fn list_mutes(state: &State, buffer: &Buffer) {
+    let until = |is_server, id| match config::mute_until(is_server, id) {
+        Some(until) => format!(" ({} minutes left)", until.saturating_sub(now()) / 60),
+        None => String::new(),
+    };
+    let mut any = false;
+    for server in state.servers() {
+        if is_server_muted(server.id) {
+            buffer.print(&format!("server {}{}",
+                                  server.name(&NameFormat::none()),
+                                  until(true, server.id.0)));
+            any = true;
+        }
+        for channel in &server.channels {
+            if is_channel_muted(channel.id) {
+                buffer.print(&format!("{} - {}{}",
+                                      server.name(&NameFormat::none()),
+                                      channel.name(&NameFormat::prefix()),
+                                      until(false, channel.id.0)));
+                any = true;
+            }
+        }
+    }
+    if !any {
+        buffer.print("Nothing is muted");
+    }
+}
+
+// Private channel settings live under the @me "server". discord-rs drops
+// mute_config from what Discord sends, so end times only go this way.
+fn push_to_discord(token: &str,
+                   server_id: Option<ServerId>,
+                   channel_id: Option<ChannelId>,
+                   muted: bool,
+                   duration: Option<u64>)
+                   -> Result<(), String> {
+    let server = server_id.map_or("@me".into(), |id| format!("{}", id.0));
+    let mut mute = Map::new();
+    mute.insert("muted".into(), Value::Bool(muted));
+    if let Some(duration) = duration {
+        let mut mute_config = Map::new();
+        mute_config.insert("selected_time_window".into(), Value::from(duration));
+        mute_config.insert("end_time".into(), Value::String(format_time(now() + duration)));
+        mute.insert("mute_config".into(), Value::Object(mute_config));
+    }
+    let mut body = Map::new();
+    match channel_id {
+        Some(channel_id) => {
+            let mut overrides = Map::new();
+            overrides.insert(format!("{}", channel_id.0), Value::Object(mute));
+            body.insert("channel_overrides".into(), Value::Object(overrides));
+        }
+        None => body = mute,
+    }
+    rest::request(token,
+                  Method::Patch,
+                  &format!("/users/@me/guilds/{}/settings", server),
+                  Some(Value::Object(body)))
+        .map(|_| ())
+}
+
+fn close_buffer(channel: ChannelRef) {
+    if let Some(buffer) = Buffer::search(&ChannelData::buffer_name(channel)) {
+        buffer.close();
+    }
+}
+
+pub fn mute_command(state: &State, token: &str, buffer: &Buffer, args: &str, mute: bool) {
+    if mute && args.trim() == "-list" {
+        list_mutes(state, buffer);
+        return;
+    }
+    let (target, duration) = match resolve(state, buffer, args) {
+        Ok(x) => x,
+        Err(err) => {
+            buffer.print(&err);
+            return;
+        }
+    };
+    if !mute && duration.is_some() {
+        buffer.print(USAGE);
+        return;
+    }
+    let until = duration.map(|x| now() + x);
+    let (description, server_id, channel_id) = match target {
+        Target::Server(server) => {
+            config::set_mute_until(true, server.id.0, until);
+            config::set_muted_by_discord(true, server.id.0, false);
+            config::set_server_muted(server.id, mute);
+            (format!("server {}", server.name(&NameFormat::none())), Some(server.id), None)
+        }
+        Target::Channel(server, channel) => {
+            config::set_mute_until(false, channel.id().0, until);
+            config::set_muted_by_discord(false, channel.id().0, false);
+            config::set_channel_muted(channel.id(), mute);
+            (channel.name(&NameFormat::prefix()), server.map(|s| s.id), Some(channel.id()))
+        }
+    };
+    match (mute, duration) {
+        (true, Some(duration)) => {
+            buffer.print(&format!("Muted {} for {} minutes", description, duration / 60))
+        }
+        (true, None) => buffer.print(&format!("Muted {}", description)),
+        (false, _) => buffer.print(&format!("Unmuted {}", description)),
+    }
+    if config::sync_mutes() {
+        if let Err(err) = push_to_discord(token, server_id, channel_id, mute, duration) {
+            buffer.print(&format!("Unable to sync mute to Discord: {}", err));
+        }
+    }
+    // last, this may well close the buffer we're printing to
+    if mute {
+        match target {
+            Target::Server(server) => {
+                for channel in &server.channels {
+                    close_buffer(ChannelRef::Public(server, channel));
+                }
+            }
+            Target::Channel(_, channel) => close_buffer(channel),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use discord::model::{ChannelId, ServerId};
+    use serde_json;
+
+    use fixtures::{self, GENERAL, SERVER};
+    use mock_host;
+    use super::*;
+
+    fn settings(muted: bool, muted_channels: &[u64]) -> UserServerSettings {
+        let overrides = muted_channels
+            .iter()
+            .map(|id| {
+                     json!({
+                         "channel_id": id.to_string(),
+                         "muted": true,
+                         "message_notifications": 3,
+                     })
+                 })
+            .collect::<Vec<_>>();
+        unwrap1!(serde_json::from_value(json!({
+            "guild_id": SERVER.to_string(),
+            "muted": muted,
+            "message_notifications": 0,
+            "mobile_push": false,
+            "suppress_everyone": false,
+            "channel_overrides": overrides,
+        })))
+    }
+
+    #[test]
+    fn lifts_only_its_own_mutes() {
+        let _session = mock_host::setup();
+        let state = fixtures::state();
+        config::set_server_muted(ServerId(SERVER), true);
+        apply_settings(&state, &settings(false, &[GENERAL]), false);
+        // muted here, so it stays muted
+        assert_eq!(config::server_muted(ServerId(SERVER)), Some(true));
+        assert_eq!(config::channel_muted(ChannelId(GENERAL)), Some(true));
+        apply_settings(&state, &settings(false, &[]), false);
+        assert_eq!(config::server_muted(ServerId(SERVER)), Some(true));
+        assert_eq!(config::channel_muted(ChannelId(GENERAL)), Some(false));
+    }
+
+    #[test]
+    fn formats_end_times() {
+        assert_eq!(format_time(24 * 60 * 60 + 1), "1970-01-02T00:00:01+00:00");
+        assert_eq!(format_time(951782400), "2000-02-29T00:00:00+00:00");
+        assert_eq!(format_time(1583020800 + 3723), "2020-03-01T01:02:03+00:00");
+    }
+}
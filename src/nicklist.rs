@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use discord::model::UserId;
+
+use ffi::{Buffer, Nick};
+
+// WeeChat knows nicks by name, but two members can share one, and names
+// change. So which nicklist entry is whose is kept here, per buffer, along
+// with when it was last added (for capped nicklists). The nicks are removed
+// by pointer, so entries must go as soon as WeeChat frees them: when the
+// buffer is closed or its nicklist cleared.
+static mut NICKS: *mut HashMap<Buffer, HashMap<UserId, (Nick, u64)>> = 0 as *mut _;
+static mut COUNTER: u64 = 0;
+
+fn nicks() -> &'static mut HashMap<Buffer, HashMap<UserId, (Nick, u64)>> {
+    unsafe {
+        if NICKS.is_null() {
+            NICKS = Box::into_raw(Box::new(HashMap::new()));
+        }
+        &mut *NICKS
+    }
+}
+
+// Replaces the user's entry, if there is one
+pub fn add(buffer: &Buffer, user: UserId, name: &str, color: &str) {
+    remove(buffer, user);
+    if let Some(nick) = buffer.add_nick(name, color) {
+        let added = unsafe {
+            COUNTER += 1;
+            COUNTER
+        };
+        nicks()
+            .entry(Buffer::from_ptr(buffer.ptr()))
+            .or_insert_with(HashMap::new)
+            .insert(user, (nick, added));
+    }
+}
+
+pub fn remove(buffer: &Buffer, user: UserId) {
+    if let Some((nick, _)) = nicks().get_mut(buffer).and_then(|x| x.remove(&user)) {
+        buffer.remove_nick(&nick);
+    }
+}
+
+pub fn contains(buffer: &Buffer, user: UserId) -> bool {
+    nicks()
+        .get(buffer)
+        .map_or(false, |x| x.contains_key(&user))
+}
+
+pub fn len(buffer: &Buffer) -> usize {
+    nicks().get(buffer).map_or(0, |x| x.len())
+}
+
+// Removes the least recently added nicks until there are at most max,
+// except for keep
+pub fn truncate(buffer: &Buffer, max: usize, keep: &[UserId]) {
+    while len(buffer) > max {
+        let oldest = nicks()
+            .get(buffer)
+            .and_then(|x| {
+                          x.iter()
+                              .filter(|&(user, _)| !keep.contains(user))
+                              .min_by_key(|&(_, &(_, added))| added)
+                              .map(|(&user, _)| user)
+                      });
+        match oldest {
+            Some(user) => remove(buffer, user),
+            None => break,
+        }
+    }
+}
+
+pub fn clear(buffer: &Buffer) {
+    nicks().remove(buffer);
+    buffer.remove_all_nicks();
+}
+
+// From the buffer's close callback, which can come after end()
+pub fn forget(buffer: &Buffer) {
+    unsafe {
+        if !NICKS.is_null() {
+            (*NICKS).remove(buffer);
+        }
+    }
+}
+
+pub fn end() {
+    unsafe {
+        if !NICKS.is_null() {
+            let _ = Box::from_raw(NICKS);
+            NICKS = ::std::ptr::null_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use discord::model::UserId;
+
+    use ffi::Buffer;
+    use mock_host;
+    use super::*;
+
+    #[test]
+    fn closed_buffers_are_forgotten() {
+        let session = mock_host::setup();
+        let buffer = unwrap!(Buffer::new("test", |_, _| {}));
+        add(&buffer, UserId(1), "me", "");
+        add(&buffer, UserId(1), "me", "");
+        assert_eq!(session.host().nicks(&buffer), vec!["me"]);
+        buffer.close();
+        assert_eq!(len(&buffer), 0);
+    }
+}
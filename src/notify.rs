@@ -0,0 +1,157 @@
+use discord::{ChannelRef, State};
+use discord::model::{LiveServer, NotificationLevel, UserServerSettings};
+
+use config;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Mention {
+    None,
+    // @everyone or @here
+    Everyone,
+    // the user or one of their roles
+    Direct,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Level {
+    All,
+    Mentions,
+    Nothing,
+}
+
+impl Level {
+    fn from_discord(level: &NotificationLevel) -> Option<Level> {
+        match *level {
+            NotificationLevel::All => Some(Level::All),
+            NotificationLevel::Mentions => Some(Level::Mentions),
+            NotificationLevel::Nothing => Some(Level::Nothing),
+            // "use the server's (or the server default's) setting"
+            NotificationLevel::Parent => None,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "all" => Some(Level::All),
+            "mentions" => Some(Level::Mentions),
+            "nothing" => Some(Level::Nothing),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Level::All => "all",
+            Level::Mentions => "mentions",
+            Level::Nothing => "nothing",
+        }
+    }
+}
+
+// Channel override, then server setting, then the server's default
+pub fn level(channel: &ChannelRef) -> Level {
+    if let Some(level) = config::channel_notify(channel.id()).and_then(|x| Level::from_name(&x)) {
+        return level;
+    }
+    match *channel {
+        ChannelRef::Public(server, _) => {
+            config::server_notify(server.id)
+                .and_then(|x| Level::from_name(&x))
+                .unwrap_or_else(|| server_default(server))
+        }
+        _ => Level::All,
+    }
+}
+
+// What the server's admins picked: 0 is all messages, 1 only mentions
+fn server_default(server: &LiveServer) -> Level {
+    if server.default_message_notifications == 1 {
+        Level::Mentions
+    } else {
+        Level::All
+    }
+}
+
+fn suppress_everyone(channel: &ChannelRef) -> bool {
+    match *channel {
+        ChannelRef::Public(server, _) => config::server_suppress_everyone(server.id),
+        _ => false,
+    }
+}
+
+fn level_name(level: &NotificationLevel) -> &'static str {
+    Level::from_discord(level).map_or("", |x| x.name())
+}
+
+// The official client is in charge of these, so they are overwritten both on
+// connect and on every settings update
+pub fn apply_settings(state: &State, settings: &UserServerSettings) {
+    if let Some(server_id) = settings.server_id {
+        config::set_server_notify(server_id, level_name(&settings.message_notifications));
+        config::set_server_suppress_everyone(server_id, settings.suppress_everyone);
+        // channels without an override follow the server again
+        if let Some(server) = state.servers().iter().find(|s| s.id == server_id) {
+            for channel in &server.channels {
+                let overridden = settings
+                    .channel_overrides
+                    .iter()
+                    .any(|o| o.channel_id == channel.id);
+                if !overridden && config::channel_notify(channel.id).is_some() {
+                    config::set_channel_notify(channel.id, "");
+                }
+            }
+        }
+    }
+    for channel in &settings.channel_overrides {
+        config::set_channel_notify(channel.channel_id,
+                                   level_name(&channel.message_notifications));
+    }
+}
+
+// The notify_* tags of a message, which decide how (and if) WeeChat puts the
+// buffer on the hotlist
+pub fn tags(channel: &ChannelRef, mention: Mention) -> &'static str {
+    let is_private = match *channel {
+        ChannelRef::Public(_, _) => false,
+        _ => true,
+    };
+    let mentioned = match mention {
+        Mention::None => false,
+        Mention::Everyone => !suppress_everyone(channel),
+        Mention::Direct => true,
+    };
+    match (level(channel), mentioned) {
+        (Level::Nothing, _) => "notify_none,no_highlight",
+        (_, true) => "notify_highlight",
+        (Level::Mentions, false) => "notify_low",
+        (Level::All, false) if is_private => "notify_private",
+        (Level::All, false) => "notify_message",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use discord::ChannelRef;
+    use discord::model::{LiveServer, ServerId};
+    use serde_json;
+
+    use config;
+    use fixtures::{self, SERVER};
+    use mock_host;
+    use super::*;
+
+    #[test]
+    fn levels_fall_back_to_the_server_default() {
+        let _session = mock_host::setup();
+        let mut json = fixtures::server();
+        json["default_message_notifications"] = 1.into();
+        let server: LiveServer = unwrap1!(serde_json::from_value(json));
+        let channel = ChannelRef::Public(&server, &server.channels[0]);
+        assert_eq!(tags(&channel, Mention::None), "notify_low");
+        assert_eq!(tags(&channel, Mention::Direct), "notify_highlight");
+        config::set_server_notify(ServerId(SERVER), "nothing");
+        assert_eq!(tags(&channel, Mention::Direct), "notify_none,no_highlight");
+        config::set_server_notify(ServerId(SERVER), "all");
+        assert_eq!(tags(&channel, Mention::None), "notify_message");
+    }
+}
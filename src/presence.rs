@@ -0,0 +1,176 @@
+use std::sync::mpsc::Sender;
+use discord::model::{Game, OnlineStatus};
+use hyper::method::Method;
+use serde_json::{Map, Value};
+
+use config;
+use ffi::{BarItem, Buffer};
+use rest;
+
+pub const BAR_ITEM_NAME: &'static str = "weecord_status";
+
+// (game, status, afk), sent by the listening thread, which owns the gateway
+pub type PresenceUpdate = (Option<Game>, OnlineStatus, bool);
+
+pub fn status_name(status: OnlineStatus) -> &'static str {
+    match status {
+        OnlineStatus::Online => "online",
+        OnlineStatus::Idle => "idle",
+        OnlineStatus::DoNotDisturb => "dnd",
+        OnlineStatus::Invisible => "invisible",
+        OnlineStatus::Offline => "offline",
+    }
+}
+
+fn parse_status(name: &str) -> Option<OnlineStatus> {
+    match name {
+        "online" => Some(OnlineStatus::Online),
+        "idle" => Some(OnlineStatus::Idle),
+        "dnd" => Some(OnlineStatus::DoNotDisturb),
+        "invisible" => Some(OnlineStatus::Invisible),
+        _ => None,
+    }
+}
+
+fn patch_settings(token: &str, key: &str, value: Value) -> Result<(), String> {
+    let mut body = Map::new();
+    body.insert(key.into(), value);
+    rest::request(token,
+                  Method::Patch,
+                  "/users/@me/settings",
+                  Some(Value::Object(body)))
+        .map(|_| ())
+}
+
+pub struct OwnPresence {
+    status: OnlineStatus,
+    game: Option<String>,
+    // what to go back to when /away is unset, if we went idle because of it
+    before_away: Option<OnlineStatus>,
+    send: Sender<PresenceUpdate>,
+}
+
+impl OwnPresence {
+    pub fn new(status: OnlineStatus, send: Sender<PresenceUpdate>) -> OwnPresence {
+        OwnPresence {
+            status: status,
+            game: None,
+            before_away: None,
+            send: send,
+        }
+    }
+
+    // Only the listening thread can use discord-rs's Connection, and it's
+    // blocked until the next event. Saving the status, which Discord echoes
+    // back as USER_SETTINGS_UPDATE, makes sure there is one right away.
+    fn apply(&self, token: &str) -> Result<(), String> {
+        let game = self.game.as_ref().map(|x| Game::playing(x.clone()));
+        let afk = self.before_away.is_some();
+        let _ = self.send.send((game, self.status, afk));
+        BarItem::update(BAR_ITEM_NAME);
+        patch_settings(token, "status", Value::String(status_name(self.status).into()))
+    }
+
+    // Saved in the user settings too, so the official client agrees
+    fn set_status(&mut self, token: &str, status: OnlineStatus) -> Result<(), String> {
+        self.status = status;
+        self.apply(token)
+    }
+
+    pub fn status_command(&mut self, token: &str, buffer: &Buffer, args: &str) {
+        let args = args.trim();
+        if args.is_empty() {
+            buffer.print(&format!("Status: {}", self.bar_item()));
+            return;
+        }
+        let status = match parse_status(args) {
+            Some(status) => status,
+            None => {
+                buffer.print("Usage: /discord status online|idle|dnd|invisible");
+                return;
+            }
+        };
+        // an explicit status wins over the /away one
+        self.before_away = None;
+        match self.set_status(token, status) {
+            Ok(()) => buffer.print(&format!("Status set to {}", args)),
+            Err(err) => buffer.print(&format!("Status set for this session only: {}", err)),
+        }
+    }
+
+    pub fn game_command(&mut self, token: &str, buffer: &Buffer, args: &str) {
+        let args = args.trim();
+        self.game = if args.is_empty() {
+            None
+        } else {
+            Some(args.into())
+        };
+        if let Err(err) = self.apply(token) {
+            buffer.print(&format!("Game sent with the next gateway event: {}", err));
+        }
+        match self.game {
+            Some(ref game) => buffer.print(&format!("Now playing {}", game)),
+            None => buffer.print("Cleared game"),
+        }
+    }
+
+    pub fn custom_status_command(&self, token: &str, buffer: &Buffer, args: &str) {
+        let args = args.trim();
+        let value = if args.is_empty() {
+            Value::Null
+        } else {
+            let mut custom = Map::new();
+            custom.insert("text".into(), Value::String(args.into()));
+            Value::Object(custom)
+        };
+        match patch_settings(token, "custom_status", value) {
+            Ok(()) if args.is_empty() => buffer.print("Cleared custom status"),
+            Ok(()) => buffer.print(&format!("Custom status set to {}", args)),
+            Err(err) => buffer.print(&format!("Unable to set custom status: {}", err)),
+        }
+    }
+
+    // `/away [-all] [message]`, only with network.idle_on_away
+    pub fn on_away_command(&mut self, token: &str, command: &str) {
+        if !config::idle_on_away() {
+            return;
+        }
+        let args = command["/away".len()..].trim();
+        let args = if args.starts_with("-all") {
+            args["-all".len()..].trim()
+        } else {
+            args
+        };
+        let away = !args.is_empty();
+        let result = match (away, self.before_away) {
+            (true, None) if self.status == OnlineStatus::Online => {
+                self.before_away = Some(self.status);
+                self.set_status(token, OnlineStatus::Idle)
+            }
+            (false, Some(status)) => {
+                self.before_away = None;
+                self.set_status(token, status)
+            }
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            ::command_print(&format!("Unable to save status: {}", err));
+        }
+    }
+
+    // Changed from another client, or our own change coming back
+    pub fn on_settings_update(&mut self, status: OnlineStatus) {
+        if status != self.status {
+            self.status = status;
+            self.before_away = None;
+            BarItem::update(BAR_ITEM_NAME);
+        }
+    }
+
+    pub fn bar_item(&self) -> String {
+        match self.game {
+            Some(ref game) => format!("{} (playing {})", status_name(self.status), game),
+            None => status_name(self.status).into(),
+        }
+    }
+}
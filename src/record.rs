@@ -0,0 +1,394 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::spawn;
+use std::time::{SystemTime, UNIX_EPOCH};
+use discord::{Discord, State};
+use discord::model::{Event, GatewayEvent};
+use flate2::read::ZlibDecoder;
+use hyper::uri::RequestUri;
+use serde_json::{self, Map, Value};
+use websocket::{client, Client, DataFrame, Server};
+use websocket::Receiver as WsReceiver;
+use websocket::Sender as WsSender;
+use websocket::client::request::Url;
+use websocket::message::{Message, Type};
+use websocket::stream::WebSocketStream;
+
+use connection;
+use event_proc;
+
+// Raw gateway traffic, one JSON object per line:
+// {"time": <unix ms>, "from": "discord"|"client", "data": <payload>}
+pub struct Recording {
+    path: String,
+    file: Mutex<Option<File>>,
+    redact_contents: bool,
+}
+
+static mut RECORDING: *mut Arc<Recording> = 0 as *mut _;
+static mut REPLAYING: bool = false;
+
+// Recording only happens through a relay set up while connecting
+pub fn active() -> Option<Arc<Recording>> {
+    unsafe {
+        if RECORDING.is_null() {
+            None
+        } else {
+            Some((*RECORDING).clone())
+        }
+    }
+}
+
+// While a recording is played back, which must not reach Discord or the
+// message store
+pub fn replaying() -> bool {
+    unsafe { REPLAYING }
+}
+
+// Unsets REPLAYING however replay returns
+struct Replaying;
+
+impl Drop for Replaying {
+    fn drop(&mut self) {
+        unsafe {
+            REPLAYING = false;
+        }
+    }
+}
+
+pub fn start(path: &str, redact_contents: bool) -> Result<(), String> {
+    stop();
+    let file = File::create(path)
+        .map_err(|err| format!("Unable to create {}: {}", path, err))?;
+    let recording = Recording {
+        path: path.into(),
+        file: Mutex::new(Some(file)),
+        redact_contents: redact_contents,
+    };
+    unsafe {
+        RECORDING = Box::into_raw(Box::new(Arc::new(recording)));
+    }
+    Ok(())
+}
+
+// Returns the file that was being recorded to
+pub fn stop() -> Option<String> {
+    unsafe {
+        if RECORDING.is_null() {
+            return None;
+        }
+        let recording = Box::from_raw(RECORDING);
+        RECORDING = ::std::ptr::null_mut();
+        // relays outlive the recording, they just stop writing
+        *lock(&recording.file) = None;
+        Some(recording.path.clone())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl Recording {
+
+    fn write(&self, from: &str, message: &Message) {
+        let text = if message.opcode == Type::Binary {
+            // compressed payloads, READY among them
+            let mut text = String::new();
+            if ZlibDecoder::new(&message.payload[..])
+                   .read_to_string(&mut text)
+                   .is_err() {
+                return;
+            }
+            text
+        } else {
+            String::from_utf8_lossy(&message.payload).into_owned()
+        };
+        let mut data = match serde_json::from_str(&text) {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+        redact(&mut data, self.redact_contents);
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs() * 1000 + (x.subsec_nanos() / 1000000) as u64)
+            .unwrap_or(0);
+        let mut line = Map::new();
+        line.insert("time".into(), Value::from(time));
+        line.insert("from".into(), Value::String(from.into()));
+        line.insert("data".into(), data);
+        let line = Value::Object(line);
+        if let Some(ref mut file) = *lock(&self.file) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+fn redact(data: &mut Value, contents: bool) {
+    let op = data.get("op").and_then(|x| x.as_u64());
+    let kind = data.get("t").and_then(|x| x.as_str()).map(|x| x.to_owned());
+    let d = match data.get_mut("d").and_then(|x| x.as_object_mut()) {
+        Some(d) => d,
+        None => return,
+    };
+    // identify and resume
+    if (op == Some(2) || op == Some(6)) && d.contains_key("token") {
+        d.insert("token".into(), Value::String("REDACTED".into()));
+    }
+    if !contents {
+        return;
+    }
+    let kind = kind.unwrap_or_default();
+    if kind == "MESSAGE_CREATE" || kind == "MESSAGE_UPDATE" {
+        redact_strings(d, &["content"]);
+        if d.contains_key("embeds") {
+            d.insert("embeds".into(), Value::Array(Vec::new()));
+        }
+        if let Some(attachments) = d.get_mut("attachments").and_then(|x| x.as_array_mut()) {
+            for attachment in attachments.iter_mut().filter_map(|x| x.as_object_mut()) {
+                redact_strings(attachment, &["filename", "url", "proxy_url"]);
+            }
+        }
+    } else if kind == "READY" {
+        if let Some(user) = d.get_mut("user").and_then(|x| x.as_object_mut()) {
+            redact_strings(user, &["email", "phone"]);
+        }
+    }
+}
+
+fn redact_strings(data: &mut Map<String, Value>, keys: &[&str]) {
+    for key in keys {
+        if data.get(*key).map_or(false, |x| x.is_string()) {
+            data.insert((*key).into(), Value::String("REDACTED".into()));
+        }
+    }
+}
+
+// One side of a relayed connection, shared by its pump and the Relay, which
+// closes it
+type Outlet = Arc<Mutex<Box<FnMut(&Message) -> bool + Send>>>;
+
+fn outlet<S: WsSender + Send + 'static>(mut sender: S) -> Outlet {
+    let send: Box<FnMut(&Message) -> bool + Send> =
+        Box::new(move |message| sender.send_message(message).is_ok());
+    Arc::new(Mutex::new(send))
+}
+
+fn send(outlet: &Outlet, message: &Message) -> bool {
+    (&mut **lock(outlet))(message)
+}
+
+struct Links {
+    stopped: AtomicBool,
+    // (to the client, to Discord) of the latest (re)connect
+    current: Mutex<Option<(Outlet, Outlet)>>,
+}
+
+// A local gateway that passes everything on to the real one, recording as
+// it goes. Dropping it closes the connection and stops listening.
+pub struct Relay {
+    url: String,
+    addr: SocketAddr,
+    links: Arc<Links>,
+}
+
+impl Relay {
+    // For Connection::new
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.links.stopped.store(true, Ordering::SeqCst);
+        // wakes the thread up, so it notices
+        let _ = TcpStream::connect(self.addr);
+        if let Some((client, discord)) = lock(&self.links.current).take() {
+            send(&client, &Message::close());
+            send(&discord, &Message::close());
+        }
+    }
+}
+
+pub fn relay(gateway: &str, recording: Arc<Recording>) -> Result<Relay, String> {
+    let server = Server::bind("127.0.0.1:0").map_err(|err| format!("{}", err))?;
+    let addr = server.local_addr().map_err(|err| format!("{}", err))?;
+    let gateway = gateway.trim_right_matches('/').to_owned();
+    let links = Arc::new(Links {
+                             stopped: AtomicBool::new(false),
+                             current: Mutex::new(None),
+                         });
+    let thread_links = links.clone();
+    // one connection per (re)connect
+    spawn(move || for connection in server {
+              if thread_links.stopped.load(Ordering::SeqCst) {
+                  break;
+              }
+              let request = match connection.map(|x| x.read_request()) {
+                  Ok(Ok(request)) => request,
+                  _ => continue,
+              };
+              // discord-rs asks for ?v=6 and friends
+              let query = match request.url {
+                  RequestUri::AbsolutePath(ref path) => {
+                      path.find('?').map_or("", |idx| &path[idx..]).to_owned()
+                  }
+                  _ => String::new(),
+              };
+              let upstream = match connect(&format!("{}/{}", gateway, query)) {
+                  Ok(upstream) => upstream,
+                  Err(_) => continue,
+              };
+              let client = match request.accept().send() {
+                  Ok(client) => client,
+                  Err(_) => continue,
+              };
+              let (client_send, client_recv) = client.split();
+              let (upstream_send, upstream_recv) = upstream;
+              let (to_client, to_discord) = (outlet(client_send), outlet(upstream_send));
+              {
+                  let mut current = lock(&thread_links.current);
+                  // dropped while connecting
+                  if thread_links.stopped.load(Ordering::SeqCst) {
+                      send(&to_client, &Message::close());
+                      send(&to_discord, &Message::close());
+                      break;
+                  }
+                  *current = Some((to_client.clone(), to_discord.clone()));
+              }
+              let recording_client = recording.clone();
+              spawn(move || pump(client_recv, &to_discord, &recording_client, "client"));
+              let recording_discord = recording.clone();
+              spawn(move || pump(upstream_recv, &to_client, &recording_discord, "discord"));
+          });
+    Ok(Relay {
+           url: format!("ws://{}", addr),
+           addr: addr,
+           links: links,
+       })
+}
+
+fn connect(url: &str)
+           -> Result<(client::Sender<WebSocketStream>, client::Receiver<WebSocketStream>),
+                     String> {
+    let url = Url::parse(url).map_err(|err| format!("{}", err))?;
+    let request = Client::connect(url).map_err(|err| format!("{}", err))?;
+    let response = request.send().map_err(|err| format!("{}", err))?;
+    response.validate().map_err(|err| format!("{}", err))?;
+    Ok(response.begin().split())
+}
+
+fn pump<R: WsReceiver<DataFrame>>(mut from: R, to: &Outlet, recording: &Recording, source: &str) {
+    for message in from.incoming_messages() {
+        let message: Message = match message {
+            Ok(message) => message,
+            Err(_) => break,
+        };
+        if message.opcode == Type::Text || message.opcode == Type::Binary {
+            recording.write(source, &message);
+        }
+        let close = message.opcode == Type::Close;
+        if !send(to, &message) || close {
+            return;
+        }
+    }
+    // so the other side notices too
+    send(to, &Message::close());
+}
+
+// Feeds a recording through State::update and on_event (event_proc::update),
+// as if it was coming from Discord. Returns the number of events.
+pub fn replay(path: &str) -> Result<usize, String> {
+    let file = File::open(path)
+        .map_err(|err| format!("Unable to open {}: {}", path, err))?;
+    // never used to talk to Discord, on_event just wants one
+    let discord = Discord::from_user_token("replay").map_err(|err| format!("{}", err))?;
+    let mut state = None;
+    let mut count = 0;
+    unsafe {
+        REPLAYING = true;
+    }
+    let _replaying = Replaying;
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| format!("{}", err))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Value = serde_json::from_str(&line)
+            .map_err(|err| format!("line {}: {}", number + 1, err))?;
+        if entry["from"] != "discord" {
+            continue;
+        }
+        let event = match GatewayEvent::decode(entry["data"].clone()) {
+            Ok(GatewayEvent::Dispatch(_, event)) => event,
+            Ok(_) => continue,
+            Err(err) => return Err(format!("line {}: {}", number + 1, err)),
+        };
+        count += 1;
+        match event {
+            Event::Ready(ready) => {
+                let new_state = State::new(ready);
+                connection::open_buffers(&new_state, &discord);
+                state = Some(new_state);
+            }
+            event => {
+                if let Some(ref mut state) = state {
+                    event_proc::update(state, &discord, &event);
+                }
+            }
+        }
+    }
+    if state.is_none() {
+        return Err(format!("No READY in {}", path));
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::redact;
+
+    #[test]
+    fn redacts_tokens_and_contents() {
+        let mut identify = json!({ "op": 2, "d": { "token": "secret", "compress": true } });
+        redact(&mut identify, false);
+        assert_eq!(identify["d"]["token"], "REDACTED");
+        assert_eq!(identify["d"]["compress"], true);
+
+        let message = json!({
+            "op": 0,
+            "t": "MESSAGE_CREATE",
+            "d": {
+                "content": "hi",
+                "embeds": [{ "title": "x" }],
+                "attachments": [{ "filename": "cat.png", "url": "https://x/cat.png", "size": 1 }],
+            },
+        });
+        let mut kept = message.clone();
+        redact(&mut kept, false);
+        assert_eq!(kept, message);
+        let mut redacted = message;
+        redact(&mut redacted, true);
+        assert_eq!(redacted["d"]["content"], "REDACTED");
+        assert_eq!(redacted["d"]["embeds"], json!([]));
+        assert_eq!(redacted["d"]["attachments"][0]["filename"], "REDACTED");
+        assert_eq!(redacted["d"]["attachments"][0]["url"], "REDACTED");
+        assert_eq!(redacted["d"]["attachments"][0]["size"], 1);
+
+        let mut ready = json!({
+            "op": 0,
+            "t": "READY",
+            "d": { "user": { "username": "me", "email": "me@x", "phone": null } },
+        });
+        redact(&mut ready, true);
+        assert_eq!(ready["d"]["user"]["email"], "REDACTED");
+        assert_eq!(ready["d"]["user"]["phone"], Value::Null);
+        assert_eq!(ready["d"]["user"]["username"], "me");
+    }
+}
@@ -0,0 +1,108 @@
+use discord::{ChannelRef, State};
+use discord::model::User;
+
+use config;
+use connection::ChannelData;
+use ffi::Buffer;
+use types::*;
+use util::{split_arg, tag};
+
+const USAGE: &'static str = "Usage: /discord rename <@user|#channel|server|@role> <new name>, \
+                             or /discord rename -clear <target>";
+
+// Matches either the displayed name (which may already be renamed) or the
+// name Discord has for it
+fn matches<T: Name>(thing: &T, name: &str) -> bool {
+    thing.name(&NameFormat::none()) == name || thing.name_internal().1 == name
+}
+
+fn find_user<'a>(users: &mut Iterator<Item = &'a User>, name: &str) -> Option<&'a User> {
+    users.find(|u| matches(*u, name))
+}
+
+// (raw id, the user if it is one, description for the user)
+fn resolve(state: &State,
+           channel: Option<ChannelRef>,
+           target: &str)
+           -> Result<(u64, Option<User>, String), String> {
+    let server = match channel {
+        Some(ChannelRef::Public(server, _)) => Some(server),
+        _ => None,
+    };
+    if target.starts_with('#') {
+        let name = &target[1..];
+        let server = server.ok_or("#channel renames must be run from a server channel buffer")?;
+        return server
+                   .channels
+                   .iter()
+                   .find(|c| matches(*c, name))
+                   .map(|c| (c.id.0, None, format!("channel #{}", c.name)))
+                   .ok_or_else(|| format!("Channel not found: {}", target));
+    }
+    if target.starts_with('@') {
+        let name = &target[1..];
+        let user = match channel {
+            Some(ChannelRef::Public(server, _)) => {
+                server
+                    .members
+                    .iter()
+                    .find(|m| matches(*m, name) || matches(&m.user, name))
+                    .map(|m| &m.user)
+            }
+            Some(ChannelRef::Group(group)) => find_user(&mut group.recipients.iter(), name),
+            Some(ChannelRef::Private(private)) => {
+                find_user(&mut Some(&private.recipient).into_iter(), name)
+            }
+            None => None,
+        };
+        if let Some(user) = user {
+            return Ok((user.id.0, Some(user.clone()), format!("user {}", tag(user))));
+        }
+        if let Some(server) = server {
+            if let Some(role) = server.roles.iter().find(|r| matches(*r, name)) {
+                return Ok((role.id.0, None, format!("role @{}", role.name)));
+            }
+        }
+        return Err(format!("User or role not found: {}", target));
+    }
+    if let Some(found) = state.servers().iter().find(|s| matches(*s, target)) {
+        return Ok((found.id.0, None, format!("server {}", found.name)));
+    }
+    match server {
+        Some(server) if target == "server" => {
+            Ok((server.id.0, None, format!("server {}", server.name)))
+        }
+        _ => Err(format!("Server not found: {}", target)),
+    }
+}
+
+pub fn rename_command(state: &State, buffer: &Buffer, args: &str) {
+    // "-clearer" is a target like any other
+    let (first, rest) = split_arg(args);
+    let (clear, args) = if first == "-clear" {
+        (true, rest)
+    } else {
+        (false, args)
+    };
+    let (target, new_name) = split_arg(args);
+    if target.is_empty() || clear != new_name.is_empty() {
+        buffer.print(USAGE);
+        return;
+    }
+    let channel = ChannelData::from_buffer_impl(state, buffer);
+    match resolve(state, channel, &target) {
+        Ok((id, user, description)) => {
+            // re-syncs titles, nicklists and mentions
+            match user {
+                Some(user) => config::set_user_rename(&user, new_name),
+                None => config::set_rename(id, new_name),
+            };
+            if clear {
+                buffer.print(&format!("Cleared the rename of {}", description));
+            } else {
+                buffer.print(&format!("Renamed {} to {}", description, new_name));
+            }
+        }
+        Err(err) => buffer.print(&err),
+    }
+}
@@ -0,0 +1,202 @@
+use std::io::{Cursor, Read};
+use std::thread::sleep;
+use std::time::Duration;
+use hyper;
+use hyper::client::Body;
+use hyper::header::{Authorization, ContentType};
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use discord::model::{ChannelId, Message, MessageId, PrivateChannel, UserId};
+use serde_json::{self, Map, Value};
+
+use config;
+
+// Endpoints discord-rs doesn't wrap, and the ones that need to follow
+// weecord.network.api_url
+pub const DEFAULT_API_URL: &'static str = "https://discordapp.com/api/v6";
+
+const MAX_RETRIES: usize = 3;
+
+// On the main thread, where a 429 is reported right away so WeeChat doesn't
+// hang
+pub fn request(token: &str,
+               method: Method,
+               path: &str,
+               body: Option<Value>)
+               -> Result<Value, String> {
+    request_to(&config::api_url(), token, method, path, body, 0)
+}
+
+// From any thread: 429s of up to max_wait_ms are waited out
+pub fn request_to(api_url: &str,
+                  token: &str,
+                  method: Method,
+                  path: &str,
+                  body: Option<Value>,
+                  max_wait_ms: u64)
+                  -> Result<Value, String> {
+    let client = hyper::Client::new();
+    let url = format!("{}{}", api_url, path);
+    let body = body.map(|x| x.to_string());
+    let mut retries = 0;
+    loop {
+        let mut request = client
+            .request(method.clone(), &url)
+            .header(Authorization(token.to_owned()))
+            .header(ContentType::json());
+        if let Some(ref body) = body {
+            request = request.body(&**body);
+        }
+        let mut response = request.send().map_err(|err| format!("{}", err))?;
+        let mut text = String::new();
+        response
+            .read_to_string(&mut text)
+            .map_err(|err| format!("{}", err))?;
+        if response.status == StatusCode::TooManyRequests {
+            let retry_after = serde_json::from_str::<Value>(&text)
+                .ok()
+                .and_then(|x| x.get("retry_after").and_then(|x| x.as_u64()))
+                .unwrap_or(u64::max_value());
+            if retries < MAX_RETRIES && retry_after <= max_wait_ms {
+                retries += 1;
+                sleep(Duration::from_millis(retry_after));
+                continue;
+            }
+            if retry_after == u64::max_value() {
+                return Err("rate limited".into());
+            }
+            return Err(format!("rate limited, try again in {:.1}s",
+                               retry_after as f64 / 1000.0));
+        }
+        if !response.status.is_success() {
+            return Err(format!("{}: {}", response.status, text));
+        }
+        return if text.is_empty() {
+                   Ok(Value::Null)
+               } else {
+                   serde_json::from_str(&text).map_err(|err| format!("{}", err))
+               };
+    }
+}
+
+// Where to open the websocket
+pub fn gateway(token: &str) -> Result<String, String> {
+    let response = request(token, Method::Get, "/gateway", None)?;
+    match response.get("url").and_then(|x| x.as_str()) {
+        Some(url) => Ok(url.into()),
+        None => Err(format!("no gateway url in {}", response)),
+    }
+}
+
+pub fn send_message(token: &str, channel: ChannelId, content: &str) -> Result<(), String> {
+    send_message_to(&config::api_url(), token, channel, content, 0)
+}
+
+// See request_to
+pub fn send_message_to(api_url: &str,
+                       token: &str,
+                       channel: ChannelId,
+                       content: &str,
+                       max_wait_ms: u64)
+                       -> Result<(), String> {
+    let mut body = Map::new();
+    body.insert("content".into(), Value::String(content.into()));
+    body.insert("tts".into(), Value::Bool(false));
+    request_to(api_url,
+               token,
+               Method::Post,
+               &format!("/channels/{}/messages", channel.0),
+               Some(Value::Object(body)),
+               max_wait_ms)
+        .map(|_| ())
+}
+
+// See request_to
+pub fn get_message(api_url: &str,
+                   token: &str,
+                   channel: ChannelId,
+                   message: MessageId,
+                   max_wait_ms: u64)
+                   -> Result<Message, String> {
+    let response = request_to(api_url,
+                              token,
+                              Method::Get,
+                              &format!("/channels/{}/messages/{}", channel.0, message.0),
+                              None,
+                              max_wait_ms)?;
+    serde_json::from_value(response).map_err(|err| format!("{}", err))
+}
+
+// The most recent ones, newest first
+pub fn get_messages(api_url: &str,
+                    token: &str,
+                    channel: ChannelId,
+                    limit: u64,
+                    max_wait_ms: u64)
+                    -> Result<Vec<Message>, String> {
+    let response = request_to(api_url,
+                              token,
+                              Method::Get,
+                              &format!("/channels/{}/messages?limit={}", channel.0, limit),
+                              None,
+                              max_wait_ms)?;
+    serde_json::from_value(response).map_err(|err| format!("{}", err))
+}
+
+// A message with a file, sent as multipart/form-data while it's read. Not
+// retried, the file can only be read once.
+pub fn upload_file<R: Read>(api_url: &str,
+                            token: &str,
+                            channel: ChannelId,
+                            content: &str,
+                            filename: &str,
+                            file: R,
+                            size: u64)
+                            -> Result<(), String> {
+    const BOUNDARY: &'static str = "weecord-upload-boundary";
+    let head = format!("--{0}\r\n\
+                        Content-Disposition: form-data; name=\"content\"\r\n\r\n\
+                        {1}\r\n\
+                        --{0}\r\n\
+                        Content-Disposition: form-data; name=\"tts\"\r\n\r\n\
+                        false\r\n\
+                        --{0}\r\n\
+                        Content-Disposition: form-data; name=\"file\"; filename=\"{2}\"\r\n\
+                        Content-Type: application/octet-stream\r\n\r\n",
+                       BOUNDARY,
+                       content,
+                       filename.replace('"', "'"));
+    let tail = format!("\r\n--{}--\r\n", BOUNDARY);
+    let length = head.len() as u64 + size + tail.len() as u64;
+    let mut body = Cursor::new(head.into_bytes())
+        .chain(file.take(size))
+        .chain(Cursor::new(tail.into_bytes()));
+    let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+    let client = hyper::Client::new();
+    let mut response = client
+        .post(&format!("{}/channels/{}/messages", api_url, channel.0))
+        .header(Authorization(token.to_owned()))
+        .header(ContentType(content_type
+                                .parse()
+                                .map_err(|_| "invalid content type".to_owned())?))
+        .body(Body::SizedBody(&mut body, length))
+        .send()
+        .map_err(|err| format!("{}", err))?;
+    let mut text = String::new();
+    let _ = response.read_to_string(&mut text);
+    match response.status {
+        StatusCode::TooManyRequests => Err("rate limited".into()),
+        status if !status.is_success() => Err(format!("{}: {}", status, text)),
+        _ => Ok(()),
+    }
+}
+
+pub fn create_private_channel(token: &str, user: UserId) -> Result<PrivateChannel, String> {
+    let mut body = Map::new();
+    body.insert("recipient_id".into(), Value::String(format!("{}", user.0)));
+    let response = request(token,
+                           Method::Post,
+                           "/users/@me/channels",
+                           Some(Value::Object(body)))?;
+    serde_json::from_value(response).map_err(|err| format!("{}", err))
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use discord::model::{ChannelId, MessageId, UserId};
+use serde_json::{self, Map, Value};
+
+use cache::{self, CachedMessage};
+use config;
+use ffi::info_get;
+use record;
+
+// Recent messages on disk (weecord.network.message_store), so edits and
+// deletes after a restart still know what they're about. An append-only log
+// of JSON lines, {"set": <message>} or {"delete": <id>}, that's cut down to
+// the limits when it's loaded, so rewriting it never holds up WeeChat in
+// the middle of a session. Only the user can read it.
+static mut STORE: *mut File = 0 as *mut _;
+
+pub fn path() -> PathBuf {
+    let dir = info_get("weechat_data_dir", "")
+        .or_else(|| info_get("weechat_dir", ""))
+        .unwrap_or_else(|| ".".into());
+    Path::new(&dir).join("weecord_messages.jsonl")
+}
+
+// Discord ids start with milliseconds since 2015
+pub fn timestamp(id: MessageId) -> u64 {
+    ((id.0 >> 22) + 1420070400000) / 1000
+}
+
+// Loads the stored messages into the cache and appends from then on.
+// Returns how many were loaded.
+pub fn start() -> Result<usize, String> {
+    stop();
+    let messages = open()?;
+    for &(id, ref message) in &messages {
+        cache::insert(id, message.clone());
+    }
+    Ok(messages.len())
+}
+
+// Compacts the file and appends to it from then on
+fn open() -> Result<Vec<(MessageId, CachedMessage)>, String> {
+    let path = path();
+    let messages = load(&path, config::message_store_max(), config::message_store_days())?;
+    compact(&path, &messages)?;
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(&path)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    unsafe {
+        STORE = Box::into_raw(Box::new(file));
+    }
+    Ok(messages)
+}
+
+pub fn stop() {
+    unsafe {
+        if !STORE.is_null() {
+            let _ = Box::from_raw(STORE);
+            STORE = ::std::ptr::null_mut();
+        }
+    }
+}
+
+// New or edited
+pub fn save(id: MessageId, message: &CachedMessage) {
+    let mut line = Map::new();
+    line.insert("set".into(), to_json(id, message));
+    append(Value::Object(line));
+}
+
+pub fn forget(id: MessageId) {
+    let mut line = Map::new();
+    line.insert("delete".into(), Value::String(id.0.to_string()));
+    append(Value::Object(line));
+}
+
+fn append(line: Value) {
+    unsafe {
+        // replayed messages may well be redacted
+        if STORE.is_null() || record::replaying() {
+            return;
+        }
+        let _ = writeln!(*STORE, "{}", line);
+    }
+}
+
+// Oldest first, at most max per channel and none older than days
+fn load(path: &Path, max: usize, days: u64) -> Result<Vec<(MessageId, CachedMessage)>, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut messages = HashMap::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|err| format!("{}: {}", path.display(), err))?;
+        // a line cut short by a crash is skipped, not fatal
+        let line: Value = match serde_json::from_str(&line) {
+            Ok(line) => line,
+            Err(_) => continue,
+        };
+        if let Some((id, message)) = line.get("set").and_then(from_json) {
+            messages.insert(id, message);
+        } else if let Some(id) = line.get("delete").and_then(parse_id) {
+            messages.remove(&MessageId(id));
+        }
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let mut messages = messages
+        .into_iter()
+        .filter(|&(id, _)| days == 0 || timestamp(id) + days * 24 * 60 * 60 >= now)
+        .collect::<Vec<_>>();
+    // newest first, to count per channel
+    messages.sort_by(|a, b| b.0.cmp(&a.0));
+    let mut counts = HashMap::new();
+    messages.retain(|&(_, ref message)| {
+                        let count = counts.entry(message.channel_id).or_insert(0);
+                        *count += 1;
+                        *count <= max
+                    });
+    messages.reverse();
+    Ok(messages)
+}
+
+fn compact(path: &Path, messages: &[(MessageId, CachedMessage)]) -> Result<(), String> {
+    let temp = path.with_extension("jsonl.tmp");
+    let error = |err: ::std::io::Error| format!("{}: {}", temp.display(), err);
+    {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp)
+            .map_err(&error)?;
+        for &(id, ref message) in messages {
+            let mut line = Map::new();
+            line.insert("set".into(), to_json(id, message));
+            writeln!(file, "{}", Value::Object(line)).map_err(&error)?;
+        }
+    }
+    fs::rename(&temp, path).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// Ids as strings, like Discord does
+fn to_json(id: MessageId, message: &CachedMessage) -> Value {
+    let mut data = Map::new();
+    data.insert("id".into(), Value::String(id.0.to_string()));
+    data.insert("channel_id".into(),
+                Value::String(message.channel_id.0.to_string()));
+    data.insert("author_id".into(),
+                Value::String(message.author_id.0.to_string()));
+    data.insert("author".into(), Value::String(message.author.clone()));
+    data.insert("content".into(), Value::String(message.content.clone()));
+    data.insert("attachments".into(),
+                Value::Array(message
+                                 .attachments
+                                 .iter()
+                                 .map(|x| Value::String(x.clone()))
+                                 .collect()));
+    Value::Object(data)
+}
+
+fn from_json(data: &Value) -> Option<(MessageId, CachedMessage)> {
+    let attachments = tryopt!(data.get("attachments").and_then(|x| x.as_array()));
+    let message = CachedMessage {
+        channel_id: ChannelId(tryopt!(data.get("channel_id").and_then(parse_id))),
+        author_id: UserId(tryopt!(data.get("author_id").and_then(parse_id))),
+        author: tryopt!(data.get("author").and_then(|x| x.as_str())).into(),
+        content: tryopt!(data.get("content").and_then(|x| x.as_str())).into(),
+        attachments: attachments
+            .iter()
+            .filter_map(|x| x.as_str().map(|x| x.to_owned()))
+            .collect(),
+    };
+    Some((MessageId(tryopt!(data.get("id").and_then(parse_id))), message))
+}
+
+fn parse_id(value: &Value) -> Option<u64> {
+    value.as_str().and_then(|x| x.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use discord::model::{ChannelId, MessageId, UserId};
+
+    use cache::CachedMessage;
+    use mock_host;
+    use super::*;
+
+    fn cached(channel: u64, content: &str) -> CachedMessage {
+        CachedMessage {
+            channel_id: ChannelId(channel),
+            author_id: UserId(2),
+            author: "alice".into(),
+            content: content.into(),
+            attachments: Vec::new(),
+        }
+    }
+
+    // an id from seconds since 2015
+    fn id(seconds: u64, n: u64) -> MessageId {
+        MessageId((seconds * 1000) << 22 | n)
+    }
+
+    #[test]
+    fn loads_within_the_limits() {
+        let path = env::temp_dir().join("weecord_store_test.jsonl");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0) - 1420070400;
+        let lines = vec![save_line(id(now - 365 * 24 * 60 * 60, 0), cached(1, "ancient")),
+                         save_line(id(now - 60, 1), cached(1, "first")),
+                         save_line(id(now - 50, 2), cached(1, "second")),
+                         save_line(id(now - 40, 3), cached(1, "deleted")),
+                         save_line(id(now - 30, 4), cached(2, "elsewhere")),
+                         save_line(id(now - 20, 5), cached(1, "latest")),
+                         format!("{{\"delete\": \"{}\"}}", id(now - 40, 3).0),
+                         // cut short
+                         "{\"set\": {\"id\": ".into()];
+        {
+            let mut file = unwrap1!(File::create(&path));
+            unwrap1!(writeln!(file, "{}", lines.join("\n")));
+        }
+        let messages = unwrap1!(load(&path, 2, 30));
+        let contents = messages.iter().map(|x| &*x.1.content).collect::<Vec<_>>();
+        assert_eq!(contents, vec!["second", "elsewhere", "latest"]);
+        unwrap1!(compact(&path, &messages));
+        assert_eq!(unwrap1!(load(&path, 2, 0)), messages);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn compacts_on_load() {
+        let _session = mock_host::setup();
+        unwrap1!(start());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0) - 1420070400;
+        // edited over and over
+        for _ in 0..10 {
+            save(id(now, 0), &cached(1, "hello"));
+        }
+        assert_eq!(unwrap1!(start()), 1);
+        let mut contents = String::new();
+        unwrap1!(unwrap1!(File::open(path())).read_to_string(&mut contents));
+        assert_eq!(contents.lines().count(), 1);
+        let mode = unwrap1!(fs::metadata(path())).permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    fn save_line(id: MessageId, message: CachedMessage) -> String {
+        let mut line = Map::new();
+        line.insert("set".into(), to_json(id, &message));
+        Value::Object(line).to_string()
+    }
+}
@@ -0,0 +1,353 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::*;
+use std::thread::spawn;
+use discord::model::{Attachment, ChannelId, Message, MessageId};
+use hyper;
+
+use ffi::*;
+use config;
+use rest;
+use util::split_arg;
+
+// Transfer threads wait out 429s up to this long
+const MAX_WAIT_MS: u64 = 10000;
+
+// A line of output from a transfer thread, to be printed in the buffer named
+// `buffer` (if it still exists) on the main thread.
+struct TransferEvent {
+    buffer: String,
+    message: String,
+}
+
+// Uploads and downloads run on their own threads so weechat doesn't hang,
+// but buffers can only be touched from the main thread. Every transfer
+// thread reports back through this one channel.
+pub struct Transfers {
+    send: Sender<TransferEvent>,
+    recv: Receiver<TransferEvent>,
+    poke_fd: PokeableFd,
+}
+
+impl Transfers {
+    pub fn new<F: FnMut() + 'static>(on_poke: F) -> Transfers {
+        let (send, recv) = channel();
+        Transfers {
+            send: send,
+            recv: recv,
+            poke_fd: PokeableFd::new(on_poke),
+        }
+    }
+
+    pub fn on_poke(&mut self) {
+        while let Ok(event) = self.recv.try_recv() {
+            match Buffer::search(&event.buffer) {
+                Some(buffer) => buffer.print(&event.message),
+                None => MAIN_BUFFER.print(&event.message),
+            }
+        }
+    }
+
+    fn reporter(&self, buffer: &Buffer) -> Reporter {
+        Reporter {
+            buffer: buffer.get("name").unwrap_or_default(),
+            send: self.send.clone(),
+            poker: self.poke_fd.get_poker(),
+        }
+    }
+
+    pub fn upload<F: Fn(&str) -> String>(&self,
+                                         token: &str,
+                                         buffer: &Buffer,
+                                         channel_id: ChannelId,
+                                         args: &str,
+                                         format_comment: F) {
+        let (path, comment) = split_arg(args);
+        if path.is_empty() {
+            buffer.print("Usage: /discord upload <path> [comment]");
+            return;
+        }
+        let path = expand_home(&path);
+        let size = match path.metadata() {
+            Ok(ref meta) if meta.is_file() => meta.len(),
+            Ok(_) => {
+                buffer.print(&format!("Not a file: {}", path.display()));
+                return;
+            }
+            Err(err) => {
+                buffer.print(&format!("Unable to read {}: {}", path.display(), err));
+                return;
+            }
+        };
+        let limit = config::upload_limit();
+        if size > limit {
+            buffer.print(&format!("{} is {}, larger than the upload limit of {} \
+                                   (weecord.network.upload_limit)",
+                                  path.display(),
+                                  format_size(size),
+                                  format_size(limit)));
+            return;
+        }
+        let filename = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => {
+                buffer.print(&format!("Not a file: {}", path.display()));
+                return;
+            }
+        };
+        let comment = format_comment(comment);
+        let api_url = config::api_url();
+        let token = token.to_owned();
+        let reporter = self.reporter(buffer);
+        reporter.report(format!("Uploading {} ({})...", filename, format_size(size)));
+        spawn(move || {
+            let result = File::open(&path).map_err(|err| format!("{}", err)).and_then(|file| {
+                let reader = ProgressReader {
+                    inner: file,
+                    name: &filename,
+                    total: size,
+                    done: 0,
+                    next_report: 25,
+                    reporter: &reporter,
+                };
+                rest::upload_file(&api_url, &token, channel_id, &comment, &filename, reader, size)
+            });
+            match result {
+                Ok(_) => reporter.report(format!("Uploaded {}", filename)),
+                Err(err) => reporter.report(format!("Upload of {} failed: {}", filename, err)),
+            }
+        });
+    }
+
+    pub fn download(&self, token: &str, buffer: &Buffer, channel_id: ChannelId, args: &str) {
+        let (msgref, args) = split_arg(args);
+        let msgref = match MessageRef::parse(&msgref) {
+            Some(msgref) => msgref,
+            None => {
+                buffer.print("Usage: /discord download <message_id|^[N]> [dir]");
+                return;
+            }
+        };
+        // quoted like upload paths, for directories with spaces
+        let (dir, _) = split_arg(args);
+        let dir = if dir.is_empty() {
+            download_dir()
+        } else {
+            expand_home(&dir)
+        };
+        let api_url = config::api_url();
+        let token = token.to_owned();
+        let reporter = self.reporter(buffer);
+        spawn(move || {
+            let message = match msgref.fetch(&api_url, &token, channel_id) {
+                Ok(message) => message,
+                Err(err) => return reporter.report(format!("Download failed: {}", err)),
+            };
+            if let Err(err) = fs::create_dir_all(&dir) {
+                return reporter.report(format!("Unable to create {}: {}", dir.display(), err));
+            }
+            for attachment in &message.attachments {
+                match download_attachment(attachment, &dir) {
+                    Ok(path) => {
+                        reporter.report(format!("Downloaded {} ({}) to {}",
+                                                attachment.filename,
+                                                format_size(attachment.size),
+                                                path.display()))
+                    }
+                    Err(err) => {
+                        reporter.report(format!("Download of {} failed: {}",
+                                                attachment.filename,
+                                                err))
+                    }
+                }
+            }
+        });
+    }
+}
+
+enum MessageRef {
+    Id(MessageId),
+    // ^N: the Nth most recent message in the channel that has attachments
+    Recent(usize),
+}
+
+impl MessageRef {
+    fn parse(arg: &str) -> Option<MessageRef> {
+        if arg.starts_with('^') {
+            if arg.len() == 1 {
+                Some(MessageRef::Recent(1))
+            } else {
+                arg[1..].parse().ok().and_then(|n| if n > 0 {
+                                                  Some(MessageRef::Recent(n))
+                                              } else {
+                                                  None
+                                              })
+            }
+        } else {
+            arg.parse().ok().map(MessageId).map(MessageRef::Id)
+        }
+    }
+
+    fn fetch(&self, api_url: &str, token: &str, channel_id: ChannelId) -> Result<Message, String> {
+        let message = match *self {
+            MessageRef::Id(id) => rest::get_message(api_url, token, channel_id, id, MAX_WAIT_MS)?,
+            MessageRef::Recent(n) => {
+                let messages = rest::get_messages(api_url, token, channel_id, 100, MAX_WAIT_MS)?;
+                match messages.into_iter().filter(|m| !m.attachments.is_empty()).nth(n - 1) {
+                    Some(message) => message,
+                    None => return Err("no such message with attachments".into()),
+                }
+            }
+        };
+        if message.attachments.is_empty() {
+            Err(format!("message {} has no attachments", message.id.0))
+        } else {
+            Ok(message)
+        }
+    }
+}
+
+fn download_dir() -> PathBuf {
+    if let Some(dir) = config::download_dir() {
+        return expand_home(&dir);
+    }
+    let weechat_dir = info_get("weechat_dir", "").unwrap_or_else(|| ".".into());
+    Path::new(&weechat_dir).join("weecord_downloads")
+}
+
+fn download_attachment(attachment: &Attachment, dir: &Path) -> Result<PathBuf, String> {
+    let client = hyper::Client::new();
+    let mut response = client
+        .get(&attachment.url)
+        .send()
+        .map_err(|err| format!("{}", err))?;
+    if !response.status.is_success() {
+        return Err(format!("{}", response.status));
+    }
+    let (path, mut file) = create_unique(dir, &attachment.filename)
+        .map_err(|err| format!("{}", err))?;
+    if let Err(err) = io::copy(&mut response, &mut file) {
+        // don't leave half a file behind
+        let _ = fs::remove_file(&path);
+        return Err(format!("{}", err));
+    }
+    Ok(path)
+}
+
+// Never overwrites: foo.png, then foo (1).png, foo (2).png, ...
+fn create_unique(dir: &Path, filename: &str) -> io::Result<(PathBuf, File)> {
+    // attachment names come from other users, don't let them pick the directory
+    let filename = Path::new(filename)
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "attachment".into());
+    let (stem, ext) = match filename.rfind('.') {
+        Some(idx) if idx > 0 => (&filename[..idx], &filename[idx..]),
+        _ => (&*filename, ""),
+    };
+    let mut index = 0;
+    loop {
+        let path = if index == 0 {
+            dir.join(&filename)
+        } else {
+            dir.join(format!("{} ({}){}", stem, index, ext))
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => index += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+struct Reporter {
+    buffer: String,
+    send: Sender<TransferEvent>,
+    poker: PokeableFdPoker,
+}
+
+impl Reporter {
+    fn report(&self, message: String) {
+        let event = TransferEvent {
+            buffer: self.buffer.clone(),
+            message: message,
+        };
+        // the receiver is gone if we disconnected, nobody to tell
+        if self.send.send(event).is_ok() {
+            self.poker.poke();
+        }
+    }
+}
+
+// Reports every quarter of the file as it's read by the multipart writer.
+struct ProgressReader<'a, R: Read> {
+    inner: R,
+    name: &'a str,
+    total: u64,
+    done: u64,
+    next_report: u64,
+    reporter: &'a Reporter,
+}
+
+impl<'a, R: Read> Read for ProgressReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.done += count as u64;
+        if self.total > 0 && self.next_report < 100 &&
+           self.done * 100 / self.total >= self.next_report {
+            self.reporter.report(format!("Uploading {}: {}%", self.name, self.next_report));
+            self.next_report += 25;
+        }
+        Ok(count)
+    }
+}
+
+pub fn expand_home(path: &str) -> PathBuf {
+    if path.starts_with("~/") {
+        if let Some(home) = env::var_os("HOME") {
+            return Path::new(&home).join(&path[2..]);
+        }
+    }
+    PathBuf::from(path)
+}
+
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&'static str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", size, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use discord::model::ChannelId;
+    use hyper::method::Method;
+
+    use fake_discord::FakeDiscord;
+    use fixtures::{self, GENERAL};
+    use rest;
+
+    #[test]
+    fn uploads_follow_the_api_url() {
+        let fake = FakeDiscord::start(fixtures::ready_json());
+        unwrap1!(rest::upload_file(&fake.api_url,
+                                   "token",
+                                   ChannelId(GENERAL),
+                                   "hi",
+                                   "hello.txt",
+                                   &b"hello"[..],
+                                   5));
+        let call = unwrap!(fake.calls().pop());
+        assert_eq!((call.method, &*call.path), (Method::Post, "/channels/11/messages"));
+    }
+}
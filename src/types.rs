@@ -5,10 +5,7 @@ use discord::model::{User, Role, Emoji, Server, PrivateChannel, PublicChannel, C
 use discord::model::{UserId, RoleId, EmojiId, ServerId, ChannelId};
 use discord::model::{Member, CurrentUser, LiveServer, Mention, Group};
 use ffi;
-
-fn get_rename_option<Id: DiscordId>(id: &Id) -> Option<String> {
-    ffi::get_option(&format!("rename.{}", id.raw_id()))
-}
+use config;
 
 pub trait Mentionable {
     fn mention_tr(&self) -> Mention;
@@ -201,7 +198,7 @@ impl NameFormat {
         }
     }
 
-    fn format(&self, prefix: &str, name: &str) -> String {
+    pub fn format(&self, prefix: &str, name: &str) -> String {
         let (left, right): (Cow<str>, &str) = if self.include_color {
                 ffi::info_get("nick_color", name).map(|color| (color.into(), "\u{1c}"))
             } else {
@@ -216,10 +213,12 @@ impl NameFormat {
 pub trait Name: Id {
     // (prefix, raw_name)
     fn name_internal(&self) -> (&'static str, Cow<str>);
+    fn rename(&self) -> Option<String> {
+        config::rename(&self.id())
+    }
     fn name(&self, fmt: &NameFormat) -> String {
         let (prefix, raw_name) = self.name_internal();
-        let rename = get_rename_option(&self.id());
-        let name: Cow<str> = rename.map_or(raw_name, |x| x.into());
+        let name: Cow<str> = self.rename().map_or(raw_name, |x| x.into());
         fmt.format(prefix, &name)
     }
 }
@@ -228,6 +227,9 @@ impl Name for User {
     fn name_internal(&self) -> (&'static str, Cow<str>) {
         ("@", Cow::Borrowed(&*self.name))
     }
+    fn rename(&self) -> Option<String> {
+        config::user_rename(self)
+    }
 }
 
 impl Name for Member {
@@ -237,6 +239,9 @@ impl Name for Member {
             .as_ref()
             .map_or_else(|| self.user.name_internal(), |n| ("@", Cow::Borrowed(&**n)))
     }
+    fn rename(&self) -> Option<String> {
+        self.user.rename()
+    }
 }
 
 impl Name for CurrentUser {
@@ -269,11 +274,13 @@ impl Name for Role {
     }
 }
 
-static MAX_GROUP_LEN: usize = 16;
-
 impl Name for Group {
     fn name_internal(&self) -> (&'static str, Cow<str>) {
-        ("&", Cow::Owned(self.name().chars().take(MAX_GROUP_LEN).collect()))
+        let name = self.name();
+        match config::max_group_len() {
+            0 => ("&", Cow::Owned(name.into_owned())),
+            max => ("&", Cow::Owned(name.chars().take(max).collect())),
+        }
     }
 }
 
@@ -30,3 +30,35 @@ impl ServerExt for LiveServer {
         self.find_member(user_id).map(|m| &m.user)
     }
 }
+
+// name#1234
+pub fn tag(user: &User) -> String {
+    format!("{}#{:04}", user.name, user.discriminator)
+}
+
+// Splits off the first argument, which may be quoted to contain spaces:
+// `"some thing" rest` or `thing rest`
+pub fn split_arg(args: &str) -> (String, &str) {
+    let args = args.trim_left();
+    if args.starts_with('"') {
+        if let Some(end) = args[1..].find('"') {
+            return (args[1..end + 1].into(), args[end + 2..].trim());
+        }
+    }
+    match args.find(' ') {
+        Some(idx) => (args[..idx].into(), args[idx + 1..].trim()),
+        None => (args.into(), ""),
+    }
+}
+
+// The closest color of xterm's 6x6x6 cube, as a WeeChat color number
+pub fn xterm_color(rgb: u64) -> u8 {
+    const LEVELS: [u64; 6] = [0, 95, 135, 175, 215, 255];
+    let nearest = |value: u64| {
+        (0..LEVELS.len())
+            .min_by_key(|&i| (LEVELS[i] as i64 - value as i64).abs())
+            .unwrap_or(0) as u8
+    };
+    let (r, g, b) = ((rgb >> 16) & 0xff, (rgb >> 8) & 0xff, rgb & 0xff);
+    16 + 36 * nearest(r) + 6 * nearest(g) + nearest(b)
+}
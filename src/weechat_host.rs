@@ -0,0 +1,1134 @@
+use libc::*;
+use std::any::Any;
+use std::ffi::*;
+
+use ffi::{really_bad, wrap_panic, BarItem, Buffer, Completion, ConfigFile, ConfigOption,
+          ConfigSection, CreateOptionFn, DeleteOptionFn, Hook, Nick, CONFIG_OPTION_SET_ERROR};
+use host::{Host, Line};
+
+// From weechat-plugin.h, for the delete option callback
+const CONFIG_OPTION_UNSET_OK_REMOVED: c_int = 2;
+
+// The real thing, through the wdc_* shims in weecord.c
+pub struct WeechatHost;
+
+struct WeechatAny {
+    data: *mut c_void,
+    hdata: *mut c_void,
+}
+
+struct SharedString(String);
+
+fn strip_indexer_field(field: &str) -> &str {
+    if let Some(idx) = field.find('|') {
+        &field[(idx + 1)..]
+    } else {
+        field
+    }
+}
+
+impl WeechatAny {
+    fn buffer(buffer: &Buffer) -> WeechatAny {
+        WeechatAny {
+            data: buffer.ptr(),
+            hdata: hdata_get("buffer"),
+        }
+    }
+
+    fn get<T: HDataGetResult>(&self, field: &str) -> Option<T> {
+        let field_type = T::weechat_type();
+        if field_type != "" {
+            let actual_type = hdata_get_var_type_string(self.hdata, field);
+            if field_type != actual_type {
+                really_bad(format!("Field {} had type {} but we expected {}",
+                                   field,
+                                   actual_type,
+                                   field_type));
+            }
+        }
+        T::new(self, field)
+    }
+
+    fn get_idx<T: HDataGetResult>(&self, field: &str, index: usize) -> Option<T> {
+        self.get(&format!("{}|{}", index, field))
+    }
+
+    fn get_any(&self, field: &str) -> Option<WeechatAny> {
+        self.get(field)
+    }
+}
+
+trait HDataGetResult: Sized {
+    fn new(parent: &WeechatAny, field: &str) -> Option<Self>;
+    fn weechat_type() -> &'static str;
+}
+
+impl HDataGetResult for WeechatAny {
+    fn new(parent: &WeechatAny, field: &str) -> Option<Self> {
+        let data = tryopt!(hdata_pointer(parent.hdata, parent.data, field));
+        let hdata_name = hdata_get_var_hdata(parent.hdata, field);
+        Some(WeechatAny {
+                 data: data,
+                 hdata: hdata_get(&hdata_name),
+             })
+    }
+
+    fn weechat_type() -> &'static str {
+        "pointer"
+    }
+}
+
+impl HDataGetResult for String {
+    fn new(parent: &WeechatAny, field: &str) -> Option<Self> {
+        hdata_string(parent.hdata, parent.data, field)
+    }
+
+    fn weechat_type() -> &'static str {
+        "string"
+    }
+}
+
+impl HDataGetResult for SharedString {
+    fn new(parent: &WeechatAny, field: &str) -> Option<Self> {
+        hdata_string(parent.hdata, parent.data, field).map(SharedString)
+    }
+
+    fn weechat_type() -> &'static str {
+        "shared_string"
+    }
+}
+
+impl HDataGetResult for i32 {
+    fn new(parent: &WeechatAny, field: &str) -> Option<Self> {
+        hdata_integer(parent.hdata, parent.data, field).map(|x| x as i32)
+    }
+
+    fn weechat_type() -> &'static str {
+        "integer"
+    }
+}
+
+fn hdata_get(name: &str) -> *mut c_void {
+    extern "C" {
+        fn wdc_hdata_get(name: *const c_char) -> *mut c_void;
+    }
+    unsafe {
+        let name_c = unwrap1!(CString::new(name));
+        let data = wdc_hdata_get(name_c.as_ptr());
+        if data.is_null() {
+            really_bad(format!("hdata name {} was invalid", name));
+        }
+        data
+    }
+}
+
+fn hdata_pointer(hdata: *mut c_void, obj: *mut c_void, name: &str) -> Option<*mut c_void> {
+    extern "C" {
+        fn wdc_hdata_pointer(hdata: *mut c_void,
+                             obj: *mut c_void,
+                             name: *const c_char)
+                             -> *mut c_void;
+    }
+    unsafe {
+        let name = unwrap1!(CString::new(name));
+        let result = wdc_hdata_pointer(hdata, obj, name.as_ptr());
+        if result.is_null() { None } else { Some(result) }
+    }
+}
+
+fn hdata_get_var_hdata(hdata: *mut c_void, name: &str) -> String {
+    extern "C" {
+        fn wdc_hdata_get_var_hdata(hdata: *mut c_void, name: *const c_char) -> *const c_char;
+    }
+    let name = strip_indexer_field(name);
+    unsafe {
+        let name_c = unwrap1!(CString::new(name));
+        let result = wdc_hdata_get_var_hdata(hdata, name_c.as_ptr());
+        if result.is_null() {
+            really_bad(format!("hdata field {} hdata was invalid", name));
+        }
+        CStr::from_ptr(result).to_string_lossy().into_owned()
+    }
+}
+
+fn hdata_get_var_type_string(hdata: *mut c_void, name: &str) -> String {
+    extern "C" {
+        fn wdc_hdata_get_var_type_string(hdata: *mut c_void, name: *const c_char) -> *const c_char;
+    }
+    let name = strip_indexer_field(name);
+    unsafe {
+        let name_c = unwrap1!(CString::new(name));
+        let result = wdc_hdata_get_var_type_string(hdata, name_c.as_ptr());
+        if result.is_null() {
+            really_bad(format!("hdata field {} type was invalid", name));
+        }
+        CStr::from_ptr(result).to_string_lossy().into_owned()
+    }
+}
+
+fn hdata_integer(hdata: *mut c_void, data: *mut c_void, name: &str) -> Option<c_int> {
+    extern "C" {
+        fn wdc_hdata_integer(hdata: *mut c_void, data: *mut c_void, name: *const c_char) -> c_int;
+    }
+    unsafe {
+        let name = unwrap1!(CString::new(name));
+        Some(wdc_hdata_integer(hdata, data, name.as_ptr()))
+    }
+}
+
+fn hdata_string(hdata: *mut c_void, data: *mut c_void, name: &str) -> Option<String> {
+    extern "C" {
+        fn wdc_hdata_string(hdata: *mut c_void,
+                            data: *mut c_void,
+                            name: *const c_char)
+                            -> *const c_char;
+    }
+    unsafe {
+        let name = unwrap1!(CString::new(name));
+        let result = wdc_hdata_string(hdata, data, name.as_ptr());
+        if result.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(result).to_string_lossy().into_owned())
+        }
+    }
+}
+
+fn c_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned())
+    }
+}
+
+fn line_data(data: &WeechatAny) -> Line {
+    let tagcount: i32 = unwrap!(data.get("tags_count"));
+    let tags = (0..tagcount as usize)
+        .map(|i| unwrap!(data.get_idx::<SharedString>("tags_array", i)).0)
+        .collect();
+    Line {
+        prefix: data.get::<SharedString>("prefix").map(|x| x.0).unwrap_or_default(),
+        message: data.get("message").unwrap_or_default(),
+        tags: tags,
+    }
+}
+
+impl Host for WeechatHost {
+    fn buffer_new(&self, name: &str, on_input: fn(Buffer, &str)) -> Option<Buffer> {
+        extern "C" {
+            fn wdc_buffer_new(name: *const c_char,
+                              pointer: *const c_void,
+                              input_callback: extern "C" fn(*const c_void,
+                                                            *mut c_void,
+                                                            *mut c_void,
+                                                            *const c_char)
+                                                            -> c_int,
+                              close_callback: extern "C" fn(*const c_void,
+                                                            *mut c_void,
+                                                            *mut c_void)
+                                                            -> c_int)
+                              -> *mut c_void;
+        }
+        extern "C" fn input_cb(pointer: *const c_void,
+                               data: *mut c_void,
+                               buffer: *mut c_void,
+                               input_data: *const c_char)
+                               -> c_int {
+            let _ = data;
+            wrap_panic(|| {
+                let buffer = Buffer::from_ptr(buffer);
+                let on_input: fn(Buffer, &str) = unsafe { ::std::mem::transmute(pointer) };
+                let input_data = unsafe { CStr::from_ptr(input_data).to_str() };
+                let input_data = match input_data {
+                    Ok(x) => x,
+                    Err(_) => return,
+                };
+                on_input(buffer, input_data);
+            });
+            0
+        }
+        extern "C" fn close_cb(pointer: *const c_void,
+                               data: *mut c_void,
+                               buffer: *mut c_void)
+                               -> c_int {
+            let _ = pointer;
+            let _ = data;
+            wrap_panic(|| ::nicklist::forget(&Buffer::from_ptr(buffer)));
+            0
+        }
+        unsafe {
+            let name = unwrap1!(CString::new(name));
+            let pointer = on_input as *const c_void;
+            let result = wdc_buffer_new(name.as_ptr(), pointer, input_cb, close_cb);
+            if result.is_null() {
+                None
+            } else {
+                Some(Buffer::from_ptr(result))
+            }
+        }
+    }
+
+    fn buffer_search(&self, name: &str) -> Option<Buffer> {
+        extern "C" {
+            fn wdc_buffer_search(name: *const c_char) -> *mut c_void;
+        }
+        unsafe {
+            let name_c = unwrap1!(CString::new(name));
+            let result = wdc_buffer_search(name_c.as_ptr());
+            if result.is_null() {
+                None
+            } else {
+                Some(Buffer::from_ptr(result))
+            }
+        }
+    }
+
+    fn buffer_close(&self, buffer: &Buffer) {
+        extern "C" {
+            fn wdc_buffer_close(buffer: *mut c_void);
+        }
+        unsafe {
+            wdc_buffer_close(buffer.ptr());
+        }
+    }
+
+    fn buffer_clear(&self, buffer: &Buffer) {
+        extern "C" {
+            fn wdc_buffer_clear(buffer: *mut c_void);
+        }
+        unsafe {
+            wdc_buffer_clear(buffer.ptr());
+        }
+    }
+
+    fn buffer_set(&self, buffer: &Buffer, property: &str, value: &str) {
+        extern "C" {
+            fn wdc_buffer_set(buffer: *mut c_void, property: *const c_char, value: *const c_char);
+        }
+        unsafe {
+            let property = unwrap1!(CString::new(property));
+            let value = unwrap1!(CString::new(value));
+            wdc_buffer_set(buffer.ptr(), property.as_ptr(), value.as_ptr());
+        }
+    }
+
+    fn buffer_get(&self, buffer: &Buffer, property: &str) -> Option<String> {
+        extern "C" {
+            fn wdc_buffer_get(buffer: *mut c_void, property: *const c_char) -> *const c_char;
+        }
+        unsafe {
+            let property = unwrap1!(CString::new(property));
+            c_string(wdc_buffer_get(buffer.ptr(), property.as_ptr()))
+        }
+    }
+
+    fn buffer_lines(&self, buffer: &Buffer) -> Vec<Line> {
+        let mut result = Vec::new();
+        let mut line = unwrap!(WeechatAny::buffer(buffer).get_any("lines")).get_any("first_line");
+        while let Some(current) = line {
+            result.push(line_data(&unwrap!(current.get_any("data"))));
+            line = current.get_any("next_line");
+        }
+        result
+    }
+
+    fn print(&self, buffer: &Buffer, message: &str) {
+        extern "C" {
+            fn wdc_print(buffer: *mut c_void, message: *const c_char);
+        }
+        unsafe {
+            let msg = unwrap1!(CString::new(message));
+            wdc_print(buffer.ptr(), msg.as_ptr());
+        }
+    }
+
+    fn print_tags(&self, buffer: &Buffer, tags: &str, message: &str) {
+        extern "C" {
+            fn wdc_print_tags(buffer: *mut c_void, tags: *const c_char, message: *const c_char);
+        }
+        unsafe {
+            let msg = unwrap1!(CString::new(message));
+            let tags = unwrap1!(CString::new(tags));
+            wdc_print_tags(buffer.ptr(), tags.as_ptr(), msg.as_ptr());
+        }
+    }
+
+    fn print_date_tags(&self, buffer: &Buffer, date: i64, tags: &str, message: &str) {
+        extern "C" {
+            fn wdc_print_date_tags(buffer: *mut c_void,
+                                   date: time_t,
+                                   tags: *const c_char,
+                                   message: *const c_char);
+        }
+        unsafe {
+            let msg = unwrap1!(CString::new(message));
+            let tags = unwrap1!(CString::new(tags));
+            wdc_print_date_tags(buffer.ptr(), date as time_t, tags.as_ptr(), msg.as_ptr());
+        }
+    }
+
+    fn nick_add(&self, buffer: &Buffer, nick: &str, color: &str) -> Option<Nick> {
+        extern "C" {
+            fn wdc_nicklist_add_nick(buffer: *const c_void,
+                                     nick: *const c_char,
+                                     color: *const c_char)
+                                     -> *mut c_void;
+        }
+        unsafe {
+            let nick = unwrap1!(CString::new(nick));
+            let color = unwrap1!(CString::new(color));
+            let result = wdc_nicklist_add_nick(buffer.ptr(), nick.as_ptr(), color.as_ptr());
+            if result.is_null() {
+                None
+            } else {
+                Some(Nick::from_ptr(result))
+            }
+        }
+    }
+
+    fn nick_remove(&self, buffer: &Buffer, nick: &Nick) {
+        extern "C" {
+            fn wdc_nicklist_remove_nick(buffer: *const c_void, nick: *const c_void);
+        }
+        unsafe {
+            wdc_nicklist_remove_nick(buffer.ptr(), nick.ptr());
+        }
+    }
+
+    fn nick_remove_all(&self, buffer: &Buffer) {
+        extern "C" {
+            fn wdc_nicklist_remove_all(buffer: *const c_void);
+        }
+        unsafe {
+            wdc_nicklist_remove_all(buffer.ptr());
+        }
+    }
+
+    fn hook_command(&self,
+                    command: &str,
+                    description: &str,
+                    args: &str,
+                    args_description: &str,
+                    completion: &str,
+                    callback: Box<FnMut(Buffer, &str)>)
+                    -> Option<Hook> {
+        type CB = FnMut(Buffer, &str);
+        extern "C" {
+            fn wdc_hook_command(command: *const c_char,
+                                description: *const c_char,
+                                args: *const c_char,
+                                args_description: *const c_char,
+                                completion: *const c_char,
+                                pointer: *const c_void,
+                                callback: extern "C" fn(*const c_void,
+                                                        *mut c_void,
+                                                        *mut c_void,
+                                                        c_int,
+                                                        *mut *mut c_char,
+                                                        *mut *mut c_char)
+                                                        -> c_int)
+                                -> *mut c_void;
+        }
+        extern "C" fn callback_fn(pointer: *const c_void,
+                                  data: *mut c_void,
+                                  buffer: *mut c_void,
+                                  argc: c_int,
+                                  argv: *mut *mut c_char,
+                                  argv_eol: *mut *mut c_char)
+                                  -> c_int {
+            let _ = data;
+            let _ = argv;
+            wrap_panic(|| {
+                let pointer = pointer as *mut Box<CB>;
+                let buffer = Buffer::from_ptr(buffer);
+                if argc <= 1 {
+                    (unsafe { &mut **pointer })(buffer, "");
+                    return;
+                }
+                let args = unsafe { *argv_eol.offset(1) };
+                let args = unsafe { CStr::from_ptr(args).to_str() };
+                let args = match args {
+                    Ok(x) => x,
+                    Err(_) => return,
+                };
+                (unsafe { &mut **pointer })(buffer, args);
+            });
+            0
+        }
+        unsafe {
+            let command = unwrap1!(CString::new(command));
+            let description = unwrap1!(CString::new(description));
+            let args = unwrap1!(CString::new(args));
+            let args_description = unwrap1!(CString::new(args_description));
+            let completion = unwrap1!(CString::new(completion));
+            let callback: Box<Box<CB>> = Box::new(callback);
+            let pointer = &*callback as *const _ as *const c_void;
+            let hook = wdc_hook_command(command.as_ptr(),
+                                        description.as_ptr(),
+                                        args.as_ptr(),
+                                        args_description.as_ptr(),
+                                        completion.as_ptr(),
+                                        pointer,
+                                        callback_fn);
+            if hook.is_null() {
+                None
+            } else {
+                Some(Hook::new(hook, Some(callback as Box<Any>)))
+            }
+        }
+    }
+
+    fn hook_command_run(&self, command: &str, callback: Box<FnMut(Buffer, &str)>)
+                        -> Option<Hook> {
+        type CB = FnMut(Buffer, &str);
+        extern "C" {
+            fn wdc_hook_command_run(command: *const c_char,
+                                    pointer: *const c_void,
+                                    callback: extern "C" fn(*const c_void,
+                                                            *mut c_void,
+                                                            *mut c_void,
+                                                            *const c_char)
+                                                            -> c_int)
+                                    -> *mut c_void;
+        }
+        extern "C" fn callback_fn(pointer: *const c_void,
+                                  data: *mut c_void,
+                                  buffer: *mut c_void,
+                                  command: *const c_char)
+                                  -> c_int {
+            let _ = data;
+            wrap_panic(|| {
+                let pointer = pointer as *mut Box<CB>;
+                let buffer = Buffer::from_ptr(buffer);
+                let command = unsafe { CStr::from_ptr(command).to_str() };
+                if let Ok(command) = command {
+                    (unsafe { &mut **pointer })(buffer, command);
+                }
+            });
+            0
+        }
+        unsafe {
+            let command = unwrap1!(CString::new(command));
+            let callback: Box<Box<CB>> = Box::new(callback);
+            let pointer = &*callback as *const _ as *const c_void;
+            let hook = wdc_hook_command_run(command.as_ptr(), pointer, callback_fn);
+            if hook.is_null() {
+                None
+            } else {
+                Some(Hook::new(hook, Some(callback as Box<Any>)))
+            }
+        }
+    }
+
+    fn hook_completion(&self,
+                       name: &str,
+                       description: &str,
+                       callback: Box<Fn(Buffer, Completion)>)
+                       -> Option<Hook> {
+        type CB = Fn(Buffer, Completion);
+        extern "C" {
+            fn wdc_hook_completion(completion_item: *const c_char,
+                                   description: *const c_char,
+                                   callback_pointer: *const c_void,
+                                   callback: extern "C" fn(*const c_void,
+                                                           *mut c_void,
+                                                           *const c_char,
+                                                           *mut c_void,
+                                                           *mut c_void)
+                                                           -> c_int)
+                                   -> *mut c_void;
+        }
+        extern "C" fn callback_fn(pointer: *const c_void,
+                                  data: *mut c_void,
+                                  completion_item: *const c_char,
+                                  buffer: *mut c_void,
+                                  completion: *mut c_void)
+                                  -> c_int {
+            let _ = data;
+            let _ = completion_item;
+            wrap_panic(|| {
+                           let buffer = Buffer::from_ptr(buffer);
+                           let completion = Completion::from_ptr(completion);
+                           let pointer = pointer as *const Box<CB>;
+                           (unsafe { &**pointer })(buffer, completion);
+                       });
+            0
+        }
+        let callback: Box<Box<CB>> = Box::new(callback);
+        unsafe {
+            let name_c = unwrap1!(CString::new(name));
+            let description_c = unwrap1!(CString::new(description));
+            let pointer = &*callback as *const _ as *const c_void;
+            let result = wdc_hook_completion(name_c.as_ptr(),
+                                             description_c.as_ptr(),
+                                             pointer,
+                                             callback_fn);
+            if result.is_null() {
+                None
+            } else {
+                Some(Hook::new(result, Some(callback as Box<Any>)))
+            }
+        }
+    }
+
+    fn completion_add(&self, completion: &Completion, word: &str) {
+        extern "C" {
+            fn wdc_hook_completion_add(gui_completion: *const c_void, word: *const c_char);
+        }
+        unsafe {
+            let word_c = unwrap1!(CString::new(word));
+            wdc_hook_completion_add(completion.ptr(), word_c.as_ptr());
+        }
+    }
+
+    fn hook_fd(&self, fd: c_int, callback: Box<FnMut()>) -> Option<Hook> {
+        extern "C" {
+            fn wdc_hook_fd(fd: c_int,
+                           pointer: *const c_void,
+                           callback: extern "C" fn(*const c_void, *mut c_void, c_int) -> c_int)
+                           -> *mut c_void;
+        }
+        extern "C" fn callback_fn(pointer: *const c_void, data: *mut c_void, fd: c_int) -> c_int {
+            let _ = data;
+            let _ = fd;
+            wrap_panic(|| {
+                           let callback = pointer as *mut Box<FnMut()>;
+                           (unsafe { &mut **callback })();
+                       });
+            0
+        }
+        let callback: Box<Box<FnMut()>> = Box::new(callback);
+        let hook = unsafe {
+            // haha screw you borrowck
+            let pointer = &*callback as *const _ as *const c_void;
+            wdc_hook_fd(fd, pointer, callback_fn)
+        };
+        if hook.is_null() {
+            None
+        } else {
+            Some(Hook::new(hook, Some(callback as Box<Any>)))
+        }
+    }
+
+    fn hook_signal(&self, signal: &str, callback: Box<FnMut(*mut c_void)>) -> Option<Hook> {
+        extern "C" {
+            fn wdc_hook_signal(signal: *const c_char,
+                               pointer: *const c_void,
+                               callback: extern "C" fn(*const c_void,
+                                                       *mut c_void,
+                                                       *const c_char,
+                                                       *const c_char,
+                                                       *mut c_void)
+                                                       -> c_int)
+                               -> *mut c_void;
+        }
+        extern "C" fn callback_fn(pointer: *const c_void,
+                                  data: *mut c_void,
+                                  signal: *const c_char,
+                                  type_data: *const c_char,
+                                  signal_data: *mut c_void)
+                                  -> c_int {
+            let _ = (data, signal, type_data);
+            wrap_panic(|| {
+                           let callback = pointer as *mut Box<FnMut(*mut c_void)>;
+                           (unsafe { &mut **callback })(signal_data);
+                       });
+            0
+        }
+        let callback: Box<Box<FnMut(*mut c_void)>> = Box::new(callback);
+        let signal = unwrap1!(CString::new(signal));
+        let hook = unsafe {
+            let pointer = &*callback as *const _ as *const c_void;
+            wdc_hook_signal(signal.as_ptr(), pointer, callback_fn)
+        };
+        if hook.is_null() {
+            None
+        } else {
+            Some(Hook::new(hook, Some(callback as Box<Any>)))
+        }
+    }
+
+    fn hook_timer(&self, interval_ms: i64, callback: Box<FnMut()>) -> Option<Hook> {
+        extern "C" {
+            fn wdc_hook_timer(interval: c_long,
+                              pointer: *const c_void,
+                              callback: extern "C" fn(*const c_void, *mut c_void, c_int) -> c_int)
+                              -> *mut c_void;
+        }
+        extern "C" fn callback_fn(pointer: *const c_void,
+                                  data: *mut c_void,
+                                  remaining_calls: c_int)
+                                  -> c_int {
+            let _ = data;
+            let _ = remaining_calls;
+            wrap_panic(|| {
+                           let callback = pointer as *mut Box<FnMut()>;
+                           (unsafe { &mut **callback })();
+                       });
+            0
+        }
+        let callback: Box<Box<FnMut()>> = Box::new(callback);
+        let hook = unsafe {
+            let pointer = &*callback as *const _ as *const c_void;
+            wdc_hook_timer(interval_ms as c_long, pointer, callback_fn)
+        };
+        if hook.is_null() {
+            None
+        } else {
+            Some(Hook::new(hook, Some(callback as Box<Any>)))
+        }
+    }
+
+    fn unhook(&self, hook: &Hook) {
+        extern "C" {
+            fn wdc_unhook(hook: *mut c_void);
+        }
+        unsafe {
+            wdc_unhook(hook.ptr());
+        }
+    }
+
+    fn bar_item_new(&self, name: &str, callback: Box<Fn() -> String>) -> Option<BarItem> {
+        type CB = Fn() -> String;
+        extern "C" {
+            fn wdc_bar_item_new(name: *const c_char,
+                                pointer: *const c_void,
+                                callback: extern "C" fn(*const c_void,
+                                                        *mut c_void,
+                                                        *mut c_void,
+                                                        *mut c_void,
+                                                        *mut c_void,
+                                                        *mut c_void)
+                                                        -> *mut c_char)
+                                -> *mut c_void;
+        }
+        extern "C" fn callback_fn(pointer: *const c_void,
+                                  data: *mut c_void,
+                                  item: *mut c_void,
+                                  window: *mut c_void,
+                                  buffer: *mut c_void,
+                                  extra_info: *mut c_void)
+                                  -> *mut c_char {
+            let _ = (data, item, window, buffer, extra_info);
+            let result = wrap_panic(|| {
+                                        let pointer = pointer as *const Box<CB>;
+                                        (unsafe { &**pointer })()
+                                    });
+            let result = CString::new(result.unwrap_or_default()).unwrap_or_default();
+            // weechat frees the result
+            unsafe { strdup(result.as_ptr()) }
+        }
+        unsafe {
+            let name = unwrap1!(CString::new(name));
+            let callback: Box<Box<CB>> = Box::new(callback);
+            let pointer = &*callback as *const _ as *const c_void;
+            let item = wdc_bar_item_new(name.as_ptr(), pointer, callback_fn);
+            if item.is_null() {
+                None
+            } else {
+                Some(BarItem::from_ptr(item, Some(callback as Box<Any>)))
+            }
+        }
+    }
+
+    fn bar_item_update(&self, name: &str) {
+        extern "C" {
+            fn wdc_bar_item_update(name: *const c_char);
+        }
+        unsafe {
+            let name = unwrap1!(CString::new(name));
+            wdc_bar_item_update(name.as_ptr());
+        }
+    }
+
+    fn bar_item_remove(&self, item: &BarItem) {
+        extern "C" {
+            fn wdc_bar_item_remove(item: *mut c_void);
+        }
+        unsafe {
+            wdc_bar_item_remove(item.ptr());
+        }
+    }
+
+    fn config_new(&self, name: &str, on_reload: fn(&ConfigFile) -> c_int) -> Option<ConfigFile> {
+        extern "C" {
+            fn wdc_config_new(name: *const c_char,
+                              pointer: *const c_void,
+                              callback: extern "C" fn(*const c_void, *mut c_void, *mut c_void)
+                                                      -> c_int)
+                              -> *mut c_void;
+        }
+        extern "C" fn reload_cb(pointer: *const c_void,
+                                data: *mut c_void,
+                                config_file: *mut c_void)
+                                -> c_int {
+            let _ = data;
+            let result = wrap_panic(|| {
+                let on_reload: fn(&ConfigFile) -> c_int = unsafe { ::std::mem::transmute(pointer) };
+                let file = ConfigFile::from_ptr(config_file);
+                let result = on_reload(&file);
+                // not ours to free
+                ::std::mem::forget(file);
+                result
+            });
+            result.unwrap_or(-1)
+        }
+        unsafe {
+            let name = unwrap1!(CString::new(name));
+            let result = wdc_config_new(name.as_ptr(), on_reload as *const c_void, reload_cb);
+            if result.is_null() {
+                None
+            } else {
+                Some(ConfigFile::from_ptr(result))
+            }
+        }
+    }
+
+    fn config_new_section(&self,
+                          file: &ConfigFile,
+                          name: &str,
+                          dynamic: Option<(CreateOptionFn, DeleteOptionFn)>)
+                          -> Option<ConfigSection> {
+        type CreateCb = extern "C" fn(*const c_void,
+                                      *mut c_void,
+                                      *mut c_void,
+                                      *mut c_void,
+                                      *const c_char,
+                                      *const c_char)
+                                      -> c_int;
+        type DeleteCb = extern "C" fn(*const c_void,
+                                      *mut c_void,
+                                      *mut c_void,
+                                      *mut c_void,
+                                      *mut c_void)
+                                      -> c_int;
+        extern "C" {
+            fn wdc_config_new_section(config_file: *mut c_void,
+                                      name: *const c_char,
+                                      pointer: *const c_void,
+                                      create_cb: Option<CreateCb>,
+                                      delete_cb: Option<DeleteCb>)
+                                      -> *mut c_void;
+        }
+        extern "C" fn create_cb(pointer: *const c_void,
+                                data: *mut c_void,
+                                config_file: *mut c_void,
+                                section: *mut c_void,
+                                option_name: *const c_char,
+                                value: *const c_char)
+                                -> c_int {
+            let _ = data;
+            let _ = config_file;
+            let result = wrap_panic(|| {
+                let &(create, _) =
+                    unsafe { &*(pointer as *const (CreateOptionFn, DeleteOptionFn)) };
+                let name = c_string(option_name).unwrap_or_default();
+                let value = c_string(value).unwrap_or_default();
+                create(ConfigSection::from_ptr(section), &name, &value)
+            });
+            result.unwrap_or(CONFIG_OPTION_SET_ERROR)
+        }
+        extern "C" fn delete_cb(pointer: *const c_void,
+                                data: *mut c_void,
+                                config_file: *mut c_void,
+                                section: *mut c_void,
+                                option: *mut c_void)
+                                -> c_int {
+            let _ = data;
+            let _ = config_file;
+            wrap_panic(|| {
+                let &(_, delete) =
+                    unsafe { &*(pointer as *const (CreateOptionFn, DeleteOptionFn)) };
+                delete(ConfigSection::from_ptr(section), ConfigOption::from_ptr(option));
+            });
+            CONFIG_OPTION_UNSET_OK_REMOVED
+        }
+        unsafe {
+            let name = unwrap1!(CString::new(name));
+            // sections live as long as the plugin, so the callbacks can too
+            let (pointer, create, delete) = match dynamic {
+                Some(dynamic) => {
+                    (Box::into_raw(Box::new(dynamic)) as *const c_void,
+                     Some(create_cb as CreateCb),
+                     Some(delete_cb as DeleteCb))
+                }
+                None => (::std::ptr::null(), None, None),
+            };
+            let result = wdc_config_new_section(file.ptr(), name.as_ptr(), pointer, create, delete);
+            if result.is_null() {
+                None
+            } else {
+                Some(ConfigSection::from_ptr(result))
+            }
+        }
+    }
+
+    fn config_new_option(&self,
+                         file: &ConfigFile,
+                         section: ConfigSection,
+                         name: &str,
+                         kind: &str,
+                         description: &str,
+                         string_values: &str,
+                         min: i32,
+                         max: i32,
+                         default: &str,
+                         value: &str,
+                         on_change: Option<fn(ConfigOption)>)
+                         -> Option<ConfigOption> {
+        type ChangeCb = extern "C" fn(*const c_void, *mut c_void, *mut c_void);
+        extern "C" {
+            fn wdc_config_new_option(config_file: *mut c_void,
+                                     section: *mut c_void,
+                                     name: *const c_char,
+                                     kind: *const c_char,
+                                     description: *const c_char,
+                                     string_values: *const c_char,
+                                     min: c_int,
+                                     max: c_int,
+                                     default_value: *const c_char,
+                                     value: *const c_char,
+                                     pointer: *const c_void,
+                                     change_cb: Option<ChangeCb>)
+                                     -> *mut c_void;
+        }
+        extern "C" fn change_cb(pointer: *const c_void, data: *mut c_void, option: *mut c_void) {
+            let _ = data;
+            wrap_panic(|| {
+                let on_change: fn(ConfigOption) = unsafe { ::std::mem::transmute(pointer) };
+                on_change(ConfigOption::from_ptr(option));
+            });
+        }
+        unsafe {
+            let name = unwrap1!(CString::new(name));
+            let kind = unwrap1!(CString::new(kind));
+            let description = unwrap1!(CString::new(description));
+            let string_values = unwrap1!(CString::new(string_values));
+            let default = unwrap1!(CString::new(default));
+            let value = unwrap1!(CString::new(value));
+            let (pointer, change) = match on_change {
+                Some(on_change) => (on_change as *const c_void, Some(change_cb as ChangeCb)),
+                None => (::std::ptr::null(), None),
+            };
+            let result = wdc_config_new_option(file.ptr(),
+                                               section.ptr(),
+                                               name.as_ptr(),
+                                               kind.as_ptr(),
+                                               description.as_ptr(),
+                                               string_values.as_ptr(),
+                                               min,
+                                               max,
+                                               default.as_ptr(),
+                                               value.as_ptr(),
+                                               pointer,
+                                               change);
+            if result.is_null() {
+                None
+            } else {
+                Some(ConfigOption::from_ptr(result))
+            }
+        }
+    }
+
+    fn config_search_option(&self,
+                            file: &ConfigFile,
+                            section: ConfigSection,
+                            name: &str)
+                            -> Option<ConfigOption> {
+        extern "C" {
+            fn wdc_config_search_option(config_file: *mut c_void,
+                                        section: *mut c_void,
+                                        name: *const c_char)
+                                        -> *mut c_void;
+        }
+        unsafe {
+            let name = unwrap1!(CString::new(name));
+            let result = wdc_config_search_option(file.ptr(), section.ptr(), name.as_ptr());
+            if result.is_null() {
+                None
+            } else {
+                Some(ConfigOption::from_ptr(result))
+            }
+        }
+    }
+
+    fn config_read(&self, file: &ConfigFile) -> c_int {
+        extern "C" {
+            fn wdc_config_read(config_file: *mut c_void) -> c_int;
+        }
+        unsafe { wdc_config_read(file.ptr()) }
+    }
+
+    fn config_reload(&self, file: &ConfigFile) -> c_int {
+        extern "C" {
+            fn wdc_config_reload(config_file: *mut c_void) -> c_int;
+        }
+        unsafe { wdc_config_reload(file.ptr()) }
+    }
+
+    fn config_write(&self, file: &ConfigFile) -> c_int {
+        extern "C" {
+            fn wdc_config_write(config_file: *mut c_void) -> c_int;
+        }
+        unsafe { wdc_config_write(file.ptr()) }
+    }
+
+    fn config_free(&self, file: &ConfigFile) {
+        extern "C" {
+            fn wdc_config_free(config_file: *mut c_void);
+        }
+        unsafe {
+            wdc_config_free(file.ptr());
+        }
+    }
+
+    fn config_section_free_options(&self, section: ConfigSection) {
+        extern "C" {
+            fn wdc_config_section_free_options(section: *mut c_void);
+        }
+        unsafe {
+            wdc_config_section_free_options(section.ptr());
+        }
+    }
+
+    fn option_name(&self, option: ConfigOption) -> String {
+        extern "C" {
+            fn wdc_config_option_name(option: *mut c_void) -> *const c_char;
+        }
+        c_string(unsafe { wdc_config_option_name(option.ptr()) }).unwrap_or_default()
+    }
+
+    fn option_string(&self, option: ConfigOption) -> String {
+        extern "C" {
+            fn wdc_config_string(option: *mut c_void) -> *const c_char;
+        }
+        c_string(unsafe { wdc_config_string(option.ptr()) }).unwrap_or_default()
+    }
+
+    fn option_integer(&self, option: ConfigOption) -> i32 {
+        extern "C" {
+            fn wdc_config_integer(option: *mut c_void) -> c_int;
+        }
+        unsafe { wdc_config_integer(option.ptr()) }
+    }
+
+    fn option_boolean(&self, option: ConfigOption) -> bool {
+        extern "C" {
+            fn wdc_config_boolean(option: *mut c_void) -> c_int;
+        }
+        unsafe { wdc_config_boolean(option.ptr()) != 0 }
+    }
+
+    fn option_color(&self, option: ConfigOption) -> String {
+        extern "C" {
+            fn wdc_config_color(option: *mut c_void) -> *const c_char;
+        }
+        c_string(unsafe { wdc_config_color(option.ptr()) }).unwrap_or_default()
+    }
+
+    fn option_set(&self, option: ConfigOption, value: &str) -> c_int {
+        extern "C" {
+            fn wdc_config_option_set(option: *mut c_void, value: *const c_char) -> c_int;
+        }
+        unsafe {
+            let value_c = unwrap1!(CString::new(value));
+            wdc_config_option_set(option.ptr(), value_c.as_ptr())
+        }
+    }
+
+    fn option_free(&self, option: ConfigOption) {
+        extern "C" {
+            fn wdc_config_option_free(option: *mut c_void);
+        }
+        unsafe {
+            wdc_config_option_free(option.ptr());
+        }
+    }
+
+    fn plugin_options(&self) -> Vec<(String, String)> {
+        extern "C" {
+            fn wdc_infolist_get(name: *const c_char, arguments: *const c_char) -> *mut c_void;
+            fn wdc_infolist_next(infolist: *mut c_void) -> c_int;
+            fn wdc_infolist_string(infolist: *mut c_void, var: *const c_char) -> *const c_char;
+            fn wdc_infolist_free(infolist: *mut c_void);
+        }
+        const PREFIX: &'static str = "plugins.var.weecord.";
+        let mut result = Vec::new();
+        unsafe {
+            let name = unwrap1!(CString::new("option"));
+            let arguments = unwrap1!(CString::new(format!("{}*", PREFIX)));
+            let full_name = unwrap1!(CString::new("full_name"));
+            let value = unwrap1!(CString::new("value"));
+            let infolist = wdc_infolist_get(name.as_ptr(), arguments.as_ptr());
+            if infolist.is_null() {
+                return result;
+            }
+            while wdc_infolist_next(infolist) != 0 {
+                let option_name = c_string(wdc_infolist_string(infolist, full_name.as_ptr()));
+                let option_value = c_string(wdc_infolist_string(infolist, value.as_ptr()));
+                if let (Some(option_name), Some(option_value)) = (option_name, option_value) {
+                    if option_name.starts_with(PREFIX) {
+                        result.push((option_name[PREFIX.len()..].into(), option_value));
+                    }
+                }
+            }
+            wdc_infolist_free(infolist);
+        }
+        result
+    }
+
+    fn unset_plugin_option(&self, name: &str) {
+        extern "C" {
+            fn wdc_config_unset_plugin(name: *const c_char) -> c_int;
+        }
+        unsafe {
+            let name_c = unwrap1!(CString::new(name));
+            wdc_config_unset_plugin(name_c.as_ptr());
+        }
+    }
+
+    fn info_get(&self, info_name: &str, arguments: &str) -> Option<String> {
+        extern "C" {
+            fn wdc_info_get(info_name: *const c_char, arguments: *const c_char) -> *const c_char;
+        }
+        unsafe {
+            let info_name = unwrap1!(CString::new(info_name));
+            let arguments = unwrap1!(CString::new(arguments));
+            c_string(wdc_info_get(info_name.as_ptr(), arguments.as_ptr()))
+        }
+    }
+
+    fn color(&self, name: &str) -> String {
+        extern "C" {
+            fn wdc_color(name: *const c_char) -> *const c_char;
+        }
+        unsafe {
+            let name = unwrap1!(CString::new(name));
+            c_string(wdc_color(name.as_ptr())).unwrap_or_default()
+        }
+    }
+
+    fn prefix(&self, name: &str) -> String {
+        extern "C" {
+            fn wdc_prefix(name: *const c_char) -> *const c_char;
+        }
+        unsafe {
+            let name = unwrap1!(CString::new(name));
+            c_string(wdc_prefix(name.as_ptr())).unwrap_or_default()
+        }
+    }
+
+    fn remove_color(&self, string: &str) -> String {
+        extern "C" {
+            fn wdc_string_remove_color(string: *const c_char) -> *mut c_char;
+        }
+        unsafe {
+            let string_c = unwrap1!(CString::new(string));
+            let result_c = wdc_string_remove_color(string_c.as_ptr());
+            let result = CStr::from_ptr(result_c).to_str().unwrap().into();
+            free(result_c as *mut c_void);
+            result
+        }
+    }
+}
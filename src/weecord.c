@@ -66,6 +66,42 @@ wdc_hook_command(const char* command,
                               NULL);
 }
 
+struct t_hook*
+wdc_hook_command_run(const char* command,
+                     const void* pointer,
+                     int (*callback)(const void* pointer,
+                                     void* data,
+                                     struct t_gui_buffer* buffer,
+                                     const char* command))
+{
+  return weechat_hook_command_run(command, callback, pointer, NULL);
+}
+
+struct t_gui_bar_item*
+wdc_bar_item_new(const char* name,
+                 const void* pointer,
+                 char* (*callback)(const void* pointer,
+                                   void* data,
+                                   struct t_gui_bar_item* item,
+                                   struct t_gui_window* window,
+                                   struct t_gui_buffer* buffer,
+                                   struct t_hashtable* extra_info))
+{
+  return weechat_bar_item_new(name, callback, pointer, NULL);
+}
+
+void
+wdc_bar_item_update(const char* name)
+{
+  weechat_bar_item_update(name);
+}
+
+void
+wdc_bar_item_remove(struct t_gui_bar_item* item)
+{
+  weechat_bar_item_remove(item);
+}
+
 void
 wdc_print(struct t_gui_buffer* buffer, const char* message)
 {
@@ -80,26 +116,13 @@ wdc_print_tags(struct t_gui_buffer* buffer,
   weechat_printf_date_tags(buffer, 0, tags, "%s", message);
 }
 
-const char*
-wdc_config_get_plugin(const char* message)
-{
-  return weechat_config_get_plugin(message);
-}
-
-int
-wdc_config_set_plugin(const char* message, const char* value)
+void
+wdc_print_date_tags(struct t_gui_buffer* buffer,
+                    time_t date,
+                    const char* tags,
+                    const char* message)
 {
-  switch (weechat_config_set_plugin(message, value)) {
-    case WEECHAT_CONFIG_OPTION_SET_OK_CHANGED:
-      return 0;
-    case WEECHAT_CONFIG_OPTION_SET_OK_SAME_VALUE:
-      return 1;
-    case WEECHAT_CONFIG_OPTION_SET_OPTION_NOT_FOUND:
-      return 2;
-    case WEECHAT_CONFIG_OPTION_SET_ERROR:
-    default:
-      return 3;
-  }
+  weechat_printf_date_tags(buffer, date, tags, "%s", message);
 }
 
 struct t_gui_buffer*
@@ -123,6 +146,18 @@ wdc_buffer_new(const char* name,
     name, input_callback, pointer, NULL, close_callback, pointer, NULL);
 }
 
+void
+wdc_buffer_close(struct t_gui_buffer* buffer)
+{
+  weechat_buffer_close(buffer);
+}
+
+void
+wdc_buffer_clear(struct t_gui_buffer* buffer)
+{
+  weechat_buffer_clear(buffer);
+}
+
 void
 wdc_buffer_set(struct t_gui_buffer* buffer,
                const char* property,
@@ -159,6 +194,28 @@ wdc_hook_fd(int fd,
   return weechat_hook_fd(fd, 1, 0, 0, callback, pointer, NULL);
 }
 
+struct t_hook*
+wdc_hook_timer(long interval,
+               const void* pointer,
+               int (*callback)(const void* pointer,
+                               void* data,
+                               int remaining_calls))
+{
+  return weechat_hook_timer(interval, 0, 0, callback, pointer, NULL);
+}
+
+struct t_hook*
+wdc_hook_signal(const char* signal,
+                const void* pointer,
+                int (*callback)(const void* pointer,
+                                void* data,
+                                const char* signal,
+                                const char* type_data,
+                                void* signal_data))
+{
+  return weechat_hook_signal(signal, callback, pointer, NULL);
+}
+
 void
 wdc_unhook(struct t_hook* hook)
 {
@@ -172,19 +229,27 @@ wdc_nicklist_nick_exists(struct t_gui_buffer* buffer, const char* nick)
   return gnick != NULL;
 }
 
+struct t_gui_nick*
+wdc_nicklist_add_nick(struct t_gui_buffer* buffer,
+                      const char* nick,
+                      const char* color)
+{
+  if (!color || !*color)
+    color = weechat_info_get("nick_color", nick);
+  return weechat_nicklist_add_nick(buffer, NULL, nick, color, "", "", 1);
+}
+
+/* Only removes nick if it's still in buffer, so stale pointers are harmless */
 void
-wdc_nicklist_add_nick(struct t_gui_buffer* buffer, const char* nick)
+wdc_nicklist_remove_nick(struct t_gui_buffer* buffer, struct t_gui_nick* nick)
 {
-  const char* color = weechat_info_get("nick_color", nick);
-  (void)weechat_nicklist_add_nick(buffer, NULL, nick, color, "", "", 1);
+  weechat_nicklist_remove_nick(buffer, nick);
 }
 
 void
-wdc_nicklist_remove_nick(struct t_gui_buffer* buffer, const char* nick)
+wdc_nicklist_remove_all(struct t_gui_buffer* buffer)
 {
-  struct t_gui_nick* gnick = weechat_nicklist_search_nick(buffer, NULL, nick);
-  if (gnick)
-    weechat_nicklist_remove_nick(buffer, gnick);
+  weechat_nicklist_remove_all(buffer);
 }
 
 const char*
@@ -193,6 +258,18 @@ wdc_info_get(const char* info_name, const char* arguments)
   return weechat_info_get(info_name, arguments);
 }
 
+const char*
+wdc_color(const char* name)
+{
+  return weechat_color(name);
+}
+
+const char*
+wdc_prefix(const char* name)
+{
+  return weechat_prefix(name);
+}
+
 void*
 wdc_hdata_get(const char* name)
 {
@@ -255,3 +332,210 @@ wdc_string_remove_color(const char* str)
 {
   return weechat_string_remove_color(str, NULL);
 }
+
+struct t_config_file*
+wdc_config_new(const char* name,
+               const void* pointer,
+               int (*callback_reload)(const void* pointer,
+                                      void* data,
+                                      struct t_config_file* config_file))
+{
+  return weechat_config_new(name, callback_reload, pointer, NULL);
+}
+
+struct t_config_section*
+wdc_config_new_section(
+  struct t_config_file* config_file,
+  const char* name,
+  const void* pointer,
+  int (*callback_create_option)(const void* pointer,
+                                void* data,
+                                struct t_config_file* config_file,
+                                struct t_config_section* section,
+                                const char* option_name,
+                                const char* value),
+  int (*callback_delete_option)(const void* pointer,
+                                void* data,
+                                struct t_config_file* config_file,
+                                struct t_config_section* section,
+                                struct t_config_option* option))
+{
+  int dynamic = callback_create_option != NULL;
+  return weechat_config_new_section(config_file,
+                                    name,
+                                    dynamic,
+                                    dynamic,
+                                    NULL,
+                                    NULL,
+                                    NULL,
+                                    NULL,
+                                    NULL,
+                                    NULL,
+                                    NULL,
+                                    NULL,
+                                    NULL,
+                                    callback_create_option,
+                                    pointer,
+                                    NULL,
+                                    callback_delete_option,
+                                    pointer,
+                                    NULL);
+}
+
+struct t_config_option*
+wdc_config_new_option(struct t_config_file* config_file,
+                      struct t_config_section* section,
+                      const char* name,
+                      const char* type,
+                      const char* description,
+                      const char* string_values,
+                      int min,
+                      int max,
+                      const char* default_value,
+                      const char* value,
+                      const void* pointer,
+                      void (*callback_change)(const void* pointer,
+                                              void* data,
+                                              struct t_config_option* option))
+{
+  return weechat_config_new_option(config_file,
+                                   section,
+                                   name,
+                                   type,
+                                   description,
+                                   string_values,
+                                   min,
+                                   max,
+                                   default_value,
+                                   value,
+                                   0,
+                                   NULL,
+                                   NULL,
+                                   NULL,
+                                   callback_change,
+                                   pointer,
+                                   NULL,
+                                   NULL,
+                                   NULL,
+                                   NULL);
+}
+
+int
+wdc_config_read(struct t_config_file* config_file)
+{
+  return weechat_config_read(config_file);
+}
+
+int
+wdc_config_reload(struct t_config_file* config_file)
+{
+  return weechat_config_reload(config_file);
+}
+
+int
+wdc_config_write(struct t_config_file* config_file)
+{
+  return weechat_config_write(config_file);
+}
+
+void
+wdc_config_free(struct t_config_file* config_file)
+{
+  weechat_config_free(config_file);
+}
+
+void
+wdc_config_section_free_options(struct t_config_section* section)
+{
+  weechat_config_section_free_options(section);
+}
+
+struct t_config_option*
+wdc_config_search_option(struct t_config_file* config_file,
+                         struct t_config_section* section,
+                         const char* name)
+{
+  return weechat_config_search_option(config_file, section, name);
+}
+
+const char*
+wdc_config_option_name(struct t_config_option* option)
+{
+  return weechat_config_option_get_string(option, "name");
+}
+
+const char*
+wdc_config_string(struct t_config_option* option)
+{
+  return weechat_config_string(option);
+}
+
+int
+wdc_config_integer(struct t_config_option* option)
+{
+  return weechat_config_integer(option);
+}
+
+int
+wdc_config_boolean(struct t_config_option* option)
+{
+  return weechat_config_boolean(option);
+}
+
+const char*
+wdc_config_color(struct t_config_option* option)
+{
+  return weechat_color(weechat_config_color(option));
+}
+
+int
+wdc_config_option_set(struct t_config_option* option, const char* value)
+{
+  switch (weechat_config_option_set(option, value, 1)) {
+    case WEECHAT_CONFIG_OPTION_SET_OK_CHANGED:
+      return 0;
+    case WEECHAT_CONFIG_OPTION_SET_OK_SAME_VALUE:
+      return 1;
+    case WEECHAT_CONFIG_OPTION_SET_OPTION_NOT_FOUND:
+      return 2;
+    case WEECHAT_CONFIG_OPTION_SET_ERROR:
+    default:
+      return 3;
+  }
+}
+
+void
+wdc_config_option_free(struct t_config_option* option)
+{
+  weechat_config_option_free(option);
+}
+
+int
+wdc_config_unset_plugin(const char* name)
+{
+  return weechat_config_unset_plugin(name);
+}
+
+struct t_infolist*
+wdc_infolist_get(const char* name, const char* arguments)
+{
+  return weechat_infolist_get(name, NULL, arguments);
+}
+
+int
+wdc_infolist_next(struct t_infolist* infolist)
+{
+  return weechat_infolist_next(infolist);
+}
+
+const char*
+wdc_infolist_string(struct t_infolist* infolist, const char* var)
+{
+  return weechat_infolist_string(infolist, var);
+}
+
+void
+wdc_infolist_free(struct t_infolist* infolist)
+{
+  weechat_infolist_free(infolist);
+}
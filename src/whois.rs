@@ -0,0 +1,84 @@
+use discord::State;
+use discord::model::{Presence, UserId};
+
+use ffi::Buffer;
+use message;
+use presence::status_name;
+use types::*;
+use util::{tag, ServerExt};
+
+fn format_presence(presence: &Presence) -> String {
+    match presence.game {
+        Some(ref game) => format!("{}, playing {}", status_name(presence.status), game.name),
+        None => status_name(presence.status).into(),
+    }
+}
+
+pub fn whois_command(state: &State, buffer: &Buffer, name: &str) {
+    let name = name.trim().trim_left_matches('@');
+    if name.is_empty() {
+        buffer.print("Usage: /discord whois <name>");
+        return;
+    }
+    let user = message::all_names_everywhere(state, |found, user| if found == name {
+        Some(user.clone())
+    } else {
+        None
+    });
+    let user = match user {
+        Some(user) => user,
+        None => {
+            buffer.print(&format!("User not found: {}", name));
+            return;
+        }
+    };
+    let user_id: UserId = user.id;
+    buffer.print(&format!("{} ({}){}",
+                          tag(user),
+                          user.id.0,
+                          if user.bot { " [bot]" } else { "" }));
+
+    let mut presence = state
+        .presences()
+        .iter()
+        .find(|p| p.user_id == user_id)
+        .map(format_presence);
+    let mut mutual = Vec::new();
+    for server in state.servers() {
+        let member = match server.find_member(user_id) {
+            Some(member) => member,
+            None => continue,
+        };
+        let server_name = server.name(&NameFormat::none());
+        let roles = member
+            .roles
+            .iter()
+            .filter_map(|id| server.roles.iter().find(|r| r.id == *id))
+            .map(|r| r.name(&NameFormat::none()))
+            .collect::<Vec<_>>();
+        buffer.print(&format!("  {}: {}joined {}{}",
+                              server_name,
+                              member
+                                  .nick
+                                  .as_ref()
+                                  .map(|x| format!("nick {}, ", x))
+                                  .unwrap_or_default(),
+                              // "2017-05-01T12:34:56.789000+00:00", the date is enough
+                              member.joined_at.split('T').next().unwrap_or(""),
+                              if roles.is_empty() {
+                                  String::new()
+                              } else {
+                                  format!(", roles {}", roles.join(", "))
+                              }));
+        if presence.is_none() {
+            presence = server.find_presence(user_id).map(format_presence);
+        }
+        mutual.push(server_name);
+    }
+    buffer.print(&format!("  status: {}", presence.unwrap_or_else(|| "offline".into())));
+    if mutual.is_empty() {
+        buffer.print("  no mutual servers");
+    } else {
+        buffer.print(&format!("  mutual servers: {}", mutual.join(", ")));
+    }
+}